@@ -0,0 +1,41 @@
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Embeds the git SHA and the build time for /admin/info. Builds outside of a git checkout, like
+// Docker builds, can pass them by SDS_GIT_SHA and SOURCE_DATE_EPOCH.
+fn main() {
+    let sha = env::var("SDS_GIT_SHA").ok().or_else(git_sha);
+    println!(
+        "cargo:rustc-env=SDS_GIT_SHA={}",
+        sha.unwrap_or_else(|| "unknown".to_owned())
+    );
+
+    let built_at = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=SDS_BUILD_TIMESTAMP={}", built_at);
+
+    println!("cargo:rerun-if-env-changed=SDS_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
+
+fn git_sha() -> Option<String> {
+    let output = Command::new("git")
+        .args(&["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let sha = String::from_utf8(output.stdout).ok()?;
+    Some(sha.trim().to_owned())
+}
@@ -0,0 +1,9 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use sds::request::parse_discovery_request;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_discovery_request(data);
+});
@@ -0,0 +1,21 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use std::str;
+use std::time::Instant;
+
+use sds::request::{
+    match_feedback_path, match_host_path, match_registration_path, parse_deadline, parse_port,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_deadline(data, Instant::now());
+    if let Ok(path) = str::from_utf8(data) {
+        let _ = match_registration_path(path);
+        let _ = match_feedback_path(path);
+        if let Some((_, _, port)) = match_host_path(path) {
+            let _ = parse_port(port);
+        }
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use sds::request::{parse_feedback_param, parse_registration_param};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_registration_param(data, false);
+    let _ = parse_registration_param(data, true);
+    let _ = parse_feedback_param(data, false);
+    let _ = parse_feedback_param(data, true);
+});
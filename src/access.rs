@@ -0,0 +1,140 @@
+use std::net::IpAddr;
+
+use hyper::Method;
+use serde::de::{self, Deserialize, Deserializer};
+use serde_derive::Deserialize;
+
+use super::request::{
+    match_alias_path, match_drain_path, match_feedback_path, match_host_path,
+    match_registration_path, match_tags_path,
+};
+
+// An IPv4 or IPv6 network like "10.0.0.0/8". A plain address is a network of the address only.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (addr, prefix_len) = match s.find('/') {
+            Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("Invalid CIDR {}: bad address", s))?;
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len {
+            Some(v) => match v.parse::<u8>() {
+                Ok(n) if n <= max => n,
+                _ => return Err(format!("Invalid CIDR {}: bad prefix length", s)),
+            },
+            None => max,
+        };
+        Ok(Cidr { addr, prefix_len })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, normalize(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = if self.prefix_len == 0 {
+                    0
+                } else {
+                    !0u32 << (32 - u32::from(self.prefix_len))
+                };
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = if self.prefix_len == 0 {
+                    0
+                } else {
+                    !0u128 << (128 - u32::from(self.prefix_len))
+                };
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Cidr::parse(&s).map_err(de::Error::custom)
+    }
+}
+
+// Clients connecting over IPv6 sockets show up as IPv4-mapped addresses like ::ffff:10.0.0.1.
+fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => {
+                let o = v6.octets();
+                IpAddr::from([o[12], o[13], o[14], o[15]])
+            }
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+// Denies addresses in `deny`. When `allow` isn't empty, also denies addresses outside of it.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct IpFilter {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub fn is_open(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip))
+    }
+}
+
+// Restricts which clients can change the registry and use the admin API by their address. Reads
+// are open to anyone.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct AccessConfig {
+    // Applies to POST, PUT, PATCH and DELETE of registrations, aliases and feedback.
+    pub mutations: IpFilter,
+    // Applies to every request under /admin.
+    pub admin: IpFilter,
+}
+
+impl AccessConfig {
+    // Returns the filter the request is subject to, None for requests open to anyone.
+    pub fn filter_of(&self, method: &Method, path: &str) -> Option<&IpFilter> {
+        if path == "/admin" || path.starts_with("/admin/") {
+            return Some(&self.admin);
+        }
+        match *method {
+            Method::POST | Method::PUT | Method::PATCH | Method::DELETE => {}
+            _ => return None,
+        }
+        let is_mutation = match_registration_path(path).is_some()
+            || match_host_path(path).is_some()
+            || match_drain_path(path).is_some()
+            || match_tags_path(path).is_some()
+            || match_feedback_path(path).is_some()
+            || match_alias_path(path).is_some();
+        if is_mutation {
+            Some(&self.mutations)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{future, Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use tokio::timer::Timeout;
+
+use super::types::{Host, Tag};
+
+// Decisions cached over this many entries are swept of expired ones on insert.
+const MAX_CACHE_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct AdmissionConfig {
+    // URL registrations are posted to before being stored. Only plain HTTP is supported.
+    pub url: String,
+    pub timeout: Duration,
+    // How long a decision is reused for the same host, tags and revision.
+    pub cache_ttl: Duration,
+    // Accepts registrations as they are when the webhook fails, otherwise rejects them.
+    pub fail_open: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    // The host to store, possibly mutated by the webhook.
+    Allow(Host),
+    Deny(String),
+    // The webhook failed and admission fails closed.
+    Unavailable(String),
+}
+
+#[derive(Serialize, Debug)]
+struct AdmissionRequest<'a> {
+    service: &'a str,
+    host: &'a Host,
+}
+
+// Response of the webhook. `tags` and `revision` replace the ones of the host when given.
+#[derive(Deserialize, Debug, Clone)]
+struct AdmissionResponse {
+    allowed: bool,
+    #[serde(default)]
+    reason: Option<String>,
+    #[serde(default)]
+    tags: Option<Tag>,
+    #[serde(default)]
+    revision: Option<String>,
+}
+
+// Asks an external webhook whether to accept registrations.
+#[derive(Clone)]
+pub struct Admission {
+    url: Uri,
+    config: AdmissionConfig,
+    client: Client<HttpConnector>,
+    cache: Arc<Mutex<HashMap<String, (Instant, AdmissionResponse)>>>,
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Result<Self, String> {
+        let url: Uri = config
+            .url
+            .parse()
+            .map_err(|e| format!("Invalid admission webhook URL {}: {}", config.url, e))?;
+        if url.scheme_part().map(|s| s.as_str()) != Some("http") {
+            return Err(format!(
+                "Admission webhook URL must be http: {}",
+                config.url
+            ));
+        }
+        Ok(Admission {
+            url,
+            config,
+            client: Client::new(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn review(
+        &self,
+        service: &str,
+        host: Host,
+    ) -> Box<Future<Item = Decision, Error = ()> + Send> {
+        let key = match cache_key(service, &host) {
+            Ok(v) => v,
+            Err(e) => return Box::new(future::ok(self.on_error(host, e))),
+        };
+        if let Some(res) = self.cached(&key) {
+            return Box::new(future::ok(apply(host, res)));
+        }
+        let body = match serde_json::to_vec(&AdmissionRequest {
+            service,
+            host: &host,
+        }) {
+            Ok(v) => v,
+            Err(e) => return Box::new(future::ok(self.on_error(host, e.to_string()))),
+        };
+        let req = match Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+        {
+            Ok(v) => v,
+            Err(e) => return Box::new(future::ok(self.on_error(host, e.to_string()))),
+        };
+        let call = self.client.request(req).and_then(|res| {
+            let status = res.status();
+            res.into_body().concat2().map(move |body| (status, body))
+        });
+        let admission = self.clone();
+        let f = Timeout::new(call, self.config.timeout).then(move |r| {
+            let parsed = match r {
+                Ok((status, _)) if !status.is_success() => {
+                    Err(format!("Admission webhook responded {}", status))
+                }
+                Ok((_, body)) => serde_json::from_slice::<AdmissionResponse>(&body)
+                    .map_err(|e| format!("Invalid admission response: {}", e)),
+                Err(e) => Err(format!("Admission webhook failed: {}", e)),
+            };
+            Ok(match parsed {
+                Ok(res) => {
+                    admission.store(key, res.clone());
+                    apply(host, res)
+                }
+                Err(e) => admission.on_error(host, e),
+            })
+        });
+        Box::new(f)
+    }
+
+    fn on_error(&self, host: Host, e: String) -> Decision {
+        warn!("{}, fail_open={}", e, self.config.fail_open);
+        if self.config.fail_open {
+            Decision::Allow(host)
+        } else {
+            Decision::Unavailable(e)
+        }
+    }
+
+    fn cached(&self, key: &str) -> Option<AdmissionResponse> {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        match cache.get(key) {
+            Some((at, res)) if at.elapsed() < self.config.cache_ttl => Some(res.clone()),
+            _ => None,
+        }
+    }
+
+    fn store(&self, key: String, res: AdmissionResponse) {
+        if self.config.cache_ttl == Duration::from_secs(0) {
+            return;
+        }
+        let ttl = self.config.cache_ttl;
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            cache.retain(|_, (at, _)| at.elapsed() < ttl);
+        }
+        cache.insert(key, (Instant::now(), res));
+    }
+}
+
+// Check-ins of the same host with the same tags and revision share a decision.
+fn cache_key(service: &str, host: &Host) -> Result<String, String> {
+    serde_json::to_string(&(
+        service,
+        &host.ip_address,
+        host.port,
+        &host.revision,
+        &host.tags,
+    ))
+    .map_err(|e| e.to_string())
+}
+
+fn apply(mut host: Host, res: AdmissionResponse) -> Decision {
+    if !res.allowed {
+        return Decision::Deny(
+            res.reason
+                .unwrap_or_else(|| "Denied by the admission webhook".to_owned()),
+        );
+    }
+    if let Some(tags) = res.tags {
+        host.tags = tags;
+    }
+    if let Some(revision) = res.revision {
+        host.revision = revision;
+    }
+    Decision::Allow(host)
+}
@@ -0,0 +1,231 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use log::{info, warn};
+use serde_derive::Deserialize;
+use serde_json;
+use tokio::runtime::current_thread::Runtime;
+use tokio::timer::Timeout;
+
+use super::request::RegistrationParam;
+use super::types::Tag;
+
+// How often the shutdown flag is checked while waiting for the next heartbeat.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Declarative file of the local services sds-agent keeps registered.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AgentConfig {
+    // Base URL of the sds API, e.g. "http://sds.internal:8080". Only plain HTTP is supported.
+    pub sds_url: String,
+    // Seconds between heartbeats of each service, which must be shorter than HOST_TTL of sds.
+    pub interval_sec: u64,
+    // Upper bound of the retry delay after failed heartbeats.
+    pub max_backoff_sec: u64,
+    pub timeout_ms: u64,
+    pub services: Vec<AgentService>,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        AgentConfig {
+            sds_url: "http://127.0.0.1:8080".to_owned(),
+            interval_sec: 30,
+            max_backoff_sec: 30,
+            timeout_ms: 2000,
+            services: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AgentService {
+    pub name: String,
+    pub ip: String,
+    pub port: u16,
+    pub revision: String,
+    pub tags: Tag,
+}
+
+pub fn load_agent_config(path: &str) -> Result<AgentConfig, String> {
+    let s = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read agent config {}: {}", path, e))?;
+    let config: AgentConfig = serde_json::from_str(&s)
+        .map_err(|e| format!("Failed to parse agent config {}: {}", path, e))?;
+    if config.interval_sec == 0 {
+        return Err("interval_sec must be positive".to_owned());
+    }
+    Ok(config)
+}
+
+// Returns the delay before retrying after the given number of consecutive failures: 1 second
+// doubled per failure, capped at `max`.
+pub fn backoff(failures: u32, max: Duration) -> Duration {
+    let secs = 1u64
+        .checked_shl(failures.saturating_sub(1))
+        .unwrap_or(u64::max_value());
+    Duration::from_secs(secs).min(max)
+}
+
+struct ServiceState {
+    service: AgentService,
+    next_at: Instant,
+    failures: u32,
+}
+
+// Keeps the services registered by heartbeats, blocking the calling thread.
+pub struct Agent {
+    config: AgentConfig,
+    base_url: String,
+    states: Vec<ServiceState>,
+    client: Client<HttpConnector>,
+    runtime: Runtime,
+}
+
+impl Agent {
+    pub fn new(config: AgentConfig) -> Result<Self, String> {
+        let base_url = config.sds_url.trim_end_matches('/').to_owned();
+        let uri: Uri = base_url
+            .parse()
+            .map_err(|e| format!("Invalid sds_url {}: {}", config.sds_url, e))?;
+        if uri.scheme_part().map(|s| s.as_str()) != Some("http") {
+            return Err(format!("sds_url must be http: {}", config.sds_url));
+        }
+        let runtime = Runtime::new().map_err(|e| format!("Failed to start runtime: {}", e))?;
+        let now = Instant::now();
+        let states = config
+            .services
+            .iter()
+            .map(|s| ServiceState {
+                service: s.clone(),
+                next_at: now,
+                failures: 0,
+            })
+            .collect();
+        Ok(Agent {
+            config,
+            base_url,
+            states,
+            client: Client::new(),
+            runtime,
+        })
+    }
+
+    // Sends heartbeats of the services due at `now`, and returns how long to wait for the next
+    // one. Failed services are retried with backoff instead of the interval.
+    pub fn tick(&mut self, now: Instant) -> Duration {
+        let interval = Duration::from_secs(self.config.interval_sec);
+        let max_backoff = Duration::from_secs(self.config.max_backoff_sec);
+        for i in 0..self.states.len() {
+            if self.states[i].next_at > now {
+                continue;
+            }
+            let service = self.states[i].service.clone();
+            let result = self.register(&service);
+            let state = &mut self.states[i];
+            match result {
+                Ok(()) => {
+                    if state.failures > 0 {
+                        info!("Recovered heartbeat: service={}", service.name);
+                    }
+                    state.next_at = now + interval;
+                    state.failures = 0;
+                }
+                Err(e) => {
+                    state.failures += 1;
+                    let delay = backoff(state.failures, max_backoff.min(interval));
+                    warn!(
+                        "Failed heartbeat: service={}, failures={}, retry-in={:?}, error={}",
+                        service.name, state.failures, delay, e
+                    );
+                    state.next_at = now + delay;
+                }
+            }
+        }
+        self.states
+            .iter()
+            .map(|s| {
+                if s.next_at > now {
+                    s.next_at - now
+                } else {
+                    Duration::from_secs(0)
+                }
+            })
+            .min()
+            .unwrap_or(interval)
+    }
+
+    // Deregisters every service, reporting the ones that failed.
+    pub fn deregister_all(&mut self) -> Result<(), String> {
+        let services: Vec<AgentService> = self.states.iter().map(|s| s.service.clone()).collect();
+        let mut errors = Vec::new();
+        for service in services {
+            match self.deregister(&service) {
+                Ok(()) => info!("Deregistered: service={}", service.name),
+                Err(e) => errors.push(format!("{}: {}", service.name, e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    // Sends heartbeats until `shutdown` is set, then deregisters the services.
+    pub fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<(), String> {
+        while !shutdown.load(Ordering::SeqCst) {
+            let wait_until = Instant::now() + self.tick(Instant::now());
+            while !shutdown.load(Ordering::SeqCst) && Instant::now() < wait_until {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        self.deregister_all()
+    }
+
+    pub fn register(&mut self, service: &AgentService) -> Result<(), String> {
+        let param = RegistrationParam {
+            ip: service.ip.to_owned(),
+            port: service.port,
+            revision: service.revision.to_owned(),
+            tags: service.tags.clone(),
+        };
+        let body = serde_json::to_vec(&param).map_err(|e| e.to_string())?;
+        let path = format!("/v1/registration/{}", service.name);
+        match self.send(Method::POST, &path, body)? {
+            status if status.is_success() => Ok(()),
+            status => Err(format!("sds responded {}", status)),
+        }
+    }
+
+    pub fn deregister(&mut self, service: &AgentService) -> Result<(), String> {
+        let path = format!(
+            "/v1/registration/{}/{}:{}",
+            service.name, service.ip, service.port
+        );
+        match self.send(Method::DELETE, &path, Vec::new())? {
+            // 404 means the host already expired or was deregistered.
+            status if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+            status => Err(format!("sds responded {}", status)),
+        }
+    }
+
+    fn send(&mut self, method: Method, path: &str, body: Vec<u8>) -> Result<StatusCode, String> {
+        let req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.base_url, path))
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .map_err(|e| e.to_string())?;
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let f = Timeout::new(self.client.request(req), timeout).map_err(|e| e.to_string());
+        self.runtime.block_on(f).map(|res| res.status())
+    }
+}
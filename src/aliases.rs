@@ -0,0 +1,87 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Longest chain of aliases followed on lookups.
+pub const MAX_ALIAS_DEPTH: usize = 8;
+// How long a snapshot of the aliases is used. Aliases written through other sds processes show up
+// after it at most.
+const SNAPSHOT_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AliasError {
+    // The chain of aliases starting from the name comes back to itself.
+    Loop(Vec<String>),
+    // The chain is longer than MAX_ALIAS_DEPTH.
+    TooDeep(Vec<String>),
+}
+
+impl std::fmt::Display for AliasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AliasError::Loop(chain) => write!(f, "Alias loop: {}", chain.join(" -> ")),
+            AliasError::TooDeep(chain) => write!(
+                f,
+                "Alias chain is longer than {}: {}",
+                MAX_ALIAS_DEPTH,
+                chain.join(" -> ")
+            ),
+        }
+    }
+}
+
+// Follows aliases from the name to a service that isn't an alias.
+pub fn resolve(aliases: &BTreeMap<String, String>, name: &str) -> Result<String, AliasError> {
+    let mut chain = vec![name.to_owned()];
+    let mut seen = BTreeSet::new();
+    seen.insert(name.to_owned());
+    let mut current = name;
+    while let Some(target) = aliases.get(current) {
+        chain.push(target.to_owned());
+        if !seen.insert(target.to_owned()) {
+            return Err(AliasError::Loop(chain));
+        }
+        if chain.len() > MAX_ALIAS_DEPTH + 1 {
+            return Err(AliasError::TooDeep(chain));
+        }
+        current = target;
+    }
+    Ok(current.to_owned())
+}
+
+// Aliases loaded from the storage, shared by requests for SNAPSHOT_TTL so that lookups don't cost a
+// storage call each.
+#[derive(Debug, Clone, Default)]
+pub struct AliasCache {
+    snapshot: Arc<Mutex<Option<(Instant, Arc<BTreeMap<String, String>>)>>>,
+}
+
+impl AliasCache {
+    pub fn new() -> Self {
+        AliasCache::default()
+    }
+
+    // Returns the snapshot, reloading it by `load` once it's expired.
+    pub fn get<E, F>(&self, load: F) -> Result<Arc<BTreeMap<String, String>>, E>
+    where
+        F: FnOnce() -> Result<BTreeMap<String, String>, E>,
+    {
+        {
+            let snapshot = self.snapshot.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((loaded_at, aliases)) = snapshot.as_ref() {
+                if loaded_at.elapsed() < SNAPSHOT_TTL {
+                    return Ok(aliases.clone());
+                }
+            }
+        }
+        let aliases = Arc::new(load()?);
+        *self.snapshot.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((Instant::now(), aliases.clone()));
+        Ok(aliases)
+    }
+
+    // Drops the snapshot so that writes through this process show up immediately.
+    pub fn invalidate(&self) {
+        *self.snapshot.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
@@ -0,0 +1,40 @@
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use super::metrics::Metrics;
+use super::types::Storage;
+
+// Resyncs the storage every interval and records the drift found into the metrics.
+pub fn start_anti_entropy<S: Storage>(storage: S, metrics: Metrics, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match storage.resync() {
+            Ok(drifts) => {
+                let mut failures = Vec::new();
+                for d in drifts {
+                    if let Some(e) = d.error {
+                        failures.push(format!("{}: {}", d.service, e));
+                        continue;
+                    }
+                    warn!(
+                        "Repaired drift: service={}, missing={}, extra={}, stale={}",
+                        d.service, d.missing, d.extra, d.stale
+                    );
+                    metrics.record_drift(&d);
+                }
+                if failures.is_empty() {
+                    info!("Resync completed");
+                } else {
+                    warn!(
+                        "Failed to resync {} services: {}",
+                        failures.len(),
+                        failures.join(", ")
+                    );
+                }
+            }
+            Err(e) => warn!("Failed to resync storage: {}", e),
+        }
+    });
+}
@@ -0,0 +1,64 @@
+use std::env;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use futures::{Future, Stream};
+use log::{error, info};
+
+use sds::agent::{load_agent_config, Agent};
+
+fn main() {
+    env_logger::init();
+
+    let path = match env::args().nth(1).or_else(|| env::var("AGENT_CONFIG").ok()) {
+        Some(v) => v,
+        None => {
+            eprintln!("Usage: sds-agent <config file> (or AGENT_CONFIG)");
+            exit(2);
+        }
+    };
+    let config = match load_agent_config(&path) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    };
+    let mut agent = match Agent::new(config) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    };
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let flag = shutdown.clone();
+    thread::spawn(move || {
+        wait_for_signal();
+        info!("Received signal, deregistering services");
+        flag.store(true, Ordering::SeqCst);
+    });
+
+    if let Err(e) = agent.run(shutdown) {
+        error!("Failed to deregister: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(unix)]
+fn wait_for_signal() {
+    use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+    let signals = Signal::new(SIGTERM)
+        .flatten_stream()
+        .select(Signal::new(SIGINT).flatten_stream());
+    let _ = signals.into_future().wait();
+}
+
+#[cfg(not(unix))]
+fn wait_for_signal() {
+    let _ = tokio_signal::ctrl_c().flatten_stream().into_future().wait();
+}
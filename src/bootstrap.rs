@@ -0,0 +1,139 @@
+use std::fmt::Write;
+
+use serde_json;
+
+pub const USAGE: &str = "Usage: sds gen-envoy-bootstrap --cluster NAME [--cluster NAME ...] \
+                         --sds-address HOST:PORT [--node-id ID] [--node-cluster NAME] \
+                         [--admin-port PORT] [--refresh-delay-ms MS]";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapOptions {
+    // Clusters whose endpoints are discovered from sds by EDS.
+    pub clusters: Vec<String>,
+    pub sds_host: String,
+    pub sds_port: u16,
+    pub node_id: String,
+    pub node_cluster: String,
+    pub admin_port: u16,
+    pub refresh_delay_ms: u64,
+}
+
+// Parses the arguments following "gen-envoy-bootstrap".
+pub fn parse_args(args: &[String]) -> Result<BootstrapOptions, String> {
+    let mut clusters = Vec::new();
+    let mut sds_address = None;
+    let mut node_id = "envoy".to_owned();
+    let mut node_cluster = "envoy".to_owned();
+    let mut admin_port = 9901;
+    let mut refresh_delay_ms = 1000;
+
+    let mut it = args.iter();
+    while let Some(flag) = it.next() {
+        let mut value = || {
+            it.next()
+                .cloned()
+                .ok_or_else(|| format!("{} requires a value", flag))
+        };
+        match flag.as_str() {
+            "--cluster" => clusters.push(value()?),
+            "--sds-address" => sds_address = Some(value()?),
+            "--node-id" => node_id = value()?,
+            "--node-cluster" => node_cluster = value()?,
+            "--admin-port" => admin_port = parse_number(flag, &value()?)?,
+            "--refresh-delay-ms" => refresh_delay_ms = parse_number(flag, &value()?)?,
+            _ => return Err(format!("Unknown argument: {}", flag)),
+        }
+    }
+
+    if clusters.is_empty() || clusters.iter().any(|c| c.is_empty()) {
+        return Err("--cluster is required and must not be empty".to_owned());
+    }
+    let sds_address = sds_address.ok_or_else(|| "--sds-address is required".to_owned())?;
+    let (sds_host, sds_port) = split_address(&sds_address)?;
+    Ok(BootstrapOptions {
+        clusters,
+        sds_host,
+        sds_port,
+        node_id,
+        node_cluster,
+        admin_port,
+        refresh_delay_ms,
+    })
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, v: &str) -> Result<T, String> {
+    v.parse()
+        .map_err(|_| format!("{} must be a number: {}", flag, v))
+}
+
+fn split_address(address: &str) -> Result<(String, u16), String> {
+    let mut parts = address.rsplitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(port), Some(host)) if !host.is_empty() => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("Invalid port of --sds-address: {}", address))?;
+            Ok((host.to_owned(), port))
+        }
+        _ => Err(format!(
+            "--sds-address must be formatted like host:port: {}",
+            address
+        )),
+    }
+}
+
+// Renders a minimal Envoy v2 bootstrap in YAML. The clusters get their endpoints from the v2 EDS
+// REST endpoint of sds, reached through the "sds" cluster.
+pub fn render(opts: &BootstrapOptions) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "node:");
+    let _ = writeln!(out, "  id: {}", quote(&opts.node_id));
+    let _ = writeln!(out, "  cluster: {}", quote(&opts.node_cluster));
+    let _ = writeln!(out, "admin:");
+    let _ = writeln!(out, "  access_log_path: /dev/null");
+    let _ = writeln!(out, "  address:");
+    let _ = writeln!(out, "    socket_address:");
+    let _ = writeln!(out, "      address: 127.0.0.1");
+    let _ = writeln!(out, "      port_value: {}", opts.admin_port);
+    let _ = writeln!(out, "static_resources:");
+    let _ = writeln!(out, "  clusters:");
+    for c in &opts.clusters {
+        let _ = writeln!(out, "  - name: {}", quote(c));
+        let _ = writeln!(out, "    connect_timeout: 0.25s");
+        let _ = writeln!(out, "    type: EDS");
+        let _ = writeln!(out, "    lb_policy: ROUND_ROBIN");
+        let _ = writeln!(out, "    eds_cluster_config:");
+        let _ = writeln!(out, "      service_name: {}", quote(c));
+        let _ = writeln!(out, "      eds_config:");
+        let _ = writeln!(out, "        api_config_source:");
+        let _ = writeln!(out, "          api_type: REST");
+        let _ = writeln!(out, "          cluster_names: [sds]");
+        let _ = writeln!(
+            out,
+            "          refresh_delay: {}",
+            format_duration(opts.refresh_delay_ms)
+        );
+    }
+    let _ = writeln!(out, "  - name: sds");
+    let _ = writeln!(out, "    connect_timeout: 0.25s");
+    let _ = writeln!(out, "    type: STRICT_DNS");
+    let _ = writeln!(out, "    lb_policy: ROUND_ROBIN");
+    let _ = writeln!(out, "    hosts:");
+    let _ = writeln!(out, "    - socket_address:");
+    let _ = writeln!(out, "        address: {}", quote(&opts.sds_host));
+    let _ = writeln!(out, "        port_value: {}", opts.sds_port);
+    out
+}
+
+// JSON strings are valid YAML scalars, which keeps names like "on" or "1" strings.
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_else(|_| "\"\"".to_owned())
+}
+
+fn format_duration(ms: u64) -> String {
+    if ms % 1000 == 0 {
+        format!("{}s", ms / 1000)
+    } else {
+        format!("{}.{:03}s", ms / 1000, ms % 1000)
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use log::info;
+
+use super::clock::SharedClock;
+use super::storage::StorageError;
+use super::types::{DataSource, Drift, Freshness, Host, RegistryIndex, SchemaCheck, Storage};
+
+#[derive(Debug, Default)]
+struct Entry {
+    // "ip:port" -> host
+    hosts: BTreeMap<String, Host>,
+    // Bumped by every write so that a resync doesn't overwrite writes made while it reads the
+    // storage.
+    generation: u64,
+    // "ip:port" -> generation of the last write of the host, including deletes. Only writes newer
+    // than a running resync matter, older ones are pruned by the resync.
+    written: HashMap<String, u64>,
+    // Epoch seconds when the hosts were last read from the inner storage.
+    synced_at: u64,
+    // Whether the last resync of the service failed, so the hosts are served stale.
+    resync_failed: bool,
+}
+
+// Serves queries from memory and writes through to the inner storage. Services are cached on
+// their first query finding hosts, and evicted by a resync finding none. Writes by other sds
+// processes only show up on the next resync, so the cache should be used together with the
+// anti-entropy job.
+#[derive(Clone)]
+pub struct CachedStorage<S> {
+    inner: S,
+    clock: SharedClock,
+    // service -> entry
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl<S: Storage> CachedStorage<S> {
+    pub fn new(inner: S, clock: SharedClock) -> Self {
+        CachedStorage {
+            inner,
+            clock,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<String, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn live_hosts(&self, entry: &Entry) -> Result<Vec<Host>, StorageError> {
+        let now = self.clock.epoch_secs().map_err(StorageError::new)?;
+        Ok(entry
+            .hosts
+            .values()
+            .filter(|h| h.expire_time >= now)
+            .cloned()
+            .collect())
+    }
+
+    // Re-reads the service from the inner storage and replaces the cached hosts with it, except
+    // hosts written while reading which are kept as cached. A service left without hosts is
+    // evicted, its next query reads the inner storage.
+    fn resync_service(&self, name: &str) -> Result<Drift, StorageError> {
+        let generation = match self.lock().get(name) {
+            Some(e) => e.generation,
+            None => return Ok(Drift::default()),
+        };
+        let hosts = match self.inner.query_items(name) {
+            Ok(v) => v,
+            Err(e) => {
+                if let Some(entry) = self.lock().get_mut(name) {
+                    entry.resync_failed = true;
+                }
+                return Err(inner_error(e));
+            }
+        };
+        let mut fresh: BTreeMap<String, Host> = hosts.into_iter().map(|h| (key(&h), h)).collect();
+
+        let now = self.clock.epoch_secs().map_err(StorageError::new)?;
+        let mut entries = self.lock();
+        let entry = match entries.get_mut(name) {
+            Some(v) => v,
+            None => return Ok(Drift::default()),
+        };
+        let written: Vec<String> = entry
+            .written
+            .iter()
+            .filter(|(_, g)| **g > generation)
+            .map(|(k, _)| k.to_owned())
+            .collect();
+        for k in &written {
+            match entry.hosts.get(k) {
+                Some(h) => fresh.insert(k.to_owned(), h.clone()),
+                None => fresh.remove(k),
+            };
+        }
+        entry.written.retain(|_, g| *g > generation);
+        let mut drift = Drift {
+            service: name.to_owned(),
+            ..Default::default()
+        };
+        for (k, h) in &fresh {
+            match entry.hosts.get(k) {
+                Some(cached) if cached == h => {}
+                Some(_) => drift.stale += 1,
+                None => drift.missing += 1,
+            }
+        }
+        drift.extra = entry
+            .hosts
+            .iter()
+            .filter(|(k, h)| h.expire_time >= now && !fresh.contains_key(*k))
+            .count() as u64;
+        if !written.is_empty() {
+            info!(
+                "Kept hosts written during resync: service={}, hosts={}",
+                name,
+                written.len()
+            );
+        }
+        if fresh.is_empty() && entry.written.is_empty() {
+            entries.remove(name);
+            return Ok(drift);
+        }
+        entry.hosts = fresh;
+        entry.synced_at = now;
+        entry.resync_failed = false;
+        Ok(drift)
+    }
+}
+
+impl<S: Storage> Storage for CachedStorage<S> {
+    type E = StorageError;
+
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E> {
+        if let Some(entry) = self.lock().get(name) {
+            return self.live_hosts(entry);
+        }
+        let hosts = self.inner.query_items(name).map_err(inner_error)?;
+        // Unknown services aren't cached, so that queries of arbitrary names don't grow the cache.
+        if hosts.is_empty() {
+            return Ok(hosts);
+        }
+        let now = self.clock.epoch_secs().map_err(StorageError::new)?;
+        let mut entries = self.lock();
+        // Another query may have filled the entry meanwhile, keep it since it may have writes.
+        let entry = entries.entry(name.to_owned()).or_insert_with(|| Entry {
+            hosts: hosts.iter().map(|h| (key(h), h.clone())).collect(),
+            generation: 0,
+            written: HashMap::new(),
+            synced_at: now,
+            resync_failed: false,
+        });
+        self.live_hosts(entry)
+    }
+
+    fn store_item(&self, name: &str, host: Host) -> Result<(), Self::E> {
+        self.inner
+            .store_item(name, host.clone())
+            .map_err(inner_error)?;
+        if let Some(entry) = self.lock().get_mut(name) {
+            entry.generation += 1;
+            entry.written.insert(key(&host), entry.generation);
+            entry.hosts.insert(key(&host), host);
+        }
+        Ok(())
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E> {
+        let k = format!("{}:{}", ip, port);
+        let removed = self
+            .inner
+            .delete_item(name, ip, port)
+            .map_err(inner_error)?;
+        if let Some(entry) = self.lock().get_mut(name) {
+            entry.hosts.remove(&k);
+            entry.generation += 1;
+            entry.written.insert(k, entry.generation);
+        }
+        Ok(removed)
+    }
+
+    fn reap_expired(&self, now: u64) -> Result<Vec<Host>, Self::E> {
+        let reaped = self.inner.reap_expired(now).map_err(inner_error)?;
+        let mut entries = self.lock();
+        for h in &reaped {
+            if let Some(entry) = entries.get_mut(&h.service) {
+                entry.hosts.remove(&key(h));
+                entry.generation += 1;
+                entry.written.insert(key(h), entry.generation);
+            }
+        }
+        Ok(reaped)
+    }
+
+    // Lookups across services aren't cached.
+    fn query_by_ip(&self, ip: &str) -> Result<Vec<Host>, Self::E> {
+        self.inner.query_by_ip(ip).map_err(inner_error)
+    }
+
+    fn list_services(&self) -> Result<Vec<String>, Self::E> {
+        self.inner.list_services().map_err(inner_error)
+    }
+
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, Self::E> {
+        self.inner.list_aliases().map_err(inner_error)
+    }
+
+    fn put_alias(&self, alias: &str, service: &str) -> Result<bool, Self::E> {
+        self.inner.put_alias(alias, service).map_err(inner_error)
+    }
+
+    fn delete_alias(&self, alias: &str) -> Result<Option<String>, Self::E> {
+        self.inner.delete_alias(alias).map_err(inner_error)
+    }
+
+    fn registry_index(&self, name: &str) -> Result<Option<RegistryIndex>, Self::E> {
+        self.inner.registry_index(name).map_err(inner_error)
+    }
+
+    // Services not cached yet are read live by the next query.
+    fn freshness(&self, name: &str) -> Freshness {
+        let now = self.clock.epoch_secs().unwrap_or(0);
+        match self.lock().get(name) {
+            Some(entry) => Freshness {
+                source: if entry.resync_failed {
+                    DataSource::StaleFallback
+                } else {
+                    DataSource::Cached
+                },
+                age_seconds: now.saturating_sub(entry.synced_at),
+            },
+            None => Freshness::live(),
+        }
+    }
+
+    fn ttl(&self) -> u64 {
+        self.inner.ttl()
+    }
+
+    // The cache is shared with the returned storage.
+    fn with_deadline(&self, deadline: Instant) -> Self {
+        CachedStorage {
+            inner: self.inner.with_deadline(deadline),
+            clock: self.clock.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+
+    fn check_schema(&self) -> Result<SchemaCheck, Self::E> {
+        self.inner.check_schema().map_err(inner_error)
+    }
+
+    fn init_schema(&self) -> Result<(), Self::E> {
+        self.inner.init_schema().map_err(inner_error)
+    }
+
+    fn warm_up(&self) -> Result<(), Self::E> {
+        self.inner.warm_up().map_err(inner_error)
+    }
+
+    fn resync(&self) -> Result<Vec<Drift>, Self::E> {
+        let names: Vec<String> = self.lock().keys().cloned().collect();
+        let mut drifts = Vec::with_capacity(names.len());
+        for name in names {
+            let drift = match self.resync_service(&name) {
+                Ok(v) => v,
+                Err(e) => Drift {
+                    service: name,
+                    error: Some(e.to_string()),
+                    ..Default::default()
+                },
+            };
+            if !drift.is_empty() {
+                drifts.push(drift);
+            }
+        }
+        Ok(drifts)
+    }
+}
+
+fn key(h: &Host) -> String {
+    format!("{}:{}", h.ip_address, h.port)
+}
+
+fn inner_error<E: std::fmt::Display>(e: E) -> StorageError {
+    StorageError::new(e.to_string())
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Source of the wall clock time for expiry, reaping, draining and slow-start, so that tests can
+// control the time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    // Returns the current time in epoch seconds.
+    fn epoch_secs(&self) -> Result<u64, String> {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .map_err(|_| "Failed to fetch system time".to_owned())
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+// Clock which only moves when told to. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl MockClock {
+    pub fn new(epoch_secs: u64) -> Self {
+        MockClock {
+            now: Arc::new(Mutex::new(UNIX_EPOCH + Duration::from_secs(epoch_secs))),
+        }
+    }
+
+    pub fn set(&self, epoch_secs: u64) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now = UNIX_EPOCH + Duration::from_secs(epoch_secs);
+    }
+
+    pub fn advance(&self, d: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now += d;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
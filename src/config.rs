@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use log::{info, LevelFilter};
+use serde_derive::Deserialize;
+use serde_json;
+
+use super::access::AccessConfig;
+use super::limiter::LoadSheddingConfig;
+use super::quota::QuotaConfig;
+use super::region::RegionPinning;
+use super::slo::SloObjective;
+use super::virtual_service::VirtualServiceConfig;
+
+// Sections other than `storage` are reloadable at runtime. `log_level` is reloadable only when
+// it's set on startup since it decides how the logger is initialized.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct FileConfig {
+    pub log_level: Option<String>,
+    pub storage: StorageConfig,
+    pub services: HashMap<String, ServiceConfig>,
+    pub duplicate_hosts: DuplicateHostsConfig,
+    pub quotas: QuotaConfig,
+    // Services merging the hosts of other services, keyed by their names.
+    pub virtual_services: HashMap<String, VirtualServiceConfig>,
+    // Objectives evaluated by SloTracker.
+    pub slos: Vec<SloObjective>,
+    // Address based restrictions of writes and the admin API.
+    pub access: AccessConfig,
+    // Limits of ConcurrencyLimiter, overriding the environment variables.
+    pub load_shedding: LoadSheddingConfig,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct StorageConfig {
+    // Key of the backend in StorageRegistry, e.g. "dynamodb" or "memory".
+    #[serde(rename = "type")]
+    pub storage_type: Option<String>,
+    // Backend specific options passed to the backend's factory.
+    pub options: HashMap<String, String>,
+    // Backend shadowing the primary one while migrating to it, see ShadowStorage.
+    pub secondary: Option<SecondaryStorageConfig>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct SecondaryStorageConfig {
+    #[serde(rename = "type")]
+    pub storage_type: String,
+    pub options: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct ServiceConfig {
+    // Populates ClusterLoadAssignment.policy.overprovisioning_factor in EDS.
+    pub overprovisioning_factor: Option<u32>,
+    // Maps a zone (az tag) to the priority of its locality in EDS. Zones missing here are put
+    // into the next priority after the largest configured one.
+    pub priorities: HashMap<String, u32>,
+    // Seconds over which the EDS weight of a draining host ramps down to zero. Defaults to 60.
+    pub drain_period_sec: Option<u64>,
+    // Seconds over which the EDS weight of a newly registered host ramps up to full. Slow-start
+    // is disabled when missing.
+    pub slow_start_sec: Option<u64>,
+    // Prioritizes localities relative to the locality of the requesting node, taking precedence
+    // over `priorities` when the node tells its locality.
+    pub zone_aware: bool,
+    // Tag keys copied into filter_metadata["envoy.lb"] of endpoints for subset load balancing.
+    pub metadata_keys: Vec<String>,
+    // Maximum number of hosts of the service. New hosts over it are rejected on registration
+    // and responses are truncated to it.
+    pub max_hosts: Option<usize>,
+    // Favors hosts in the region of the client, see RegionPinning.
+    pub region_pinning: RegionPinning,
+}
+
+// Policy for hosts registered under more than one service, which usually means a misconfigured
+// agent.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct DuplicateHostsConfig {
+    pub scope: DuplicateScope,
+    pub action: DuplicateAction,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateScope {
+    // The same ip:port must not be registered under other services.
+    IpPort,
+    // The same ip must not be registered under other services on any port.
+    Ip,
+}
+
+impl Default for DuplicateScope {
+    fn default() -> Self {
+        DuplicateScope::IpPort
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAction {
+    Off,
+    // Accepts the registration and records the conflict.
+    Flag,
+    // Rejects new hosts conflicting with hosts of other services and records the conflict.
+    Reject,
+}
+
+impl Default for DuplicateAction {
+    fn default() -> Self {
+        DuplicateAction::Off
+    }
+}
+
+impl FileConfig {
+    pub fn parse_log_level(&self) -> Result<Option<LevelFilter>, String> {
+        match self.log_level {
+            Some(ref v) => match LevelFilter::from_str(v) {
+                Ok(level) => Ok(Some(level)),
+                Err(_) => Err(format!("Invalid log_level: {}", v)),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+pub fn load_file_config(path: &str) -> Result<FileConfig, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to read config file {}: {}", path, e)),
+    };
+    let file_config: FileConfig = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+    file_config.parse_log_level()?;
+    Ok(file_config)
+}
+
+// Holds the content of the config file which can be swapped by reload() without restart.
+#[derive(Debug, Clone)]
+pub struct ReloadableConfig {
+    path: Option<String>,
+    current: Arc<RwLock<Arc<FileConfig>>>,
+}
+
+impl ReloadableConfig {
+    pub fn new(path: Option<String>, initial: FileConfig) -> Self {
+        ReloadableConfig {
+            path,
+            current: Arc::new(RwLock::new(Arc::new(initial))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<FileConfig> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    // Re-reads the config file and applies it. The running config is kept as is when the file
+    // is invalid or contains changes which require restart.
+    pub fn reload(&self) -> Result<(), String> {
+        let path = match self.path {
+            Some(ref v) => v,
+            None => return Err("CONFIG_FILE is not set".to_owned()),
+        };
+        let new_config = load_file_config(path)?;
+
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        if new_config.storage != current.storage {
+            return Err(
+                "storage section can't be reloaded, restart sds to apply the change".to_owned(),
+            );
+        }
+        if new_config.log_level.is_some() != current.log_level.is_some() {
+            return Err(
+                "log_level can't be added or removed by reload, restart sds to apply the change"
+                    .to_owned(),
+            );
+        }
+        if let Some(level) = new_config.parse_log_level()? {
+            log::set_max_level(level);
+        }
+        *current = Arc::new(new_config);
+        info!("Reloaded config file: {}", path);
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use serde_derive::Serialize;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Conflict {
+    pub service: String,
+    pub ip_address: String,
+    pub port: u16,
+    // Other services having a host with the same ip:port, or the same ip in the "ip" scope.
+    pub conflicting_services: Vec<String>,
+    // Whether the last registration was rejected.
+    pub rejected: bool,
+    // Epoch seconds of the last registration which hit the conflict.
+    pub last_seen: u64,
+}
+
+// Conflicts found on registration by this process, exposed by /admin/conflicts. A conflict is
+// forgotten once no registration hits it for `window` seconds.
+#[derive(Debug, Clone)]
+pub struct ConflictTracker {
+    window: u64,
+    // (service, "ip:port") -> conflict
+    conflicts: Arc<Mutex<BTreeMap<(String, String), Conflict>>>,
+}
+
+impl ConflictTracker {
+    pub fn new(window: u64) -> Self {
+        ConflictTracker {
+            window,
+            conflicts: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn record(&self, conflict: Conflict) {
+        let key = (
+            conflict.service.to_owned(),
+            format!("{}:{}", conflict.ip_address, conflict.port),
+        );
+        let now = conflict.last_seen;
+        let mut conflicts = self.conflicts.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune(&mut conflicts, now);
+        conflicts.insert(key, conflict);
+    }
+
+    // Clears the conflict once the host registers without hitting it.
+    pub fn resolve(&self, service: &str, ip: &str, port: u16) {
+        let key = (service.to_owned(), format!("{}:{}", ip, port));
+        let mut conflicts = self.conflicts.lock().unwrap_or_else(|e| e.into_inner());
+        conflicts.remove(&key);
+    }
+
+    // Returns the conflicts ordered by service and ip:port.
+    pub fn list(&self, now: u64) -> Vec<Conflict> {
+        let mut conflicts = self.conflicts.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune(&mut conflicts, now);
+        conflicts.values().cloned().collect()
+    }
+
+    fn prune(&self, conflicts: &mut BTreeMap<(String, String), Conflict>, now: u64) {
+        let window = self.window;
+        let stale: Vec<(String, String)> = conflicts
+            .iter()
+            .filter(|(_, c)| c.last_seen.saturating_add(window) < now)
+            .map(|(k, _)| k.to_owned())
+            .collect();
+        for k in stale {
+            conflicts.remove(&k);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use serde_json::Value;
+
+use super::storage::StorageError;
+use super::types::{Drift, Freshness, Host, RegistryIndex, SchemaCheck, Storage, Tag};
+
+// Marks encrypted values so that plain values written before enabling encryption stay readable.
+const PREFIX: &str = "enc:v1:";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+// Encrypts tag values with AES-256-GCM. Each value gets a random nonce, stored in front of the
+// ciphertext as "enc:v1:" + base64(nonce || ciphertext).
+#[derive(Clone)]
+pub struct TagCipher {
+    cipher: Arc<Aes256Gcm>,
+}
+
+impl TagCipher {
+    pub fn new(key: &[u8]) -> Result<Self, String> {
+        if key.len() != KEY_LEN {
+            return Err(format!(
+                "Tag encryption key must be {} bytes, got {}",
+                KEY_LEN,
+                key.len()
+            ));
+        }
+        Ok(TagCipher {
+            cipher: Arc::new(Aes256Gcm::new(Key::from_slice(key))),
+        })
+    }
+
+    pub fn from_base64(key: &str) -> Result<Self, String> {
+        let key = base64::decode(key.trim())
+            .map_err(|e| format!("Tag encryption key is invalid as base64: {}", e))?;
+        TagCipher::new(&key)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, String> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| "Failed to encrypt tag value".to_owned())?;
+        let mut buf = nonce.to_vec();
+        buf.extend(ciphertext);
+        Ok(format!("{}{}", PREFIX, base64::encode(&buf)))
+    }
+
+    // Values without the prefix are returned as is.
+    pub fn decrypt(&self, value: &str) -> Result<String, String> {
+        if !value.starts_with(PREFIX) {
+            return Ok(value.to_owned());
+        }
+        let buf = base64::decode(&value[PREFIX.len()..])
+            .map_err(|e| format!("Encrypted tag value is invalid as base64: {}", e))?;
+        if buf.len() < NONCE_LEN {
+            return Err("Encrypted tag value is too short".to_owned());
+        }
+        let (nonce, ciphertext) = buf.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Failed to decrypt tag value, the key may be wrong".to_owned())?;
+        String::from_utf8(plaintext).map_err(|_| "Decrypted tag value is not UTF-8".to_owned())
+    }
+
+    fn encrypt_tag(&self, tag: Tag) -> Result<Tag, String> {
+        Ok(Tag {
+            az: self.encrypt(&tag.az)?,
+            region: self.encrypt(&tag.region)?,
+            instance_id: self.encrypt(&tag.instance_id)?,
+            canary: tag.canary,
+            load_balancing_weight: tag.load_balancing_weight,
+            extra: self.map_values(tag.extra, |v| self.encrypt(v))?,
+        })
+    }
+
+    fn decrypt_tag(&self, tag: Tag) -> Result<Tag, String> {
+        Ok(Tag {
+            az: self.decrypt(&tag.az)?,
+            region: self.decrypt(&tag.region)?,
+            instance_id: self.decrypt(&tag.instance_id)?,
+            canary: tag.canary,
+            load_balancing_weight: tag.load_balancing_weight,
+            extra: self.map_values(tag.extra, |v| self.decrypt(v))?,
+        })
+    }
+
+    // Only string values are encrypted, the others are kept as they are.
+    fn map_values<F>(
+        &self,
+        m: BTreeMap<String, Value>,
+        f: F,
+    ) -> Result<BTreeMap<String, Value>, String>
+    where
+        F: Fn(&str) -> Result<String, String>,
+    {
+        m.into_iter()
+            .map(|(k, v)| match v {
+                Value::String(s) => Ok((k, Value::String(f(&s)?))),
+                v => Ok((k, v)),
+            })
+            .collect()
+    }
+
+    fn decrypt_host(&self, mut host: Host) -> Result<Host, StorageError> {
+        host.tags = self.decrypt_tag(host.tags).map_err(StorageError::new)?;
+        Ok(host)
+    }
+
+    fn decrypt_hosts(&self, hosts: Vec<Host>) -> Result<Vec<Host>, StorageError> {
+        hosts.into_iter().map(|h| self.decrypt_host(h)).collect()
+    }
+}
+
+// Decrypts a data key encrypted by a KMS master key, for envelope encryption.
+#[cfg(feature = "kms")]
+pub fn decrypt_data_key(ciphertext: &str) -> Result<Vec<u8>, String> {
+    use rusoto_kms::{DecryptRequest, Kms, KmsClient};
+
+    let ciphertext_blob = base64::decode(ciphertext.trim())
+        .map_err(|e| format!("KMS encrypted key is invalid as base64: {}", e))?;
+    // rusoto requires AWS_DEFAULT_REGION env.
+    let client = KmsClient::new(Default::default());
+    let res = client
+        .decrypt(DecryptRequest {
+            ciphertext_blob,
+            ..Default::default()
+        })
+        .sync()
+        .map_err(|e| format!("API Error in decrypt: {}", e))?;
+    res.plaintext
+        .ok_or_else(|| "KMS returned no plaintext for the key".to_owned())
+}
+
+// Encrypts tag values before they reach the inner storage and decrypts them on the way back, so
+// that API consumers see plain values.
+#[derive(Clone)]
+pub struct EncryptedStorage<S> {
+    inner: S,
+    cipher: TagCipher,
+}
+
+impl<S: Storage> EncryptedStorage<S> {
+    pub fn new(inner: S, cipher: TagCipher) -> Self {
+        EncryptedStorage { inner, cipher }
+    }
+}
+
+impl<S: Storage> Storage for EncryptedStorage<S> {
+    type E = StorageError;
+
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E> {
+        let hosts = self.inner.query_items(name).map_err(inner_error)?;
+        self.cipher.decrypt_hosts(hosts)
+    }
+
+    fn store_item(&self, name: &str, mut host: Host) -> Result<(), Self::E> {
+        host.tags = self
+            .cipher
+            .encrypt_tag(host.tags)
+            .map_err(StorageError::new)?;
+        self.inner.store_item(name, host).map_err(inner_error)
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E> {
+        match self
+            .inner
+            .delete_item(name, ip, port)
+            .map_err(inner_error)?
+        {
+            Some(h) => self.cipher.decrypt_host(h).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn get_item(&self, name: &str, ip: &str, port: u16) -> Result<Option<Host>, Self::E> {
+        match self.inner.get_item(name, ip, port).map_err(inner_error)? {
+            Some(h) => self.cipher.decrypt_host(h).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn reap_expired(&self, now: u64) -> Result<Vec<Host>, Self::E> {
+        let hosts = self.inner.reap_expired(now).map_err(inner_error)?;
+        self.cipher.decrypt_hosts(hosts)
+    }
+
+    fn query_by_ip(&self, ip: &str) -> Result<Vec<Host>, Self::E> {
+        let hosts = self.inner.query_by_ip(ip).map_err(inner_error)?;
+        self.cipher.decrypt_hosts(hosts)
+    }
+
+    fn list_services(&self) -> Result<Vec<String>, Self::E> {
+        self.inner.list_services().map_err(inner_error)
+    }
+
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, Self::E> {
+        self.inner.list_aliases().map_err(inner_error)
+    }
+
+    fn put_alias(&self, alias: &str, service: &str) -> Result<bool, Self::E> {
+        self.inner.put_alias(alias, service).map_err(inner_error)
+    }
+
+    fn delete_alias(&self, alias: &str) -> Result<Option<String>, Self::E> {
+        self.inner.delete_alias(alias).map_err(inner_error)
+    }
+
+    fn registry_index(&self, name: &str) -> Result<Option<RegistryIndex>, Self::E> {
+        self.inner.registry_index(name).map_err(inner_error)
+    }
+
+    fn freshness(&self, name: &str) -> Freshness {
+        self.inner.freshness(name)
+    }
+
+    fn ttl(&self) -> u64 {
+        self.inner.ttl()
+    }
+
+    fn with_deadline(&self, deadline: Instant) -> Self {
+        EncryptedStorage {
+            inner: self.inner.with_deadline(deadline),
+            cipher: self.cipher.clone(),
+        }
+    }
+
+    fn check_schema(&self) -> Result<SchemaCheck, Self::E> {
+        self.inner.check_schema().map_err(inner_error)
+    }
+
+    fn init_schema(&self) -> Result<(), Self::E> {
+        self.inner.init_schema().map_err(inner_error)
+    }
+
+    fn warm_up(&self) -> Result<(), Self::E> {
+        self.inner.warm_up().map_err(inner_error)
+    }
+
+    fn resync(&self) -> Result<Vec<Drift>, Self::E> {
+        self.inner.resync().map_err(inner_error)
+    }
+}
+
+fn inner_error<E: std::fmt::Display>(e: E) -> StorageError {
+    StorageError::new(e.to_string())
+}
@@ -0,0 +1,138 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde_derive::Serialize;
+use serde_json;
+
+use super::clock::{system_clock, SharedClock};
+use super::types::Host;
+use super::webhook::Webhook;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Register,
+    Delete,
+    // Emitted when tags of a host are changed by PATCH.
+    Update,
+    // Emitted by the reaper when it removes an expired host.
+    Expire,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub service: String,
+    // The host record as stored, for Delete and Expire the removed one.
+    pub host: Host,
+    // Epoch seconds when the event happened.
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EventConfig {
+    // Path of a file events are appended to as JSON lines.
+    pub log_file: Option<String>,
+    // URL events are posted to as JSON one by one.
+    pub webhook_url: Option<String>,
+}
+
+// Delivers events to the configured sinks in background, in the order they are emitted.
+#[derive(Clone)]
+pub struct EventEmitter {
+    tx: Option<Arc<Mutex<Sender<Event>>>>,
+    clock: SharedClock,
+}
+
+impl EventEmitter {
+    pub fn new(config: &EventConfig, clock: SharedClock) -> Self {
+        let log_file = config.log_file.as_ref().and_then(|path| {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(f) => Some(f),
+                Err(e) => {
+                    error!("Failed to open event log {}: {}", path, e);
+                    None
+                }
+            }
+        });
+        let webhook_url = config.webhook_url.clone();
+        if log_file.is_none() && webhook_url.is_none() {
+            return EventEmitter { tx: None, clock };
+        }
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // The webhook blocks on its own runtime, which has to live in this thread.
+            let webhook = webhook_url.and_then(|url| match Webhook::new(&url, WEBHOOK_TIMEOUT) {
+                Ok(w) => Some(w),
+                Err(e) => {
+                    error!("Failed to set up event webhook: {}", e);
+                    None
+                }
+            });
+            deliver(rx, log_file, webhook)
+        });
+        EventEmitter {
+            tx: Some(Arc::new(Mutex::new(tx))),
+            clock,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        EventEmitter {
+            tx: None,
+            clock: system_clock(),
+        }
+    }
+
+    pub fn emit(&self, kind: EventKind, service: &str, host: Host) {
+        let tx = match self.tx {
+            Some(ref v) => v,
+            None => return,
+        };
+        let timestamp = self.clock.epoch_secs().unwrap_or(0);
+        let event = Event {
+            kind,
+            service: service.to_owned(),
+            host,
+            timestamp,
+        };
+        let tx = tx.lock().unwrap_or_else(|e| e.into_inner());
+        if tx.send(event).is_err() {
+            warn!("Event delivery stopped, dropping event: kind={:?}", kind);
+        }
+    }
+}
+
+fn deliver(rx: Receiver<Event>, mut log_file: Option<File>, mut webhook: Option<Webhook>) {
+    for event in rx {
+        let body = match serde_json::to_vec(&event) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to serialize event: {}", e);
+                continue;
+            }
+        };
+        if let Some(ref mut f) = log_file {
+            if let Err(e) = f.write_all(&body).and_then(|_| f.write_all(b"\n")) {
+                error!("Failed to write event log: {}", e);
+            }
+        }
+        if let Some(ref mut w) = webhook {
+            if let Err(e) = w.post(&body) {
+                error!("Failed to post event to webhook: {}", e);
+            }
+        }
+        info!(
+            "Delivered event: kind={:?}, service={}, ip={}, port={}",
+            event.kind, event.service, event.host.ip_address, event.host.port
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+#[derive(Debug, Clone)]
+pub struct FeedbackConfig {
+    // Length of the window failure rates are aggregated over.
+    pub window: Duration,
+    // Failure rate (0.0 - 1.0) at or above which an endpoint gets demoted.
+    pub failure_rate_threshold: f64,
+    // Minimum number of reported requests in a window before the failure rate is evaluated.
+    pub min_requests: u64,
+    // How long a demoted endpoint stays demoted.
+    pub demotion: Duration,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        FeedbackConfig {
+            window: Duration::from_secs(60),
+            failure_rate_threshold: 0.5,
+            min_requests: 10,
+            demotion: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EndpointStats {
+    window_start: Instant,
+    requests: u64,
+    failures: u64,
+    demoted_until: Option<Instant>,
+}
+
+// Aggregates endpoint failures reported by Envoys or agents. The state is local to this process.
+#[derive(Debug, Clone)]
+pub struct FeedbackTracker {
+    config: FeedbackConfig,
+    // Keyed by (service, "ip:port").
+    stats: Arc<Mutex<HashMap<(String, String), EndpointStats>>>,
+}
+
+impl FeedbackTracker {
+    pub fn new(config: FeedbackConfig) -> Self {
+        FeedbackTracker {
+            config,
+            stats: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Records the reported numbers and returns whether the endpoint is demoted afterwards.
+    pub fn report(&self, service: &str, ip_port: &str, requests: u64, failures: u64) -> bool {
+        let now = Instant::now();
+        let config = &self.config;
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats.retain(|_, s| {
+            now.duration_since(s.window_start) < config.window
+                || s.demoted_until.map_or(false, |t| t > now)
+        });
+
+        let s = stats
+            .entry((service.to_owned(), ip_port.to_owned()))
+            .or_insert(EndpointStats {
+                window_start: now,
+                requests: 0,
+                failures: 0,
+                demoted_until: None,
+            });
+        if now.duration_since(s.window_start) >= config.window {
+            s.window_start = now;
+            s.requests = 0;
+            s.failures = 0;
+        }
+        // A report of failures only still counts the failed requests.
+        s.requests = s.requests.saturating_add(requests.max(failures));
+        s.failures = s.failures.saturating_add(failures);
+
+        if s.requests >= config.min_requests
+            && s.failures as f64 / s.requests as f64 >= config.failure_rate_threshold
+        {
+            info!(
+                "Demote endpoint: service={}, ip_port={}, requests={}, failures={}",
+                service, ip_port, s.requests, s.failures
+            );
+            s.demoted_until = Some(now + config.demotion);
+            s.window_start = now;
+            s.requests = 0;
+            s.failures = 0;
+        }
+        s.demoted_until.map_or(false, |t| t > now)
+    }
+
+    // Returns "ip:port" of the currently demoted endpoints of the service.
+    pub fn demoted_endpoints(&self, service: &str) -> HashSet<String> {
+        let now = Instant::now();
+        let stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats
+            .iter()
+            .filter(|((s, _), v)| s == service && v.demoted_until.map_or(false, |t| t > now))
+            .map(|((_, ip_port), _)| ip_port.to_owned())
+            .collect()
+    }
+}
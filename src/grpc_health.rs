@@ -0,0 +1,193 @@
+use std::net::SocketAddr;
+
+use futures::{future, Async, Future, Poll, Stream};
+use hyper::body::Payload;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::service::service_fn;
+use hyper::{Body, Chunk, Request, Response, Server};
+use log::{error, info};
+
+use super::readiness::Readiness;
+
+const CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+// Services answered by Check. "" is the overall health of the server.
+const SERVICES: &[&str] = &["", "sds"];
+
+// grpc.health.v1.HealthCheckResponse.ServingStatus
+const SERVING: u8 = 1;
+const NOT_SERVING: u8 = 2;
+
+// gRPC status codes
+const GRPC_OK: u16 = 0;
+const GRPC_INVALID_ARGUMENT: u16 = 3;
+const GRPC_NOT_FOUND: u16 = 5;
+const GRPC_UNIMPLEMENTED: u16 = 12;
+
+// Serves grpc.health.v1.Health over HTTP/2 without TLS, reflecting the readiness of /hc/ready.
+// Only Check is implemented, Watch responds UNIMPLEMENTED. The server stops when `shutdown`
+// completes.
+pub fn serve_grpc_health<F>(
+    addr: &SocketAddr,
+    readiness: Readiness,
+    shutdown: F,
+) -> impl Future<Item = (), Error = ()> + Send
+where
+    F: Future<Item = ()> + Send + 'static,
+{
+    let server = Server::bind(addr)
+        .http2_only(true)
+        .serve(move || {
+            let readiness = readiness.clone();
+            service_fn(move |req| handle(&readiness, req))
+        })
+        .with_graceful_shutdown(shutdown)
+        .map_err(|e| error!("gRPC health server error: {}", e));
+    info!("Serving gRPC health on {}", addr);
+    server
+}
+
+fn handle(
+    readiness: &Readiness,
+    req: Request<Body>,
+) -> Box<Future<Item = Response<GrpcBody>, Error = hyper::Error> + Send> {
+    if req.uri().path() != CHECK_PATH {
+        return Box::new(future::ok(build_response(
+            None,
+            GRPC_UNIMPLEMENTED,
+            "Only Check is implemented",
+        )));
+    }
+    let readiness = readiness.clone();
+    let f = req.into_body().concat2().map(move |buffer| {
+        let service = match decode_frame(&buffer).and_then(decode_check_request) {
+            Ok(v) => v,
+            Err(msg) => return build_response(None, GRPC_INVALID_ARGUMENT, &msg),
+        };
+        if !SERVICES.contains(&service.as_str()) {
+            return build_response(None, GRPC_NOT_FOUND, "Unknown service");
+        }
+        let status = if readiness.is_ready() {
+            SERVING
+        } else {
+            NOT_SERVING
+        };
+        build_response(Some(encode_check_response(status)), GRPC_OK, "")
+    });
+    Box::new(f)
+}
+
+fn build_response(message: Option<Vec<u8>>, status: u16, reason: &str) -> Response<GrpcBody> {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(status));
+    if !reason.is_empty() {
+        if let Ok(v) = HeaderValue::from_str(reason) {
+            trailers.insert("grpc-message", v);
+        }
+    }
+    let mut res = Response::new(GrpcBody {
+        data: message.map(|m| Chunk::from(encode_frame(&m))),
+        trailers: Some(trailers),
+    });
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    res
+}
+
+// Response body of a unary call: at most one message followed by the status in trailers.
+pub struct GrpcBody {
+    data: Option<Chunk>,
+    trailers: Option<HeaderMap>,
+}
+
+impl Payload for GrpcBody {
+    type Data = Chunk;
+    type Error = hyper::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+        Ok(Async::Ready(self.data.take()))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, hyper::Error> {
+        Ok(Async::Ready(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none() && self.trailers.is_none()
+    }
+}
+
+// Prefixes the message with the uncompressed flag and its length.
+pub fn encode_frame(message: &[u8]) -> Vec<u8> {
+    let len = message.len() as u32;
+    let mut buf = Vec::with_capacity(message.len() + 5);
+    buf.push(0);
+    buf.extend_from_slice(&[
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+    ]);
+    buf.extend_from_slice(message);
+    buf
+}
+
+// Returns the message of the only frame of a unary request.
+pub fn decode_frame(buf: &[u8]) -> Result<&[u8], String> {
+    if buf.len() < 5 {
+        return Err("Truncated gRPC frame".to_owned());
+    }
+    if buf[0] != 0 {
+        return Err("Compressed messages are not supported".to_owned());
+    }
+    let len = buf[1..5]
+        .iter()
+        .fold(0usize, |acc, b| (acc << 8) | usize::from(*b));
+    buf.get(5..5 + len)
+        .ok_or_else(|| "Truncated gRPC message".to_owned())
+}
+
+// Returns `service` (field 1) of a HealthCheckRequest, skipping unknown fields.
+pub fn decode_check_request(mut buf: &[u8]) -> Result<String, String> {
+    let mut service = String::new();
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf)?;
+        let len = match key & 0x7 {
+            0 => {
+                read_varint(&mut buf)?;
+                0
+            }
+            1 => 8,
+            2 => read_varint(&mut buf)? as usize,
+            5 => 4,
+            t => return Err(format!("Unsupported wire type: {}", t)),
+        };
+        if buf.len() < len {
+            return Err("Truncated HealthCheckRequest".to_owned());
+        }
+        let (value, rest) = buf.split_at(len);
+        if key == (1 << 3) | 2 {
+            service = String::from_utf8(value.to_vec()).map_err(|e| e.to_string())?;
+        }
+        buf = rest;
+    }
+    Ok(service)
+}
+
+// HealthCheckResponse with `status` (field 1).
+pub fn encode_check_response(status: u8) -> Vec<u8> {
+    vec![1 << 3, status]
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64, String> {
+    let bytes: &[u8] = *buf;
+    let mut value = 0u64;
+    for (i, b) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            *buf = &bytes[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err("Malformed varint".to_owned())
+}
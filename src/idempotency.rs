@@ -0,0 +1,128 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hyper::{HeaderMap, StatusCode};
+
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum Lookup {
+    // No request with the key is known. The caller should complete the returned key with the
+    // response, otherwise the key is aborted when it's dropped.
+    Miss(PendingKey),
+    // The request with the key has been processed, the original response should be replayed.
+    Hit(CachedResponse),
+    // The request with the key is still being processed.
+    InProgress,
+    // The key was used with a different request.
+    Mismatch,
+}
+
+#[derive(Debug)]
+struct Entry {
+    stored_at: Instant,
+    fingerprint: u64,
+    response: Option<CachedResponse>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyCache {
+    window: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl IdempotencyCache {
+    pub fn new(window: Duration) -> Self {
+        IdempotencyCache {
+            window,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn begin(&self, key: &str, fingerprint: u64) -> Lookup {
+        let now = Instant::now();
+        let window = self.window;
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, e| now.duration_since(e.stored_at) < window);
+
+        match entries.get(key) {
+            Some(e) if e.fingerprint != fingerprint => Lookup::Mismatch,
+            Some(e) => match e.response {
+                Some(ref res) => Lookup::Hit(res.clone()),
+                None => Lookup::InProgress,
+            },
+            None => {
+                entries.insert(
+                    key.to_owned(),
+                    Entry {
+                        stored_at: now,
+                        fingerprint,
+                        response: None,
+                    },
+                );
+                Lookup::Miss(PendingKey {
+                    cache: self.clone(),
+                    key: key.to_owned(),
+                    completed: false,
+                })
+            }
+        }
+    }
+
+    // Stores the outcome of the request. Server errors and 429 are not cached so that retries can
+    // succeed, e.g. after Retry-After of a rate limit.
+    pub fn complete(&self, key: &str, response: CachedResponse) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if response.status.is_server_error() || response.status == StatusCode::TOO_MANY_REQUESTS {
+            entries.remove(key);
+            return;
+        }
+        if let Some(e) = entries.get_mut(key) {
+            e.stored_at = Instant::now();
+            e.response = Some(response);
+        }
+    }
+
+    pub fn abort(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.remove(key);
+    }
+}
+
+// A key being processed. Dropping it without complete(), e.g. when the client disconnects or the
+// response body fails, aborts the key so that retries aren't rejected as in progress.
+#[derive(Debug)]
+pub struct PendingKey {
+    cache: IdempotencyCache,
+    key: String,
+    completed: bool,
+}
+
+impl PendingKey {
+    pub fn complete(mut self, response: CachedResponse) {
+        self.cache.complete(&self.key, response);
+        self.completed = true;
+    }
+}
+
+impl Drop for PendingKey {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.cache.abort(&self.key);
+        }
+    }
+}
+
+pub fn fingerprint(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,70 @@
+use chrono::TimeZone;
+use serde_derive::Serialize;
+
+use super::storage::StorageRegistry;
+
+// Cargo features this binary is built with.
+const FEATURES: &[(&str, bool)] = &[
+    ("dynamodb", cfg!(feature = "dynamodb")),
+    ("memory", cfg!(feature = "memory")),
+    ("encryption", cfg!(feature = "encryption")),
+    ("kms", cfg!(feature = "kms")),
+    ("test-util", cfg!(feature = "test-util")),
+];
+
+// What's deployed, embedded at compile time by build.rs.
+#[derive(Serialize, Debug, Clone)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    // RFC 3339 time of the build.
+    pub built_at: String,
+    pub features: Vec<&'static str>,
+    // Storage types the binary can create, see StorageRegistry.
+    pub storage_backends: Vec<String>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let built_at = env!("SDS_BUILD_TIMESTAMP").parse::<i64>().unwrap_or(0);
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("SDS_GIT_SHA"),
+            built_at: chrono::Utc
+                .timestamp_opt(built_at, 0)
+                .single()
+                .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+                .unwrap_or_default(),
+            features: FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| *name)
+                .collect(),
+            storage_backends: StorageRegistry::new()
+                .storage_types()
+                .into_iter()
+                .map(|t| t.to_owned())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RuntimeInfo {
+    pub uptime_seconds: u64,
+    // TTL of hosts in seconds.
+    pub ttl_seconds: u64,
+    // Worker threads of the runtime started by server::run(). Runtimes of applications embedding
+    // sds aren't known.
+    pub core_threads: usize,
+    // Threads querying clusters of EDS requests.
+    pub eds_query_threads: usize,
+    // Sockets accepting connections, more than 1 with SO_REUSEPORT.
+    pub acceptors: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Info {
+    pub build: BuildInfo,
+    pub runtime: RuntimeInfo,
+}
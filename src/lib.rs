@@ -1,4 +1,41 @@
+pub mod access;
+pub mod admission;
+pub mod agent;
+pub mod aliases;
+pub mod anti_entropy;
+pub mod bootstrap;
+pub mod cache;
+pub mod clock;
+pub mod config;
+pub mod conflicts;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod events;
+pub mod feedback;
+pub mod grpc_health;
+pub mod idempotency;
+pub mod info;
+pub mod limiter;
+pub mod maintenance;
+pub mod metrics;
+pub mod payload_log;
+pub mod quota;
+pub mod readiness;
+pub mod reaper;
+pub mod region;
+pub mod request;
 pub mod server;
+pub mod shadow;
+pub mod shutdown;
+pub mod slo;
+pub mod statsd;
 pub mod storage;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 pub mod types;
 pub mod v2xds;
+pub mod versions;
+pub mod virtual_service;
+pub mod webhook;
+pub mod write_lock;
+pub mod xds_file;
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_derive::Deserialize;
+
+use super::config::ReloadableConfig;
+
+// Limits of the environment variables, overridden by LoadSheddingConfig of the config file.
+#[derive(Debug, Clone)]
+pub struct LimitConfig {
+    // Maximum number of requests in flight over all routes. Unlimited when missing.
+    pub global: Option<usize>,
+    // Maximum number of requests in flight per route name, e.g. "eds" or "registration".
+    pub per_route: HashMap<String, usize>,
+    // Sent in Retry-After header of 503 responses.
+    pub retry_after: Duration,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        LimitConfig {
+            global: None,
+            per_route: HashMap::new(),
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+// `load_shedding` section of the config file, reloadable unlike the environment variables. Set
+// limits take precedence over LimitConfig.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct LoadSheddingConfig {
+    pub max_concurrent_requests: Option<usize>,
+    pub max_concurrent_requests_per_route: HashMap<String, usize>,
+    pub retry_after_sec: Option<u64>,
+}
+
+// Counts requests in flight and sheds requests over the limits instead of queueing them. The
+// limits are read from the current config file on every request, so reloads apply to requests
+// arriving afterwards.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimiter {
+    config: Arc<LimitConfig>,
+    file: ReloadableConfig,
+    global: Arc<AtomicUsize>,
+    routes: Arc<Mutex<HashMap<&'static str, Arc<AtomicUsize>>>>,
+}
+
+// Releases the slots when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    counters: Vec<Arc<AtomicUsize>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        for c in &self.counters {
+            c.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(config: LimitConfig, file: ReloadableConfig) -> Self {
+        ConcurrencyLimiter {
+            config: Arc::new(config),
+            file,
+            global: Arc::new(AtomicUsize::new(0)),
+            routes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn retry_after(&self) -> Duration {
+        self.file
+            .current()
+            .load_shedding
+            .retry_after_sec
+            .map(Duration::from_secs)
+            .unwrap_or(self.config.retry_after)
+    }
+
+    // Returns None when the global or the route's limit is saturated.
+    pub fn try_acquire(&self, route: &'static str) -> Option<Permit> {
+        let route_counter = {
+            let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+            routes
+                .entry(route)
+                .or_insert_with(|| Arc::new(AtomicUsize::new(0)))
+                .clone()
+        };
+        let file = self.file.current();
+        let limits = &file.load_shedding;
+        let global = limits.max_concurrent_requests.or(self.config.global);
+        let per_route = limits
+            .max_concurrent_requests_per_route
+            .get(route)
+            .or_else(|| self.config.per_route.get(route))
+            .cloned();
+        let mut permit = Permit {
+            counters: Vec::with_capacity(2),
+        };
+        if !acquire(&self.global, global) {
+            return None;
+        }
+        permit.counters.push(self.global.clone());
+        if !acquire(&route_counter, per_route) {
+            // Dropping the permit releases the global slot.
+            return None;
+        }
+        permit.counters.push(route_counter);
+        Some(permit)
+    }
+
+    // Returns the number of requests in flight per route.
+    pub fn in_flight(&self) -> BTreeMap<String, usize> {
+        let routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        routes
+            .iter()
+            .map(|(r, c)| ((*r).to_owned(), c.load(Ordering::SeqCst)))
+            .collect()
+    }
+
+    // Renders the in-flight gauges in Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        let name = "sds_in_flight_requests";
+        let _ = writeln!(out, "# HELP {} Requests being processed.", name);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (route, n) in self.in_flight() {
+            let _ = writeln!(out, "{}{{route=\"{}\"}} {}", name, route, n);
+        }
+        out
+    }
+}
+
+fn acquire(counter: &AtomicUsize, limit: Option<usize>) -> bool {
+    let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+    match limit {
+        Some(limit) if n > limit => {
+            counter.fetch_sub(1, Ordering::SeqCst);
+            false
+        }
+        _ => true,
+    }
+}
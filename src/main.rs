@@ -1,14 +1,45 @@
-use log::error;
+use log::{error, info, LevelFilter};
+use std::collections::HashMap;
 use std::env;
 use std::process::exit;
 use std::str;
 
-use sds::storage::StorageImpl;
-use sds::types::Config;
+use sds::admission::AdmissionConfig;
+use sds::cache::CachedStorage;
+use sds::config::{load_file_config, FileConfig, ReloadableConfig};
+#[cfg(feature = "encryption")]
+use sds::encryption::{EncryptedStorage, TagCipher};
+use sds::events::EventConfig;
+use sds::feedback::FeedbackConfig;
+use sds::limiter::LimitConfig;
+use sds::maintenance::MaintenanceConfig;
+use sds::metrics::Metrics;
+use sds::payload_log::{parse_list, PayloadLogConfig};
+use sds::shadow::ShadowStorage;
+use sds::shutdown::{parse_steps as parse_shutdown_steps, ShutdownConfig};
+use sds::statsd::{parse_tags as parse_statsd_tags, StatsdClient, StatsdConfig, StatsdFlavor};
+use sds::storage::{DynStorage, StorageRegistry, StorageSettings};
+use sds::types::{Config, Storage};
+use sds::xds_file::XdsFileConfig;
 
-// rusoto requires AWS_DEFAULT_REGION env.
 fn main() {
-    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(|s| s.as_str()) == Some("gen-envoy-bootstrap") {
+        gen_envoy_bootstrap(&args[2..]);
+        return;
+    }
+
+    let config_file = env::var("CONFIG_FILE").ok();
+    let file_config = config_file.as_ref().map(|path| load_file_config(path));
+    init_logger(&file_config);
+    let file_config = match file_config {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => {
+            error!("{}", e);
+            exit(1);
+        }
+        None => FileConfig::default(),
+    };
 
     let listen_port = {
         let v = fetch_env_var("PORT");
@@ -19,17 +50,328 @@ fn main() {
         let v = fetch_env_var("HOST_TTL");
         parse_uint(&v)
     };
-    let table_name = fetch_env_var("DDB_TABLE");
-    let dynamodb_client = rusoto_dynamodb::DynamoDbClient::new(Default::default());
-
-    let storage = StorageImpl {
-        table_name,
+    let storage_type = file_config
+        .storage
+        .storage_type
+        .clone()
+        .or_else(|| env::var("STORAGE_TYPE").ok())
+        .unwrap_or_else(|| "dynamodb".to_owned());
+    let mut options = file_config.storage.options.clone();
+    if let Ok(table_name) = env::var("DDB_TABLE") {
+        options.entry("table_name".to_owned()).or_insert(table_name);
+    }
+    let clock = sds::clock::system_clock();
+    let settings = StorageSettings {
         ttl,
-        dynamodb_client,
         timeout: get_timeout(),
+        options,
+        clock: clock.clone(),
+    };
+    let registry = StorageRegistry::new();
+    let storage = match registry.create(&storage_type, &settings) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to create {} storage: {}", storage_type, e);
+            exit(1);
+        }
+    };
+    info!("Use {} storage", storage_type);
+    let (metrics, metrics_endpoint) = match build_metrics() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    };
+    let storage = match file_config.storage.secondary {
+        Some(ref secondary) => {
+            let settings = StorageSettings {
+                options: secondary.options.clone(),
+                ..settings.clone()
+            };
+            let shadow = match registry.create(&secondary.storage_type, &settings) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(
+                        "Failed to create {} secondary storage: {}",
+                        secondary.storage_type, e
+                    );
+                    exit(1);
+                }
+            };
+            info!("Shadow {} storage", secondary.storage_type);
+            DynStorage::new(ShadowStorage::new(storage, shadow, metrics.clone()))
+        }
+        None => storage,
+    };
+    if args.iter().any(|a| a == "--init-storage") {
+        info!("Initialize {} storage", storage_type);
+        if let Err(e) = storage.init_schema() {
+            error!("Failed to initialize {} storage: {}", storage_type, e);
+            exit(1);
+        }
+    }
+    // Writes of other sds processes reach the cache only by resyncs, so it's always resynced.
+    let storage_cache = fetch_optional_env("STORAGE_CACHE", false);
+    let resync_interval = if storage_cache {
+        Some(std::time::Duration::from_secs(fetch_optional_env(
+            "RESYNC_INTERVAL_SEC",
+            60,
+        )))
+    } else {
+        None
     };
-    let c = Config { listen_port };
-    sds::server::run(&c, storage);
+    let xds_file = env::var("XDS_FILE_DIR").ok().map(|dir| {
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            error!("Failed to create XDS_FILE_DIR {}: {}", dir, e);
+            exit(1);
+        }
+        XdsFileConfig {
+            dir: dir.into(),
+            interval: std::time::Duration::from_secs(fetch_optional_env(
+                "XDS_FILE_INTERVAL_SEC",
+                5,
+            )),
+        }
+    });
+    #[cfg(feature = "encryption")]
+    let storage = match encrypt_tags(storage) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to set up tag encryption: {}", e);
+            exit(1);
+        }
+    };
+    let feedback = {
+        let d = FeedbackConfig::default();
+        FeedbackConfig {
+            window: std::time::Duration::from_secs(fetch_optional_env(
+                "FEEDBACK_WINDOW_SEC",
+                d.window.as_secs(),
+            )),
+            failure_rate_threshold: fetch_optional_env(
+                "FEEDBACK_FAILURE_RATE",
+                d.failure_rate_threshold,
+            ),
+            min_requests: fetch_optional_env("FEEDBACK_MIN_REQUESTS", d.min_requests),
+            demotion: std::time::Duration::from_secs(fetch_optional_env(
+                "FEEDBACK_DEMOTION_SEC",
+                d.demotion.as_secs(),
+            )),
+        }
+    };
+    let idempotency_window =
+        std::time::Duration::from_secs(fetch_optional_env("IDEMPOTENCY_WINDOW_SEC", 300));
+    let readiness_timeout =
+        std::time::Duration::from_secs(fetch_optional_env("READINESS_TIMEOUT_SEC", 30));
+    let eds_query_concurrency = fetch_optional_env("EDS_QUERY_CONCURRENCY", 8);
+    let reuse_port = fetch_optional_env("REUSE_PORT", false);
+    let events = EventConfig {
+        log_file: env::var("EVENT_LOG_FILE").ok(),
+        webhook_url: env::var("EVENT_WEBHOOK_URL").ok(),
+    };
+    let reaper_interval = env::var("REAPER_INTERVAL_SEC")
+        .ok()
+        .and_then(|v| match v.parse() {
+            Ok(sec) => Some(std::time::Duration::from_secs(sec)),
+            Err(e) => {
+                log::warn!(
+                    "unable to parse REAPER_INTERVAL_SEC: value={}, error={}",
+                    v,
+                    e
+                );
+                None
+            }
+        });
+    let strict_json = fetch_optional_env("STRICT_JSON", false);
+    let legacy_time_fields = fetch_optional_env("LEGACY_TIME_FIELDS", true);
+    let limits = LimitConfig {
+        global: env::var("MAX_CONCURRENT_REQUESTS")
+            .ok()
+            .map(|v| parse_uint(&v)),
+        per_route: parse_route_limits(
+            &env::var("MAX_CONCURRENT_REQUESTS_PER_ROUTE").unwrap_or_default(),
+        ),
+        retry_after: std::time::Duration::from_secs(fetch_optional_env(
+            "LOAD_SHED_RETRY_AFTER_SEC",
+            1,
+        )),
+    };
+    let mut maintenance = MaintenanceConfig::default();
+    maintenance.enabled = fetch_optional_env("MAINTENANCE_MODE", false);
+    if let Ok(v) = env::var("MAINTENANCE_MESSAGE") {
+        maintenance.message = v;
+    }
+    let payload_log = PayloadLogConfig {
+        routes: parse_list(&env::var("PAYLOAD_LOG_ROUTES").unwrap_or_default()),
+        redact_keys: parse_list(&env::var("PAYLOAD_LOG_REDACT_KEYS").unwrap_or_default()),
+        max_bytes: fetch_optional_env(
+            "PAYLOAD_LOG_MAX_BYTES",
+            PayloadLogConfig::default().max_bytes,
+        ),
+    };
+    let grpc_health_port = env::var("GRPC_HEALTH_PORT").ok().map(|v| parse_uint(&v));
+    let shutdown = {
+        let default = ShutdownConfig::default();
+        let steps = match env::var("SHUTDOWN_STEPS") {
+            Ok(v) => match parse_shutdown_steps(&v) {
+                Ok(steps) => steps,
+                Err(e) => {
+                    error!("{}", e);
+                    exit(1);
+                }
+            },
+            Err(_) => default.steps,
+        };
+        ShutdownConfig {
+            steps,
+            delay: std::time::Duration::from_secs(fetch_optional_env("SHUTDOWN_DELAY_SEC", 0)),
+            webhooks: env::var("SHUTDOWN_WEBHOOKS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            webhook_timeout: default.webhook_timeout,
+            drain_timeout: std::time::Duration::from_secs(fetch_optional_env(
+                "SHUTDOWN_DRAIN_TIMEOUT_SEC",
+                30,
+            )),
+        }
+    };
+    let admission = env::var("ADMISSION_WEBHOOK_URL")
+        .ok()
+        .map(|url| AdmissionConfig {
+            url,
+            timeout: std::time::Duration::from_millis(fetch_optional_env(
+                "ADMISSION_TIMEOUT_MS",
+                1000,
+            )),
+            cache_ttl: std::time::Duration::from_secs(fetch_optional_env(
+                "ADMISSION_CACHE_SEC",
+                10,
+            )),
+            fail_open: fetch_optional_env("ADMISSION_FAIL_OPEN", true),
+        });
+    let c = Config {
+        listen_port,
+        file: ReloadableConfig::new(config_file, file_config),
+        feedback,
+        idempotency_window,
+        readiness_timeout,
+        eds_query_concurrency,
+        reuse_port,
+        events,
+        reaper_interval,
+        resync_interval,
+        xds_file,
+        strict_json,
+        sds_v1_sunset: env::var("SDS_V1_SUNSET").ok(),
+        limits,
+        clock,
+        legacy_time_fields,
+        metrics,
+        metrics_endpoint,
+        maintenance,
+        payload_log,
+        grpc_health_port,
+        shutdown,
+        region: env::var("SDS_REGION").ok(),
+        admission,
+    };
+    if storage_cache {
+        info!("Cache storage in memory");
+        let cached = CachedStorage::new(storage, c.clock.clone());
+        sds::server::run(&c, DynStorage::new(cached));
+    } else {
+        sds::server::run(&c, storage);
+    }
+}
+
+// Returns the metrics and whether /metrics is served, following METRICS_SINKS.
+fn build_metrics() -> Result<(Metrics, bool), String> {
+    let sinks = env::var("METRICS_SINKS").unwrap_or_else(|_| "prometheus".to_owned());
+    let mut prometheus = false;
+    let mut statsd = false;
+    for sink in sinks.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        match sink {
+            "prometheus" => prometheus = true,
+            "statsd" => statsd = true,
+            _ => return Err(format!("Unknown sink in METRICS_SINKS: {}", sink)),
+        }
+    }
+    let metrics = Metrics::new();
+    if !statsd {
+        return Ok((metrics, prometheus));
+    }
+    let flavor = match env::var("STATSD_FLAVOR").as_ref().map(|s| s.as_str()) {
+        Ok("dogstatsd") | Err(_) => StatsdFlavor::DogStatsd,
+        Ok("statsd") => StatsdFlavor::Statsd,
+        Ok(v) => return Err(format!("Unknown STATSD_FLAVOR: {}", v)),
+    };
+    let config = StatsdConfig {
+        address: env::var("STATSD_ADDRESS").unwrap_or_else(|_| "127.0.0.1:8125".to_owned()),
+        prefix: env::var("STATSD_PREFIX").unwrap_or_else(|_| "sds".to_owned()),
+        tags: parse_statsd_tags(&env::var("STATSD_TAGS").unwrap_or_default())?,
+        flavor,
+    };
+    info!("Send metrics to statsd: address={}", config.address);
+    let client =
+        StatsdClient::new(config).map_err(|e| format!("Failed to set up statsd: {}", e))?;
+    Ok((metrics.with_statsd(client), prometheus))
+}
+
+fn gen_envoy_bootstrap(args: &[String]) {
+    match sds::bootstrap::parse_args(args) {
+        Ok(opts) => print!("{}", sds::bootstrap::render(&opts)),
+        Err(e) => {
+            eprintln!("{}\n{}", e, sds::bootstrap::USAGE);
+            exit(2);
+        }
+    }
+}
+
+// log_level of the config file overrides the global level of RUST_LOG. The logger accepts any
+// level in that case so that reloading log_level can raise the verbosity too.
+fn init_logger(file_config: &Option<Result<FileConfig, String>>) {
+    let mut builder = env_logger::Builder::from_default_env();
+    let level = match file_config {
+        Some(Ok(c)) => c.parse_log_level().unwrap_or(None),
+        _ => None,
+    };
+    match level {
+        Some(level) => {
+            builder.filter_level(LevelFilter::Trace).init();
+            log::set_max_level(level);
+        }
+        None => builder.init(),
+    }
+}
+
+// Wraps the storage with tag encryption when a key is given by TAG_ENCRYPTION_KEY, or by
+// TAG_ENCRYPTION_KMS_KEY as a data key encrypted by KMS.
+#[cfg(feature = "encryption")]
+fn encrypt_tags(storage: DynStorage) -> Result<DynStorage, String> {
+    let cipher = if let Ok(key) = env::var("TAG_ENCRYPTION_KEY") {
+        TagCipher::from_base64(&key)?
+    } else if let Ok(encrypted) = env::var("TAG_ENCRYPTION_KMS_KEY") {
+        kms_tag_cipher(&encrypted)?
+    } else {
+        return Ok(storage);
+    };
+    info!("Encrypt tag values in storage");
+    Ok(DynStorage::new(EncryptedStorage::new(storage, cipher)))
+}
+
+#[cfg(feature = "kms")]
+fn kms_tag_cipher(encrypted: &str) -> Result<TagCipher, String> {
+    let key = sds::encryption::decrypt_data_key(encrypted)?;
+    TagCipher::new(&key)
+}
+
+#[cfg(all(feature = "encryption", not(feature = "kms")))]
+fn kms_tag_cipher(_: &str) -> Result<TagCipher, String> {
+    Err("TAG_ENCRYPTION_KMS_KEY requires sds built with kms feature".to_owned())
 }
 
 fn fetch_env_var(k: &'static str) -> String {
@@ -55,6 +397,41 @@ where
     }
 }
 
+fn fetch_optional_env<T>(k: &'static str, default: T) -> T
+where
+    T: str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(k) {
+        Ok(v) => match v.parse() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                log::warn!("unable to parse {}: value={}, error={}", k, v, e);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+// Parses limits like "eds=100,registration=50".
+fn parse_route_limits(s: &str) -> HashMap<String, usize> {
+    let mut limits = HashMap::new();
+    for pair in s.split(',').filter(|p| !p.trim().is_empty()) {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(route), Some(limit)) => {
+                limits.insert(route.trim().to_owned(), parse_uint(limit.trim()));
+            }
+            _ => {
+                error!("env var is invalid: value={}", s);
+                exit(1);
+            }
+        }
+    }
+    limits
+}
+
 fn get_timeout() -> std::time::Duration {
     const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
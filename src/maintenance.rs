@@ -0,0 +1,78 @@
+use std::sync::{Arc, RwLock};
+
+use serde_derive::{Deserialize, Serialize};
+
+const DEFAULT_MESSAGE: &str = "sds is under maintenance, try again later";
+
+#[derive(Debug, Clone)]
+pub struct MaintenanceConfig {
+    // Starts in maintenance mode.
+    pub enabled: bool,
+    // Sent in 503 responses when the toggle doesn't give one.
+    pub message: String,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            enabled: false,
+            message: DEFAULT_MESSAGE.to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MaintenanceState {
+    pub enabled: bool,
+    pub message: String,
+}
+
+// Toggle rejecting writes to the storage during maintenance windows. It's local to each sds
+// process.
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    default_message: String,
+    state: Arc<RwLock<MaintenanceState>>,
+}
+
+impl Maintenance {
+    pub fn new(config: &MaintenanceConfig) -> Self {
+        Maintenance {
+            default_message: config.message.to_owned(),
+            state: Arc::new(RwLock::new(MaintenanceState {
+                enabled: config.enabled,
+                message: config.message.to_owned(),
+            })),
+        }
+    }
+
+    pub fn enable(&self, message: Option<String>) {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        *state = MaintenanceState {
+            enabled: true,
+            message: message.unwrap_or_else(|| self.default_message.to_owned()),
+        };
+    }
+
+    pub fn disable(&self) {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        *state = MaintenanceState {
+            enabled: false,
+            message: self.default_message.to_owned(),
+        };
+    }
+
+    pub fn state(&self) -> MaintenanceState {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // Returns the message to respond while in maintenance.
+    pub fn check(&self) -> Option<String> {
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+        if state.enabled {
+            Some(state.message.to_owned())
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_derive::Serialize;
+
+use super::statsd::StatsdClient;
+use super::types::Drift;
+
+// Upper bounds of the time-to-expiry buckets in seconds.
+pub const TIME_TO_EXPIRY_BUCKETS: &[u64] = &[1, 5, 10, 30, 60, 120, 300, 600];
+// Upper bounds of the write lock wait buckets in milliseconds.
+pub const WRITE_LOCK_WAIT_BUCKETS: &[u64] = &[1, 5, 10, 50, 100, 500, 1000, 5000];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    // Per bucket counts, the last one is for values over all bounds.
+    counts: Vec<u64>,
+    sum: u64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[u64]) -> Self {
+        Histogram {
+            counts: vec![0; bounds.len() + 1],
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, bounds: &[u64], v: u64) {
+        let i = bounds.iter().position(|b| v <= *b).unwrap_or(bounds.len());
+        self.counts[i] += 1;
+        self.sum = self.sum.saturating_add(v);
+        self.count += 1;
+    }
+
+    // Returns (le, cumulative count) pairs like Prometheus histograms.
+    fn cumulative(&self, bounds: &[u64]) -> Vec<Bucket> {
+        let mut total = 0;
+        let mut buckets = Vec::with_capacity(self.counts.len());
+        for (i, c) in self.counts.iter().enumerate() {
+            total += c;
+            let le = match bounds.get(i) {
+                Some(b) => b.to_string(),
+                None => "+Inf".to_owned(),
+            };
+            buckets.push(Bucket { le, count: total });
+        }
+        buckets
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Bucket {
+    pub le: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistogramStats {
+    pub count: u64,
+    pub sum: u64,
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ServiceStats {
+    // Seconds left before expiry when hosts checked in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_to_expiry_seconds: Option<HistogramStats>,
+    // Number of responses truncated to max_hosts.
+    pub truncated_responses: u64,
+    // Discrepancies between the cache and the storage repaired by resyncs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_drift: Option<DriftStats>,
+    // Reads whose result differed between the primary and the secondary storage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_mismatches: Option<u64>,
+    // Registrations rejected by quotas per quota name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_rejections: Option<BTreeMap<String, u64>>,
+    // Milliseconds writes waited for the write lock of the service.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_lock_wait_ms: Option<HistogramStats>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DriftStats {
+    pub missing: u64,
+    pub extra: u64,
+    pub stale: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ShadowErrors {
+    pub writes: u64,
+    pub reads: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub services: BTreeMap<String, ServiceStats>,
+    // Failed calls to the secondary storage, present only in shadow mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_errors: Option<ShadowErrors>,
+}
+
+// Metrics local to this process, exposed by /metrics and /v1/stats.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    time_to_expiry: Arc<Mutex<HashMap<String, Histogram>>>,
+    truncated_responses: Arc<Mutex<HashMap<String, u64>>>,
+    cache_drift: Arc<Mutex<HashMap<String, DriftStats>>>,
+    shadow_mismatches: Arc<Mutex<HashMap<String, u64>>>,
+    shadow_errors: Arc<Mutex<Option<ShadowErrors>>>,
+    quota_rejections: Arc<Mutex<HashMap<String, BTreeMap<String, u64>>>>,
+    write_lock_wait: Arc<Mutex<HashMap<String, Histogram>>>,
+    // Also sends metrics to statsd as they're recorded when set.
+    statsd: Option<StatsdClient>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn with_statsd(mut self, statsd: StatsdClient) -> Self {
+        self.statsd = Some(statsd);
+        self
+    }
+
+    // Sent to statsd only, Prometheus users get request metrics from their proxies.
+    pub fn observe_request(&self, route: &str, status: u16, elapsed: Duration) {
+        if let Some(ref s) = self.statsd {
+            let status = status.to_string();
+            let labels = [("route", route), ("status", status.as_str())];
+            s.count("requests", 1, &labels);
+            s.timing("request.duration", elapsed, &labels);
+        }
+    }
+
+    // Records how many seconds the host had left before expiry when it checked in. Values close
+    // to zero mean its agent heartbeats too close to the TTL edge.
+    pub fn observe_time_to_expiry(&self, service: &str, seconds: u64) {
+        let mut m = self
+            .time_to_expiry
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        m.entry(service.to_owned())
+            .or_insert_with(|| Histogram::new(TIME_TO_EXPIRY_BUCKETS))
+            .observe(TIME_TO_EXPIRY_BUCKETS, seconds);
+        if let Some(ref s) = self.statsd {
+            s.timing(
+                "host.time_to_expiry",
+                Duration::from_secs(seconds),
+                &[("service", service)],
+            );
+        }
+    }
+
+    pub fn inc_truncated_responses(&self, service: &str) {
+        let mut m = self
+            .truncated_responses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *m.entry(service.to_owned()).or_insert(0) += 1;
+        if let Some(ref s) = self.statsd {
+            s.count("truncated_responses", 1, &[("service", service)]);
+        }
+    }
+
+    pub fn record_drift(&self, drift: &Drift) {
+        let mut m = self.cache_drift.lock().unwrap_or_else(|e| e.into_inner());
+        let s = m
+            .entry(drift.service.to_owned())
+            .or_insert_with(DriftStats::default);
+        s.missing += drift.missing;
+        s.extra += drift.extra;
+        s.stale += drift.stale;
+        if let Some(ref s) = self.statsd {
+            for (kind, v) in &[
+                ("missing", drift.missing),
+                ("extra", drift.extra),
+                ("stale", drift.stale),
+            ] {
+                if *v > 0 {
+                    s.count(
+                        "cache_drift",
+                        *v,
+                        &[("service", drift.service.as_str()), ("kind", kind)],
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn inc_shadow_mismatches(&self, service: &str) {
+        let mut m = self
+            .shadow_mismatches
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *m.entry(service.to_owned()).or_insert(0) += 1;
+        if let Some(ref s) = self.statsd {
+            s.count("shadow_mismatches", 1, &[("service", service)]);
+        }
+    }
+
+    pub fn inc_shadow_write_errors(&self) {
+        let mut m = self.shadow_errors.lock().unwrap_or_else(|e| e.into_inner());
+        m.get_or_insert_with(ShadowErrors::default).writes += 1;
+        if let Some(ref s) = self.statsd {
+            s.count("shadow_errors", 1, &[("op", "write")]);
+        }
+    }
+
+    pub fn inc_shadow_read_errors(&self) {
+        let mut m = self.shadow_errors.lock().unwrap_or_else(|e| e.into_inner());
+        m.get_or_insert_with(ShadowErrors::default).reads += 1;
+        if let Some(ref s) = self.statsd {
+            s.count("shadow_errors", 1, &[("op", "read")]);
+        }
+    }
+
+    pub fn inc_quota_rejections(&self, service: &str, quota: &str) {
+        let mut m = self
+            .quota_rejections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *m.entry(service.to_owned())
+            .or_insert_with(BTreeMap::new)
+            .entry(quota.to_owned())
+            .or_insert(0) += 1;
+        if let Some(ref s) = self.statsd {
+            s.count(
+                "quota_rejections",
+                1,
+                &[("service", service), ("quota", quota)],
+            );
+        }
+    }
+
+    pub fn observe_write_lock_wait(&self, service: &str, elapsed: Duration) {
+        let ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+        let mut m = self
+            .write_lock_wait
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        m.entry(service.to_owned())
+            .or_insert_with(|| Histogram::new(WRITE_LOCK_WAIT_BUCKETS))
+            .observe(WRITE_LOCK_WAIT_BUCKETS, ms);
+        if let Some(ref s) = self.statsd {
+            s.timing("write_lock.wait", elapsed, &[("service", service)]);
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let time_to_expiry = self
+            .time_to_expiry
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let truncated = self
+            .truncated_responses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let drift = self.cache_drift.lock().unwrap_or_else(|e| e.into_inner());
+        let mismatches = self
+            .shadow_mismatches
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let rejections = self
+            .quota_rejections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let lock_wait = self
+            .write_lock_wait
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let services = time_to_expiry
+            .keys()
+            .chain(truncated.keys())
+            .chain(drift.keys())
+            .chain(mismatches.keys())
+            .chain(rejections.keys())
+            .chain(lock_wait.keys())
+            .map(|service| {
+                let stats = ServiceStats {
+                    time_to_expiry_seconds: time_to_expiry.get(service).map(|h| HistogramStats {
+                        count: h.count,
+                        sum: h.sum,
+                        buckets: h.cumulative(TIME_TO_EXPIRY_BUCKETS),
+                    }),
+                    truncated_responses: truncated.get(service).cloned().unwrap_or(0),
+                    cache_drift: drift.get(service).cloned(),
+                    shadow_mismatches: mismatches.get(service).cloned(),
+                    quota_rejections: rejections.get(service).cloned(),
+                    write_lock_wait_ms: lock_wait.get(service).map(|h| HistogramStats {
+                        count: h.count,
+                        sum: h.sum,
+                        buckets: h.cumulative(WRITE_LOCK_WAIT_BUCKETS),
+                    }),
+                };
+                (service.to_owned(), stats)
+            })
+            .collect();
+        let shadow_errors = self
+            .shadow_errors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        Stats {
+            services,
+            shadow_errors,
+        }
+    }
+
+    // Renders the metrics in Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let stats = self.stats();
+        let mut out = String::new();
+        let name = "sds_host_time_to_expiry_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Seconds left before expiry when hosts checked in.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (service, s) in &stats.services {
+            let h = match s.time_to_expiry_seconds {
+                Some(ref v) => v,
+                None => continue,
+            };
+            let service = escape_label(service);
+            for b in &h.buckets {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{service=\"{}\",le=\"{}\"}} {}",
+                    name, service, b.le, b.count
+                );
+            }
+            let _ = writeln!(out, "{}_sum{{service=\"{}\"}} {}", name, service, h.sum);
+            let _ = writeln!(out, "{}_count{{service=\"{}\"}} {}", name, service, h.count);
+        }
+
+        let name = "sds_truncated_responses_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Responses truncated to max_hosts of the service.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (service, s) in &stats.services {
+            let _ = writeln!(
+                out,
+                "{}{{service=\"{}\"}} {}",
+                name,
+                escape_label(service),
+                s.truncated_responses
+            );
+        }
+
+        let name = "sds_cache_drift_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Hosts repaired by resyncs of the cache with the storage.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (service, s) in &stats.services {
+            let d = match s.cache_drift {
+                Some(ref v) => v,
+                None => continue,
+            };
+            let service = escape_label(service);
+            for (kind, v) in &[
+                ("missing", d.missing),
+                ("extra", d.extra),
+                ("stale", d.stale),
+            ] {
+                let _ = writeln!(
+                    out,
+                    "{}{{service=\"{}\",kind=\"{}\"}} {}",
+                    name, service, kind, v
+                );
+            }
+        }
+
+        let name = "sds_shadow_mismatches_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Reads differed between the primary and the secondary storage.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (service, s) in &stats.services {
+            if let Some(v) = s.shadow_mismatches {
+                let _ = writeln!(
+                    out,
+                    "{}{{service=\"{}\"}} {}",
+                    name,
+                    escape_label(service),
+                    v
+                );
+            }
+        }
+
+        let name = "sds_quota_rejections_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Registrations rejected by quotas of the namespace.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (service, s) in &stats.services {
+            let r = match s.quota_rejections {
+                Some(ref v) => v,
+                None => continue,
+            };
+            let service = escape_label(service);
+            for (quota, v) in r {
+                let _ = writeln!(
+                    out,
+                    "{}{{service=\"{}\",quota=\"{}\"}} {}",
+                    name, service, quota, v
+                );
+            }
+        }
+
+        let name = "sds_write_lock_wait_milliseconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Milliseconds writes waited for the write lock of the service.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (service, s) in &stats.services {
+            let h = match s.write_lock_wait_ms {
+                Some(ref v) => v,
+                None => continue,
+            };
+            let service = escape_label(service);
+            for b in &h.buckets {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{service=\"{}\",le=\"{}\"}} {}",
+                    name, service, b.le, b.count
+                );
+            }
+            let _ = writeln!(out, "{}_sum{{service=\"{}\"}} {}", name, service, h.sum);
+            let _ = writeln!(out, "{}_count{{service=\"{}\"}} {}", name, service, h.count);
+        }
+
+        if let Some(ref e) = stats.shadow_errors {
+            let name = "sds_shadow_errors_total";
+            let _ = writeln!(
+                out,
+                "# HELP {} Failed calls to the secondary storage.",
+                name
+            );
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{}{{op=\"write\"}} {}", name, e.writes);
+            let _ = writeln!(out, "{}{{op=\"read\"}} {}", name, e.reads);
+        }
+        out
+    }
+}
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
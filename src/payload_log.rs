@@ -0,0 +1,136 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+const REDACTED: &str = "[REDACTED]";
+const DEFAULT_MAX_BYTES: usize = 4096;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PayloadLogConfig {
+    // Route names whose request and response bodies are logged, e.g. "registration" or "eds".
+    // Logging is off when empty.
+    pub routes: BTreeSet<String>,
+    // JSON object keys whose values are replaced at any depth, e.g. tag keys like "instance_id".
+    pub redact_keys: BTreeSet<String>,
+    // Bodies are truncated to this size in the log.
+    pub max_bytes: usize,
+}
+
+impl Default for PayloadLogConfig {
+    fn default() -> Self {
+        PayloadLogConfig {
+            routes: BTreeSet::new(),
+            redact_keys: BTreeSet::new(),
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+// Logs bodies for debugging client serialization bugs. The config can be swapped at runtime by the
+// admin API, and is local to each sds process.
+#[derive(Debug, Clone)]
+pub struct PayloadLogger {
+    config: Arc<RwLock<Arc<PayloadLogConfig>>>,
+}
+
+impl PayloadLogger {
+    pub fn new(config: PayloadLogConfig) -> Self {
+        PayloadLogger {
+            config: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    pub fn config(&self) -> Arc<PayloadLogConfig> {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn set_config(&self, config: PayloadLogConfig) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+
+    pub fn is_enabled(&self, route: &str) -> bool {
+        self.config().routes.contains(route)
+    }
+
+    pub fn log_request(&self, route: &str, method: &str, uri: &str, body: &[u8]) {
+        info!(
+            "Payload: route={}, direction=request, method={}, uri={}, body={}",
+            route,
+            method,
+            uri,
+            render(&self.config(), body)
+        );
+    }
+
+    pub fn log_response(&self, route: &str, status: u16, body: &[u8]) {
+        info!(
+            "Payload: route={}, direction=response, status={}, body={}",
+            route,
+            status,
+            render(&self.config(), body)
+        );
+    }
+}
+
+// Redacts JSON bodies and truncates the result to max_bytes. Bodies which aren't JSON are only
+// truncated.
+pub fn render(config: &PayloadLogConfig, body: &[u8]) -> String {
+    let text = match serde_json::from_slice::<Value>(body) {
+        Ok(mut v) => {
+            redact(&mut v, &config.redact_keys);
+            v.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    };
+    truncate(text, config.max_bytes)
+}
+
+fn redact(v: &mut Value, keys: &BTreeSet<String>) {
+    match v {
+        Value::Object(m) => {
+            for (k, v) in m.iter_mut() {
+                if keys.contains(k) {
+                    *v = Value::String(REDACTED.to_owned());
+                } else {
+                    redact(v, keys);
+                }
+            }
+        }
+        Value::Array(a) => {
+            for v in a {
+                redact(v, keys);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn truncate(mut s: String, max_bytes: usize) -> String {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let total = s.len();
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+    s.push_str(&format!("...({} bytes)", total));
+    s
+}
+
+// Parses comma separated values of env vars like "registration,eds".
+pub fn parse_list(s: &str) -> BTreeSet<String> {
+    s.split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_owned())
+        .collect()
+}
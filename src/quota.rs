@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+// Quotas per namespace, evaluated on registration. The namespace of a service is the part of its
+// name before the first separator, e.g. "payments" of "payments.api". Services without the
+// separator belong to the "" namespace.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub namespace_separator: String,
+    // Applied to namespaces not listed in `namespaces`, and to limits missing there.
+    pub default: QuotaLimits,
+    pub namespaces: HashMap<String, QuotaLimits>,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            namespace_separator: ".".to_owned(),
+            default: QuotaLimits::default(),
+            namespaces: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct QuotaLimits {
+    // Maximum number of services with live hosts in the namespace.
+    pub max_services: Option<u64>,
+    // Maximum number of hosts of each service in the namespace.
+    pub max_hosts_per_service: Option<u64>,
+    // Maximum number of registrations including check-ins per minute in the namespace, counted
+    // by each sds process.
+    pub max_registrations_per_min: Option<u64>,
+}
+
+impl QuotaConfig {
+    pub fn namespace<'a>(&self, service: &'a str) -> &'a str {
+        if self.namespace_separator.is_empty() {
+            return "";
+        }
+        match service.find(self.namespace_separator.as_str()) {
+            Some(i) => &service[..i],
+            None => "",
+        }
+    }
+
+    // Limits of the namespace, falling back to the default ones per limit.
+    pub fn limits(&self, namespace: &str) -> QuotaLimits {
+        let d = &self.default;
+        match self.namespaces.get(namespace) {
+            Some(l) => QuotaLimits {
+                max_services: l.max_services.or(d.max_services),
+                max_hosts_per_service: l.max_hosts_per_service.or(d.max_hosts_per_service),
+                max_registrations_per_min: l
+                    .max_registrations_per_min
+                    .or(d.max_registrations_per_min),
+            },
+            None => d.clone(),
+        }
+    }
+}
+
+// Details of an exceeded quota sent in error responses.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QuotaViolation {
+    pub namespace: String,
+    // "max_services", "max_hosts_per_service" or "max_registrations_per_min"
+    pub quota: &'static str,
+    pub limit: u64,
+    // Usage when the request was evaluated, missing for the rate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token buckets limiting registration rates per namespace. The burst is a minute worth of
+// registrations.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter::default()
+    }
+
+    // Takes a token of the namespace, or returns how long to wait for the next one.
+    pub fn try_acquire(&self, namespace: &str, per_min: u64, now: Instant) -> Result<(), Duration> {
+        if per_min == 0 {
+            return Err(Duration::from_secs(60));
+        }
+        let capacity = per_min as f64;
+        let per_sec = capacity / 60.0;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let b = buckets.entry(namespace.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        if now > b.updated {
+            let elapsed = now.duration_since(b.updated);
+            let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+            b.tokens = (b.tokens + secs * per_sec).min(capacity);
+            b.updated = now;
+        }
+        if b.tokens >= 1.0 {
+            b.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait_ms = ((1.0 - b.tokens) / per_sec * 1000.0).ceil() as u64;
+            Err(Duration::from_millis(wait_ms))
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use super::types::{SchemaCheck, Storage};
+
+const WARM_UP_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const SCHEMA_CHECK_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    // Waiting for the storage to complete its initial sync.
+    WarmingUp,
+    // The storage schema is incompatible for the reason, e.g. the table is missing or was created
+    // by a newer sds. Never marked ready by the warm-up timeout.
+    Incompatible(String),
+    Ready,
+    // Shutting down, kept until the process exits.
+    Draining,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::WarmingUp => write!(f, "warming up"),
+            State::Incompatible(e) => write!(f, "incompatible storage: {}", e),
+            State::Ready => write!(f, "ready"),
+            State::Draining => write!(f, "draining"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    state: Arc<RwLock<State>>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Readiness {
+            state: Arc::new(RwLock::new(State::WarmingUp)),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state() == State::Ready
+    }
+
+    // Moves to `to` only when the current state is `from`, and returns whether it moved.
+    pub fn transition(&self, from: State, to: State) -> bool {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        if *state != from {
+            return false;
+        }
+        info!("Readiness state changed: from={}, to={}", from, to);
+        *state = to;
+        true
+    }
+
+    pub fn drain(&self) {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        info!(
+            "Readiness state changed: from={}, to={}",
+            state,
+            State::Draining
+        );
+        *state = State::Draining;
+    }
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Readiness::new()
+    }
+}
+
+// Checks the storage schema and runs the storage's warm-up in background until they succeed,
+// then marks the readiness ready. The readiness is marked ready anyway once the timeout passes
+// after the schema check so that a broken warm-up doesn't keep the instance out of service
+// forever, but an incompatible schema does.
+pub fn start_warm_up<S: Storage>(readiness: Readiness, storage: S, timeout: Duration) {
+    thread::spawn(move || {
+        let mut state = State::WarmingUp;
+        loop {
+            match storage.check_schema() {
+                Ok(SchemaCheck::Compatible) => break,
+                Ok(SchemaCheck::Incompatible(e)) => {
+                    let next = State::Incompatible(e.clone());
+                    if next != state {
+                        error!("Storage schema is incompatible, not serving: {}", e);
+                        if !readiness.transition(state, next.clone()) {
+                            return;
+                        }
+                        state = next;
+                    }
+                }
+                // e.g. throttling or a timeout, the schema isn't known yet.
+                Err(e) => warn!("Storage schema check failed, retrying: {}", e),
+            }
+            thread::sleep(SCHEMA_CHECK_RETRY_INTERVAL);
+        }
+        if state != State::WarmingUp && !readiness.transition(state, State::WarmingUp) {
+            return;
+        }
+
+        let r = readiness.clone();
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            if r.transition(State::WarmingUp, State::Ready) {
+                warn!("Warm-up didn't complete in {:?}, serve anyway", timeout);
+            }
+        });
+
+        let started_at = Instant::now();
+        loop {
+            match storage.warm_up() {
+                Ok(()) => {
+                    info!("Warm-up completed in {:?}", started_at.elapsed());
+                    readiness.transition(State::WarmingUp, State::Ready);
+                    return;
+                }
+                Err(e) => warn!("Warm-up failed, retrying: {}", e),
+            }
+            if readiness.state() != State::WarmingUp {
+                return;
+            }
+            thread::sleep(WARM_UP_RETRY_INTERVAL);
+        }
+    });
+}
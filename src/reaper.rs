@@ -0,0 +1,47 @@
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use super::clock::SharedClock;
+use super::events::{EventEmitter, EventKind};
+use super::maintenance::Maintenance;
+use super::types::Storage;
+
+// Removes expired hosts from the storage every interval, emitting an Expire event with each
+// removed record. Reaping pauses in maintenance mode like other writes.
+pub fn start_reaper<S: Storage>(
+    storage: S,
+    events: EventEmitter,
+    interval: Duration,
+    clock: SharedClock,
+    maintenance: Maintenance,
+) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if maintenance.check().is_some() {
+            info!("Skip reaping in maintenance mode");
+            continue;
+        }
+        let now = match clock.epoch_secs() {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("{}, skip reaping", e);
+                continue;
+            }
+        };
+        match storage.reap_expired(now) {
+            Ok(hosts) => {
+                for h in hosts {
+                    info!(
+                        "Reaped expired host: service={}, ip={}, port={}, expire_time={}",
+                        h.service, h.ip_address, h.port, h.expire_time
+                    );
+                    let service = h.service.to_owned();
+                    events.emit(EventKind::Expire, &service, h);
+                }
+            }
+            Err(e) => warn!("Failed to reap expired hosts: {}", e),
+        }
+    });
+}
@@ -0,0 +1,67 @@
+use serde_derive::Deserialize;
+
+use super::types::Host;
+use super::v2xds::LocalityLbEndpoints;
+
+// How responses of a service favor hosts in the region of the client, for backends replicated
+// across regions.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionPinning {
+    Off,
+    // Serves hosts of the region first. In v2 EDS, localities of other regions get lower
+    // priorities than any locality of the region.
+    Prefer,
+    // Serves only hosts of the region, or every host when the region has none.
+    Restrict,
+}
+
+impl Default for RegionPinning {
+    fn default() -> Self {
+        RegionPinning::Off
+    }
+}
+
+pub fn pin_hosts(hosts: Vec<Host>, region: &str, pinning: RegionPinning) -> Vec<Host> {
+    match pinning {
+        RegionPinning::Off => hosts,
+        RegionPinning::Prefer => {
+            let (mut local, others): (Vec<Host>, Vec<Host>) =
+                hosts.into_iter().partition(|h| h.tags.region == region);
+            local.extend(others);
+            local
+        }
+        RegionPinning::Restrict => {
+            if hosts.iter().any(|h| h.tags.region == region) {
+                hosts
+                    .into_iter()
+                    .filter(|h| h.tags.region == region)
+                    .collect()
+            } else {
+                hosts
+            }
+        }
+    }
+}
+
+// Moves localities of other regions below every locality of the region, keeping their relative
+// priorities.
+pub fn demote_other_regions(endpoints: &mut [LocalityLbEndpoints], region: &str) {
+    if !endpoints.iter().any(|e| e.locality.region == region) {
+        return;
+    }
+    let offset = endpoints
+        .iter()
+        .filter(|e| e.locality.region == region)
+        .map(|e| e.priority.unwrap_or(0))
+        .max()
+        .unwrap_or(0)
+        + 1;
+    for e in endpoints.iter_mut() {
+        if e.locality.region != region {
+            e.priority = Some(e.priority.unwrap_or(0) + offset);
+        } else if e.priority.is_none() {
+            e.priority = Some(0);
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::str;
+use std::time::{Duration, Instant};
+
+use hyper::Uri;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use serde_json::{Map, Value};
+
+use super::payload_log::PayloadLogConfig;
+use url::form_urlencoded;
+
+use super::types::Tag;
+use super::v2xds::DiscoveryRequest;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegistrationParam {
+    pub ip: String,
+    pub port: u16,
+    pub revision: String,
+    pub tags: Tag,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeedbackParam {
+    pub ip: String,
+    pub port: u16,
+    #[serde(default)]
+    pub requests: u64,
+    pub failures: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AliasParam {
+    // The service the alias points to.
+    pub service: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MaintenanceParam {
+    pub enabled: bool,
+    // Message of 503 responses, the configured one is used when missing.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+const REGISTRATION_PARAM_FIELDS: &[&str] = &["ip", "port", "revision", "tags"];
+const REQUIRED_TAGS: &[&str] = &["az", "region", "instance_id", "canary"];
+const FEEDBACK_PARAM_FIELDS: &[&str] = &["ip", "port", "requests", "failures"];
+
+// Unknown fields are rejected in strict mode and ignored otherwise. Tags accept arbitrary keys
+// in both modes.
+pub fn parse_registration_param(body: &[u8], strict: bool) -> Result<RegistrationParam, String> {
+    if strict {
+        parse_strict_json_body(body, REGISTRATION_PARAM_FIELDS)
+    } else {
+        parse_json_body(body)
+    }
+}
+
+// Envoy sends fields sds doesn't know, so DiscoveryRequest is always parsed leniently.
+pub fn parse_discovery_request(body: &[u8]) -> Result<DiscoveryRequest, String> {
+    parse_json_body(body)
+}
+
+pub fn parse_alias_param(body: &[u8]) -> Result<AliasParam, String> {
+    let param: AliasParam = parse_json_body(body)?;
+    if param.service.is_empty() || param.service.contains('/') {
+        return Err(format!("Invalid service: {:?}", param.service));
+    }
+    Ok(param)
+}
+
+pub fn parse_maintenance_param(body: &[u8]) -> Result<MaintenanceParam, String> {
+    parse_json_body(body)
+}
+
+// Parses a partial tag map of PATCH requests. Null values remove the tag.
+pub fn parse_tag_patch(body: &[u8]) -> Result<Map<String, Value>, String> {
+    match parse_json_body(body)? {
+        Value::Object(m) => Ok(m),
+        _ => Err("Tag patch must be a JSON object".to_owned()),
+    }
+}
+
+// Merges the patch into the tags. Required tags can be changed but not removed.
+pub fn apply_tag_patch(tags: &Tag, patch: &Map<String, Value>) -> Result<Tag, String> {
+    let mut merged = match serde_json::to_value(tags) {
+        Ok(Value::Object(m)) => m,
+        _ => return Err("Failed to serialize tags".to_owned()),
+    };
+    for (k, v) in patch {
+        if v.is_null() {
+            if REQUIRED_TAGS.contains(&k.as_str()) {
+                return Err(format!("Required tag can't be removed: {}", k));
+            }
+            merged.remove(k);
+        } else {
+            merged.insert(k.clone(), v.clone());
+        }
+    }
+    serde_json::from_value(Value::Object(merged)).map_err(|e| format!("Invalid tags: {}", e))
+}
+
+pub fn parse_payload_log_config(body: &[u8]) -> Result<PayloadLogConfig, String> {
+    parse_json_body(body)
+}
+
+pub fn parse_feedback_param(body: &[u8], strict: bool) -> Result<FeedbackParam, String> {
+    if strict {
+        parse_strict_json_body(body, FEEDBACK_PARAM_FIELDS)
+    } else {
+        parse_json_body(body)
+    }
+}
+
+fn parse_json_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, String> {
+    let body = match str::from_utf8(body) {
+        Ok(v) => v,
+        Err(_) => return Err("Invalid UTF-8 string".to_owned()),
+    };
+    serde_json::from_str(body).map_err(|e| format!("Invalid JSON string: {}", e))
+}
+
+// Lists all unexpected top-level fields instead of only the first one like deny_unknown_fields.
+fn parse_strict_json_body<T: DeserializeOwned>(body: &[u8], fields: &[&str]) -> Result<T, String> {
+    let value: Value = parse_json_body(body)?;
+    if let Value::Object(ref m) = value {
+        let unknown: Vec<&str> = m
+            .keys()
+            .map(|k| k.as_str())
+            .filter(|k| !fields.contains(k))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(format!("Unknown fields: {}", unknown.join(", ")));
+        }
+    }
+    serde_json::from_value(value).map_err(|e| format!("Invalid JSON string: {}", e))
+}
+
+// Returns the service name of "/v1/registration/:service".
+pub fn match_registration_path(path: &str) -> Option<&str> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^/v1/registration/([^/]+)/?$").unwrap();
+    }
+    RE.captures(path)
+        .and_then(|caps| caps.get(1))
+        .map(|m| m.as_str())
+}
+
+// Returns the service name of "/v1/feedback/:service".
+pub fn match_feedback_path(path: &str) -> Option<&str> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^/v1/feedback/([^/]+)/?$").unwrap();
+    }
+    RE.captures(path)
+        .and_then(|caps| caps.get(1))
+        .map(|m| m.as_str())
+}
+
+// Returns the service name, ip and port of "/v1/registration/:service/:ip:port".
+pub fn match_host_path(path: &str) -> Option<(&str, &str, &str)> {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"^/v1/registration/([^/]+)/([^/:]+):([^/:]+)/?$").unwrap();
+    }
+    let caps = RE.captures(path)?;
+    match (caps.get(1), caps.get(2), caps.get(3)) {
+        (Some(service), Some(ip), Some(port)) => {
+            Some((service.as_str(), ip.as_str(), port.as_str()))
+        }
+        _ => None,
+    }
+}
+
+// Returns the service name of the v1 registration, host, drain and feedback paths.
+pub fn match_service_path(path: &str) -> Option<&str> {
+    match_registration_path(path)
+        .or_else(|| match_feedback_path(path))
+        .or_else(|| match_host_path(path).map(|(service, _, _)| service))
+        .or_else(|| match_drain_path(path).map(|(service, _, _)| service))
+        .or_else(|| match_tags_path(path).map(|(service, _, _)| service))
+}
+
+// Returns the alias of "/v1/aliases/:alias".
+pub fn match_alias_path(path: &str) -> Option<&str> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^/v1/aliases/([^/]+)/?$").unwrap();
+    }
+    RE.captures(path)
+        .and_then(|caps| caps.get(1))
+        .map(|m| m.as_str())
+}
+
+// Returns the service name, ip and port of "/v1/registration/:service/:ip:port/drain".
+pub fn match_drain_path(path: &str) -> Option<(&str, &str, &str)> {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"^/v1/registration/([^/]+)/([^/:]+):([^/:]+)/drain/?$").unwrap();
+    }
+    let caps = RE.captures(path)?;
+    match (caps.get(1), caps.get(2), caps.get(3)) {
+        (Some(service), Some(ip), Some(port)) => {
+            Some((service.as_str(), ip.as_str(), port.as_str()))
+        }
+        _ => None,
+    }
+}
+
+pub fn match_tags_path(path: &str) -> Option<(&str, &str, &str)> {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"^/v1/registration/([^/]+)/([^/:]+):([^/:]+)/tags/?$").unwrap();
+    }
+    let caps = RE.captures(path)?;
+    match (caps.get(1), caps.get(2), caps.get(3)) {
+        (Some(service), Some(ip), Some(port)) => {
+            Some((service.as_str(), ip.as_str(), port.as_str()))
+        }
+        _ => None,
+    }
+}
+
+// Fields of Host which can be selected with `?fields=`.
+const HOST_FIELDS: &[&str] = &[
+    "ip_address",
+    "port",
+    "last_check_in",
+    "expire_time",
+    "revision",
+    "service",
+    "tags",
+    "drain_started_at",
+    "registered_at",
+    "checked_in_at",
+    "expires_at",
+    "expires_in_seconds",
+];
+
+// Returns the decoded query parameters of the URI in order.
+pub fn query_params(uri: &Uri) -> Vec<(String, String)> {
+    match uri.query() {
+        Some(q) => form_urlencoded::parse(q.as_bytes()).into_owned().collect(),
+        None => Vec::new(),
+    }
+}
+
+// Returns the last value of the query parameter.
+pub fn query_param<'a>(params: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .rev()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+// Parses comma separated Host field names like "ip_address,port".
+pub fn parse_fields(value: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    for f in value.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+        if !HOST_FIELDS.contains(&f) {
+            return Err(format!("Unknown field: {}", f));
+        }
+        fields.push(f.to_owned());
+    }
+    if fields.is_empty() {
+        return Err("fields must not be empty".to_owned());
+    }
+    Ok(fields)
+}
+
+pub fn parse_port(s: &str) -> Result<u16, String> {
+    s.parse()
+        .map_err(|_| format!("Given port is invalid as integer: {}", s))
+}
+
+// Parses an "ip:port" of the exclude query parameter. IPv6 addresses may be bracketed like
+// "[::1]:8080".
+pub fn parse_exclude(value: &str) -> Result<(String, u16), String> {
+    let pos = match value.rfind(':') {
+        Some(v) => v,
+        None => return Err(format!("exclude must be ip:port: {}", value)),
+    };
+    let ip = value[..pos].trim_start_matches('[').trim_end_matches(']');
+    if ip.is_empty() {
+        return Err(format!("exclude must be ip:port: {}", value));
+    }
+    Ok((ip.to_owned(), parse_port(&value[pos + 1..])?))
+}
+
+// Parses the number of hosts of the sample query parameter.
+pub fn parse_sample(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("sample must be a non-negative integer: {}", value))
+}
+
+// Parses the value of the deadline header, the time budget in milliseconds. Budgets too large
+// to be represented are treated as no deadline.
+pub fn parse_deadline(value: &[u8], now: Instant) -> Result<Option<Instant>, String> {
+    let ms = str::from_utf8(value)
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok());
+    match ms {
+        Some(ms) => Ok(now.checked_add(Duration::from_millis(ms))),
+        None => Err("deadline must be an integer in milliseconds".to_owned()),
+    }
+}
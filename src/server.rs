@@ -1,34 +1,113 @@
-use std::str;
-use std::time;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::io;
+use std::net::{self, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use chrono;
-use futures::{future, Future, Stream};
+use futures::sync::oneshot;
+use futures::{future, stream, Future, IntoFuture, Poll, Stream};
+use futures_cpupool::CpuPool;
 use hyper;
-use hyper::service::service_fn;
+use hyper::body::Payload;
+use hyper::http::response;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, Service};
 use hyper::Server;
-use hyper::{Body, Method, Request, Response, StatusCode};
-use lazy_static::lazy_static;
-use log::{debug, error, info};
-use regex::Regex;
-use serde_derive::{Deserialize, Serialize};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use log::{debug, error, info, warn};
+use rand::seq::SliceRandom;
+use serde_derive::Serialize;
 use serde_json;
+use serde_json::{Map, Value};
+use tokio::timer::Delay;
 use uuid::Uuid;
 
-use super::types::{Config, Host, Registration, Storage, Tag};
+use super::admission::{Admission, AdmissionConfig, Decision};
+use super::aliases::{self, AliasCache};
+use super::anti_entropy::start_anti_entropy;
+use super::clock::{Clock, SharedClock};
+use super::config::{DuplicateAction, DuplicateScope, FileConfig, ReloadableConfig, ServiceConfig};
+use super::conflicts::{Conflict, ConflictTracker};
+use super::events::{EventConfig, EventEmitter, EventKind};
+use super::feedback::{FeedbackConfig, FeedbackTracker};
+use super::grpc_health::serve_grpc_health;
+use super::idempotency::{fingerprint, CachedResponse, IdempotencyCache, Lookup};
+use super::info::{BuildInfo, Info, RuntimeInfo};
+use super::limiter::{ConcurrencyLimiter, LimitConfig, Permit};
+use super::maintenance::Maintenance;
+use super::metrics::Metrics;
+use super::payload_log::PayloadLogger;
+use super::quota::{QuotaViolation, RateLimiter};
+use super::readiness::{start_warm_up, Readiness};
+use super::reaper::start_reaper;
+use super::region::{demote_other_regions, pin_hosts, RegionPinning};
+use super::request::{
+    self, apply_tag_patch, match_alias_path, match_drain_path, match_feedback_path,
+    match_host_path, match_registration_path, match_service_path, match_tags_path,
+    parse_alias_param, parse_discovery_request, parse_exclude, parse_feedback_param, parse_fields,
+    parse_maintenance_param, parse_payload_log_config, parse_port, parse_registration_param,
+    parse_sample, parse_tag_patch, query_param, query_params, RegistrationParam,
+};
+use super::shutdown::run_pre_shutdown;
+use super::slo::SloTracker;
+use super::types::{sort_hosts, Config, Freshness, Host, Storage};
 use super::v2xds::{
-    hosts_to_locality_lb_endpoints, ClusterLoadAssignment, DiscoveryRequest, EdsDiscoveryResponse,
-    EDS_TYPE_URL,
+    build_policy, hosts_to_locality_lb_endpoints, ClusterLoadAssignment, EdsDiscoveryResponse,
+    Locality, EDS_TYPE_URL,
 };
+use super::versions::{set_headers as set_version_headers, versions, Api};
+use super::virtual_service;
+use super::write_lock::{ServiceLocks, ALIASES_LOCK};
+use super::xds_file::{start_xds_file_writer, XdsFileConfig};
 
 type BoxFut = Box<Future<Item = Response<Body>, Error = hyper::Error> + Send>;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RegistrationParam {
-    ip: String,
-    port: u16,
-    revision: String,
-    tags: Tag,
+// Time budget of the request in milliseconds, counted from when sds receives the request.
+const DEADLINE_HEADER: &str = "x-sds-deadline-ms";
+// Registry index the response reflects, see RegistryIndex.
+const INDEX_HEADER: &str = "x-sds-index";
+// Source and age of the served hosts, see Freshness.
+const FRESHNESS_HEADER: &str = "x-sds-data-freshness";
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+#[derive(Clone)]
+struct Context<S> {
+    storage: S,
+    config: Arc<Config>,
+    feedback: FeedbackTracker,
+    idempotency: IdempotencyCache,
+    readiness: Readiness,
+    // Runs blocking storage queries of EDS requests concurrently.
+    query_pool: CpuPool,
+    events: EventEmitter,
+    metrics: Metrics,
+    limiter: ConcurrencyLimiter,
+    conflicts: ConflictTracker,
+    maintenance: Maintenance,
+    registration_rates: RateLimiter,
+    payload_log: PayloadLogger,
+    aliases: AliasCache,
+    slo: SloTracker,
+    admission: Option<Admission>,
+    write_locks: ServiceLocks,
+    started_at: Instant,
+    deadline: Option<Instant>,
+}
+
+impl<S> Context<S> {
+    fn deadline_exceeded(&self) -> bool {
+        self.deadline.map_or(false, |d| Instant::now() >= d)
+    }
+
+    fn epoch_now(&self) -> Result<u64, String> {
+        self.config.clock.epoch_secs().map_err(|e| {
+            error!("{}", e);
+            e
+        })
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -42,22 +121,547 @@ struct ErrorResponse {
 #[derive(Serialize, Debug)]
 enum ErrorId {
     HostNotFound,
+    TooManyHosts,
+    DuplicateHost,
+    Maintenance,
+    QuotaExceeded,
+    AliasNotFound,
+    AliasLoop,
+    AliasesNotSupported,
+    AdmissionDenied,
+    AdmissionUnavailable,
+    AccessDenied,
 }
 
-pub fn run<S: Storage>(c: &Config, s: S) {
+#[derive(Serialize, Debug)]
+struct AliasResponse {
+    alias: String,
+    service: String,
+}
+
+#[derive(Serialize, Debug)]
+struct QuotaErrorResponse {
+    id: ErrorId,
+    reason: String,
+    quota: QuotaViolation,
+}
+
+// hyper Service serving the sds endpoints, for embedding sds into other hyper applications.
+#[derive(Clone)]
+pub struct SdsService<S> {
+    ctx: Context<S>,
+    path_prefix: Option<String>,
+    // Address of the client of the connection, checked against the access config.
+    remote_addr: Option<SocketAddr>,
+}
+
+impl<S: Storage> SdsService<S> {
+    pub fn builder(storage: S) -> SdsServiceBuilder<S> {
+        SdsServiceBuilder::new(storage)
+    }
+
+    // Returns the service for a connection from the address. Without it, requests restricted by
+    // the access config are denied.
+    pub fn with_remote_addr(&self, addr: SocketAddr) -> Self {
+        SdsService {
+            remote_addr: Some(addr),
+            ..self.clone()
+        }
+    }
+}
+
+impl<S: Storage> Service for SdsService<S> {
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = hyper::Error;
+    type Future = BoxFut;
+
+    fn call(&mut self, mut req: Request<Body>) -> BoxFut {
+        if let Some(ref prefix) = self.path_prefix {
+            match strip_path_prefix(req.uri(), prefix) {
+                Some(uri) => *req.uri_mut() = uri,
+                None => return res_404(),
+            }
+        }
+        if let Err(res) = check_access(&self.ctx, self.remote_addr, &req) {
+            return wrap_future(res);
+        }
+        // Health checks are never shed so that overload isn't mistaken for a dead instance.
+        if is_health_check(req.uri().path()) {
+            return route(self.ctx.clone(), req);
+        }
+        let api = Api::of(req.method(), req.uri().path());
+        let route_name = api.map_or("other", |a| a.name());
+        let started = Instant::now();
+        let metrics = self.ctx.metrics.clone();
+        let slo = self.ctx.slo.clone();
+        let objectives = self.ctx.config.file.current();
+        let service = if objectives.slos.is_empty() {
+            None
+        } else {
+            match_service_path(req.uri().path()).map(|s| s.to_owned())
+        };
+        let observe = move |status: u16| {
+            let elapsed = started.elapsed();
+            metrics.observe_request(route_name, status, elapsed);
+            slo.record(
+                &objectives.slos,
+                route_name,
+                service.as_ref().map(String::as_str),
+                status,
+                elapsed,
+                Instant::now(),
+            );
+        };
+        let permit = match self.ctx.limiter.try_acquire(route_name) {
+            Some(v) => v,
+            None => {
+                observe(503);
+                return res_503_overloaded(&self.ctx, route_name);
+            }
+        };
+        let config = self.ctx.config.clone();
+        Box::new(
+            route_logging_payloads(self.ctx.clone(), req, route_name).then(move |r| {
+                r.map(|res| {
+                    let mut res = hold_until_written(res, permit);
+                    if let Some(api) = api {
+                        set_version_headers(
+                            &mut res,
+                            api,
+                            config.sds_v1_sunset.as_ref().map(String::as_str),
+                        );
+                    }
+                    observe(res.status().as_u16());
+                    res
+                })
+            }),
+        )
+    }
+}
+
+// Streamed bodies, e.g. large v1 SDS responses, keep the permit of the request until they are
+// written out or the client goes away, so that the limits also bound responses being streamed.
+// Other bodies release it right away.
+fn hold_until_written(res: Response<Body>, permit: Permit) -> Response<Body> {
+    if res.body().content_length().is_some() {
+        return res;
+    }
+    res.map(|body| {
+        Body::wrap_stream(PermitBody {
+            body,
+            _permit: permit,
+        })
+    })
+}
+
+struct PermitBody {
+    body: Body,
+    _permit: Permit,
+}
+
+impl Stream for PermitBody {
+    type Item = hyper::Chunk;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Option<hyper::Chunk>, hyper::Error> {
+        self.body.poll()
+    }
+}
+
+// Buffers the request and response bodies to log them when payload logging is enabled for the
+// route, otherwise routes the request as is.
+fn route_logging_payloads<S: Storage>(
+    ctx: Context<S>,
+    req: Request<Body>,
+    route_name: &'static str,
+) -> BoxFut {
+    if !ctx.payload_log.is_enabled(route_name) {
+        return route(ctx, req);
+    }
+    let logger = ctx.payload_log.clone();
+    let (parts, body) = req.into_parts();
+    let f = body.concat2().and_then(move |chunk| {
+        logger.log_request(
+            route_name,
+            parts.method.as_str(),
+            &parts.uri.to_string(),
+            &chunk,
+        );
+        route(ctx, Request::from_parts(parts, Body::from(chunk))).and_then(move |res| {
+            let (parts, body) = res.into_parts();
+            body.concat2().map(move |chunk| {
+                logger.log_response(route_name, parts.status.as_u16(), &chunk);
+                Response::from_parts(parts, Body::from(chunk))
+            })
+        })
+    });
+    Box::new(f)
+}
+
+// Allows to pass a closure returning SdsService to hyper::Server::serve().
+impl<S: Storage> IntoFuture for SdsService<S> {
+    type Future = future::FutureResult<Self::Item, Self::Error>;
+    type Item = Self;
+    type Error = hyper::Error;
+
+    fn into_future(self) -> Self::Future {
+        future::ok(self)
+    }
+}
+
+pub struct SdsServiceBuilder<S> {
+    storage: S,
+    config: Config,
+    path_prefix: Option<String>,
+}
+
+impl<S: Storage> SdsServiceBuilder<S> {
+    pub fn new(storage: S) -> Self {
+        SdsServiceBuilder {
+            storage,
+            config: Config::default(),
+            path_prefix: None,
+        }
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn file_config(mut self, file_config: FileConfig) -> Self {
+        self.config.file = ReloadableConfig::new(None, file_config);
+        self
+    }
+
+    pub fn feedback(mut self, feedback: FeedbackConfig) -> Self {
+        self.config.feedback = feedback;
+        self
+    }
+
+    pub fn idempotency_window(mut self, window: Duration) -> Self {
+        self.config.idempotency_window = window;
+        self
+    }
+
+    pub fn readiness_timeout(mut self, timeout: Duration) -> Self {
+        self.config.readiness_timeout = timeout;
+        self
+    }
+
+    pub fn events(mut self, events: EventConfig) -> Self {
+        self.config.events = events;
+        self
+    }
+
+    // Removes expired hosts from the storage every interval and emits Expire events for them.
+    pub fn reaper_interval(mut self, interval: Duration) -> Self {
+        self.config.reaper_interval = Some(interval);
+        self
+    }
+
+    // Calls Storage::resync() every interval and records the drift found into the metrics.
+    pub fn resync_interval(mut self, interval: Duration) -> Self {
+        self.config.resync_interval = Some(interval);
+        self
+    }
+
+    // Writes a ClusterLoadAssignment file per service into the directory every interval.
+    pub fn xds_file(mut self, dir: PathBuf, interval: Duration) -> Self {
+        self.config.xds_file = Some(XdsFileConfig { dir, interval });
+        self
+    }
+
+    // Asks the webhook before storing registrations, see Admission.
+    pub fn admission(mut self, admission: AdmissionConfig) -> Self {
+        self.config.admission = Some(admission);
+        self
+    }
+
+    pub fn limits(mut self, limits: LimitConfig) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    // Replaces the wall clock, e.g. with MockClock in tests.
+    pub fn clock(mut self, clock: SharedClock) -> Self {
+        self.config.clock = clock;
+        self
+    }
+
+    pub fn eds_query_concurrency(mut self, concurrency: usize) -> Self {
+        self.config.eds_query_concurrency = concurrency;
+        self
+    }
+
+    // Serves the endpoints under the prefix, e.g. "/sds" serves "/sds/v1/registration/:name".
+    // Requests outside of the prefix are responded with 404.
+    pub fn path_prefix(mut self, prefix: &str) -> Self {
+        self.path_prefix = Some(prefix.trim_end_matches('/').to_owned());
+        self
+    }
+
+    // Starts the storage warm-up, the reaper and the anti-entropy job in background and returns
+    // the service.
+    pub fn build(self) -> SdsService<S> {
+        let c = self.config;
+        let readiness = Readiness::new();
+        start_warm_up(readiness.clone(), self.storage.clone(), c.readiness_timeout);
+        let events = EventEmitter::new(&c.events, c.clock.clone());
+        let maintenance = Maintenance::new(&c.maintenance);
+        let admission = c.admission.clone().and_then(|a| match Admission::new(a) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                error!("Admission is disabled: {}", e);
+                None
+            }
+        });
+        if let Some(interval) = c.reaper_interval {
+            start_reaper(
+                self.storage.clone(),
+                events.clone(),
+                interval,
+                c.clock.clone(),
+                maintenance.clone(),
+            );
+        }
+        let metrics = c.metrics.clone();
+        let write_locks = ServiceLocks::new(metrics.clone());
+        if let Some(interval) = c.resync_interval {
+            start_anti_entropy(self.storage.clone(), metrics.clone(), interval);
+        }
+        if let Some(ref xds_file) = c.xds_file {
+            start_xds_file_writer(
+                self.storage.clone(),
+                c.file.clone(),
+                c.clock.clone(),
+                xds_file.clone(),
+            );
+        }
+        let conflicts = ConflictTracker::new(self.storage.ttl());
+        SdsService {
+            ctx: Context {
+                storage: self.storage,
+                feedback: FeedbackTracker::new(c.feedback.clone()),
+                idempotency: IdempotencyCache::new(c.idempotency_window),
+                readiness,
+                query_pool: CpuPool::new(c.eds_query_concurrency.max(1)),
+                events,
+                metrics,
+                limiter: ConcurrencyLimiter::new(c.limits.clone(), c.file.clone()),
+                conflicts,
+                maintenance,
+                registration_rates: RateLimiter::new(),
+                payload_log: PayloadLogger::new(c.payload_log.clone()),
+                aliases: AliasCache::new(),
+                slo: SloTracker::new(),
+                admission,
+                write_locks,
+                started_at: Instant::now(),
+                deadline: None,
+                config: Arc::new(c),
+            },
+            path_prefix: self.path_prefix,
+            remote_addr: None,
+        }
+    }
+}
+
+// Denies requests from clients outside of the access config of the request's endpoint.
+fn check_access<S>(
+    ctx: &Context<S>,
+    remote_addr: Option<SocketAddr>,
+    req: &Request<Body>,
+) -> Result<(), Response<Body>> {
+    let file_config = ctx.config.file.current();
+    let filter = match file_config.access.filter_of(req.method(), req.uri().path()) {
+        Some(v) if !v.is_open() => v,
+        _ => return Ok(()),
+    };
+    let reason = match remote_addr {
+        Some(addr) if filter.permits(addr.ip()) => return Ok(()),
+        Some(addr) => format!("Access from {} is denied", addr.ip()),
+        None => "Access from unknown address is denied".to_owned(),
+    };
+    warn!(
+        "Deny request: method={}, path={}, reason={}",
+        req.method(),
+        req.uri().path(),
+        reason
+    );
+    Err(build_error_response(
+        StatusCode::FORBIDDEN,
+        ErrorId::AccessDenied,
+        reason,
+    ))
+}
+
+fn is_health_check(path: &str) -> bool {
+    path == "/hc" || path == "/hc/ready"
+}
+
+fn strip_path_prefix(uri: &Uri, prefix: &str) -> Option<Uri> {
+    let path = uri.path();
+    if !path.starts_with(prefix) {
+        return None;
+    }
+    let rest = &path[prefix.len()..];
+    let mut stripped = match rest {
+        "" => "/".to_owned(),
+        _ if rest.starts_with('/') => rest.to_owned(),
+        _ => return None,
+    };
+    if let Some(q) = uri.query() {
+        stripped.push('?');
+        stripped.push_str(q);
+    }
+    stripped.parse().ok()
+}
+
+type Drain = future::Shared<Box<Future<Item = (), Error = ()> + Send>>;
+
+// Completes when the servers should stop accepting connections, also when the drain failed.
+fn wait_for_drain(drain: &Drain) -> impl Future<Item = (), Error = ()> + Send {
+    drain.clone().then(|_| Ok(()))
+}
+
+// Returns the HTTP server future listening on c.listen_port. It's driven by run(), or by the
+// caller's runtime when sds is embedded.
+pub fn serve<S: Storage>(c: &Config, s: S) -> impl Future<Item = (), Error = ()> + Send {
+    serve_with_shutdown(c, s, future::empty())
+}
+
+// Like serve(), but shuts down when `signal` completes: runs the pre-shutdown steps of
+// c.shutdown, then drains connections for up to c.shutdown.drain_timeout.
+pub fn serve_with_shutdown<S, F>(
+    c: &Config,
+    s: S,
+    signal: F,
+) -> impl Future<Item = (), Error = ()> + Send
+where
+    S: Storage,
+    F: Future<Item = (), Error = ()> + Send + 'static,
+{
     // XXX: ipv4 only
-    let addr = ([0, 0, 0, 0], c.listen_port).into();
-    let new_service = move || {
-        let st = s.clone();
-        service_fn(move |req| {
-            let stt = st.clone();
-            route(stt, req)
+    let addr: SocketAddr = ([0, 0, 0, 0], c.listen_port).into();
+    let service = SdsService::builder(s).config(c.clone()).build();
+    let readiness = service.ctx.readiness.clone();
+    let hooks = c.shutdown.clone();
+    let listen_port = c.listen_port;
+    let drain: Box<Future<Item = (), Error = ()> + Send> = Box::new(signal.and_then(move |_| {
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            run_pre_shutdown(&hooks, &readiness, listen_port);
+            let _ = tx.send(());
+        });
+        rx.map(|_| info!("Draining connections")).map_err(|_| ())
+    }));
+    let drain = drain.shared();
+
+    let grpc_health = c.grpc_health_port.map(|port| {
+        let addr: SocketAddr = ([0, 0, 0, 0], port).into();
+        serve_grpc_health(&addr, service.ctx.readiness.clone(), wait_for_drain(&drain))
+    });
+    let server: Box<Future<Item = (), Error = ()> + Send> = if c.reuse_port {
+        serve_reuse_port(&addr, service, &drain)
+    } else {
+        // The service is created per connection to tell it the address of the client.
+        let server = Server::bind(&addr)
+            .serve(make_service_fn(move |conn: &AddrStream| {
+                Ok::<_, hyper::Error>(service.with_remote_addr(conn.remote_addr()))
+            }))
+            .with_graceful_shutdown(wait_for_drain(&drain))
+            .map_err(|e| error!("server error: {}", e));
+        info!("Listening on {}", addr);
+        Box::new(server)
+    };
+    let server: Box<Future<Item = (), Error = ()> + Send> = match grpc_health {
+        Some(f) => Box::new(server.join(f).map(|_| ())),
+        None => server,
+    };
+    let drain_timeout = c.shutdown.drain_timeout;
+    let deadline = wait_for_drain(&drain).and_then(move |_| {
+        Delay::new(Instant::now() + drain_timeout).then(move |_| {
+            warn!("Connections didn't drain in {:?}", drain_timeout);
+            Ok(())
         })
+    });
+    server.select(deadline).map(|_| ()).map_err(|_| ())
+}
+
+// Binds a socket per core thread, the kernel balances incoming connections among them.
+fn serve_reuse_port<S: Storage>(
+    addr: &SocketAddr,
+    service: SdsService<S>,
+    drain: &Drain,
+) -> Box<Future<Item = (), Error = ()> + Send> {
+    let acceptors = get_core_threads().unwrap_or_else(num_cpus::get).max(1);
+    let listeners = match bind_reuse_port(addr, acceptors) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to bind {} with SO_REUSEPORT: {}", addr, e);
+            return Box::new(future::err(()));
+        }
     };
-    let server = Server::bind(&addr)
-        .serve(new_service)
-        .map_err(|e| error!("server error: {}", e));
-    info!("Listening on {}", addr);
+    let mut servers = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        match Server::from_tcp(listener) {
+            Ok(builder) => {
+                let service = service.clone();
+                servers.push(
+                    builder
+                        .serve(make_service_fn(move |conn: &AddrStream| {
+                            Ok::<_, hyper::Error>(service.with_remote_addr(conn.remote_addr()))
+                        }))
+                        .with_graceful_shutdown(wait_for_drain(drain))
+                        .map_err(|e| error!("server error: {}", e)),
+                );
+            }
+            Err(e) => {
+                error!("Failed to start acceptor: {}", e);
+                return Box::new(future::err(()));
+            }
+        }
+    }
+    info!(
+        "Listening on {} with SO_REUSEPORT: acceptors={}",
+        addr, acceptors
+    );
+    Box::new(future::join_all(servers).map(|_| ()))
+}
+
+#[cfg(unix)]
+fn bind_reuse_port(addr: &SocketAddr, n: usize) -> io::Result<Vec<net::TcpListener>> {
+    use net2::unix::UnixTcpBuilderExt;
+    use net2::TcpBuilder;
+
+    let mut addr = *addr;
+    let mut listeners = Vec::with_capacity(n);
+    for _ in 0..n {
+        let listener = TcpBuilder::new_v4()?
+            .reuse_address(true)?
+            .reuse_port(true)?
+            .bind(addr)?
+            .listen(1024)?;
+        // The rest share the port picked for the first one when listening on port 0.
+        addr = listener.local_addr()?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+#[cfg(not(unix))]
+fn bind_reuse_port(_: &SocketAddr, _: usize) -> io::Result<Vec<net::TcpListener>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+// Serves until SIGTERM or SIGINT, then shuts down following c.shutdown.
+pub fn run<S: Storage>(c: &Config, s: S) {
+    let server = serve_with_shutdown(c, s, shutdown_signal());
     let mut builder = tokio::runtime::Builder::new();
     if let Some(num) = get_core_threads() {
         log::info!("Set core_threads to {}", num);
@@ -65,12 +669,77 @@ pub fn run<S: Storage>(c: &Config, s: S) {
     }
     let mut entered = tokio_executor::enter().expect("nested tokio::run");
     let mut runtime = builder.build().expect("failed to start new Runtime");
-    runtime.spawn(server);
+    let (done_tx, done_rx) = oneshot::channel();
+    runtime.spawn(server.then(move |_| {
+        let _ = done_tx.send(());
+        Ok(())
+    }));
+    #[cfg(unix)]
+    runtime.spawn(reload_on_sighup(c.file.clone()));
+    let _ = entered.block_on(done_rx);
+    info!("Server stopped");
     entered
-        .block_on(runtime.shutdown_on_idle())
+        .block_on(runtime.shutdown_now())
         .expect("shutdown cannot error");
 }
 
+// Completes on the first SIGTERM or SIGINT. Never completes when the signals can't be handled.
+#[cfg(unix)]
+fn shutdown_signal() -> impl Future<Item = (), Error = ()> + Send {
+    use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+    Signal::new(SIGTERM)
+        .flatten_stream()
+        .select(Signal::new(SIGINT).flatten_stream())
+        .into_future()
+        .then(|r| -> Box<Future<Item = (), Error = ()> + Send> {
+            match r {
+                Ok((signal, _)) => {
+                    info!("Received signal {:?}, shutting down", signal);
+                    Box::new(future::ok(()))
+                }
+                Err((e, _)) => {
+                    error!("Failed to handle shutdown signals: {}", e);
+                    Box::new(future::empty())
+                }
+            }
+        })
+}
+
+#[cfg(not(unix))]
+fn shutdown_signal() -> impl Future<Item = (), Error = ()> + Send {
+    tokio_signal::ctrl_c().flatten_stream().into_future().then(
+        |r| -> Box<Future<Item = (), Error = ()> + Send> {
+            match r {
+                Ok(_) => {
+                    info!("Received Ctrl-C, shutting down");
+                    Box::new(future::ok(()))
+                }
+                Err((e, _)) => {
+                    error!("Failed to handle Ctrl-C: {}", e);
+                    Box::new(future::empty())
+                }
+            }
+        },
+    )
+}
+
+#[cfg(unix)]
+fn reload_on_sighup(file: ReloadableConfig) -> impl Future<Item = (), Error = ()> + Send {
+    use tokio_signal::unix::{Signal, SIGHUP};
+
+    Signal::new(SIGHUP)
+        .flatten_stream()
+        .for_each(move |_| {
+            info!("Received SIGHUP, reloading config file");
+            if let Err(e) = file.reload() {
+                error!("Failed to reload config file: {}", e);
+            }
+            Ok(())
+        })
+        .map_err(|e| error!("Failed to handle SIGHUP: {}", e))
+}
+
 fn get_core_threads() -> Option<usize> {
     std::env::var("CORE_THREADS")
         .ok()
@@ -83,101 +752,157 @@ fn get_core_threads() -> Option<usize> {
         })
 }
 
-fn route<S: Storage>(s: S, req: Request<Body>) -> BoxFut {
+fn route<S: Storage>(mut ctx: Context<S>, req: Request<Body>) -> BoxFut {
     info!(
         "Recieve request: method={}, path={}",
         req.method(),
         req.uri().to_owned().path()
     );
+    match parse_deadline(&req) {
+        Ok(Some(deadline)) => {
+            ctx.storage = ctx.storage.with_deadline(deadline);
+            ctx.deadline = Some(deadline);
+        }
+        Ok(None) => {}
+        Err(msg) => return res_400(msg),
+    }
     match *req.method() {
-        Method::GET => route_get_req(&s, req),
-        Method::POST => route_post_req(s, req),
-        Method::DELETE => route_delete_req(&s, req),
+        Method::GET => route_get_req(&ctx, req),
+        Method::POST => route_post_req(ctx, req),
+        Method::PUT => route_put_req(ctx, req),
+        Method::DELETE => route_delete_req(&ctx, req),
+        Method::PATCH => route_patch_req(ctx, req),
         _ => res_404(),
     }
 }
 
-fn route_get_req<S: Storage>(s: &S, req: Request<Body>) -> BoxFut {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"^/v1/registration/([^/]+)/?$").unwrap();
-    }
-
+fn route_get_req<S: Storage>(ctx: &Context<S>, req: Request<Body>) -> BoxFut {
     let uri = req.uri().to_owned();
     match uri.path() {
         "/" => show_usage(req),
         "/hc" => check_health(req),
-        _ => match RE.captures(uri.path()) {
-            Some(caps) => match caps.get(1) {
-                Some(m) => get_registration(s, req, m.as_str()),
-                _ => res_404(),
-            },
-            _ => res_404(),
-        },
+        "/hc/ready" => check_readiness(ctx),
+        "/metrics" if ctx.config.metrics_endpoint => show_metrics(ctx),
+        "/versions" => show_versions(ctx),
+        "/v1/stats" => show_stats(ctx),
+        "/admin/conflicts" => show_conflicts(ctx),
+        "/admin/maintenance" => show_maintenance(ctx),
+        "/admin/payload-log" => show_payload_log(ctx),
+        "/admin/slo" => show_slo(ctx),
+        "/admin/info" => show_info(ctx),
+        "/v1/aliases" | "/v1/aliases/" => list_aliases(ctx),
+        path => {
+            if let Some(alias) = match_alias_path(path) {
+                return show_alias(ctx, alias);
+            }
+            match match_registration_path(path) {
+                Some(name) => get_registration(ctx, req, name),
+                None => res_404(),
+            }
+        }
     }
 }
 
-fn route_post_req<S: Storage>(s: S, req: Request<Body>) -> BoxFut {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"^/v1/registration/([^/]+)/?$").unwrap();
-    }
-
+fn route_post_req<S: Storage>(ctx: Context<S>, req: Request<Body>) -> BoxFut {
     let uri = req.uri().to_owned();
     match uri.path() {
         "/" => show_usage(req),
         "/hc" => check_health(req),
-        "/v2/discovery:endpoints" => get_registration_v2(&s, req),
-        _ => match RE.captures(uri.path()) {
-            Some(caps) => match caps.get(1) {
-                Some(m) => register_hosts(s, req, m.as_str()),
-                _ => res_404(),
-            },
-            _ => res_404(),
-        },
+        "/v2/discovery:endpoints" => get_registration_v2(&ctx, req),
+        "/admin/reload" => reload_config(&ctx),
+        "/admin/maintenance" => set_maintenance(ctx, req),
+        "/admin/payload-log" => set_payload_log(ctx, req),
+        path => {
+            if let Some(name) = match_registration_path(path) {
+                if let Some(msg) = ctx.maintenance.check() {
+                    return res_503_maintenance(msg);
+                }
+                let name = name.to_owned();
+                return with_idempotency(ctx, req, path.to_owned(), move |ctx, req| {
+                    register_hosts(ctx, req, &name)
+                });
+            }
+            if let Some((name, ip, port)) = match_drain_path(path) {
+                if let Some(msg) = ctx.maintenance.check() {
+                    return res_503_maintenance(msg);
+                }
+                return drain_host(&ctx, name, ip, port);
+            }
+            match match_feedback_path(path) {
+                Some(name) => report_feedback(ctx, req, name),
+                None => res_404(),
+            }
+        }
     }
 }
 
-fn route_delete_req<S: Storage>(s: &S, req: Request<Body>) -> BoxFut {
-    lazy_static! {
-        static ref RE: Regex =
-            Regex::new(r"^/v1/registration/([^/]+)/([^/:]+):([^/:]+)/?$").unwrap();
-    }
-
+fn route_delete_req<S: Storage>(ctx: &Context<S>, req: Request<Body>) -> BoxFut {
     let uri = req.uri().to_owned();
     match uri.path() {
         "/" => show_usage(req),
         "/hc" => check_health(req),
-        _ => match RE.captures(uri.path()) {
-            Some(caps) => match caps.get(1) {
-                Some(m_service) => match caps.get(2) {
-                    Some(m_ip) => match caps.get(3) {
-                        Some(m_port) => delete_host(
-                            s,
-                            m_service.as_str(),
-                            m_ip.as_str().to_string(),
-                            m_port.as_str(),
-                        ),
-                        _ => res_404(),
-                    },
-                    _ => res_404(),
+        path => {
+            if let Some(alias) = match_alias_path(path) {
+                return match ctx.maintenance.check() {
+                    Some(msg) => res_503_maintenance(msg),
+                    None => delete_alias(ctx, alias),
+                };
+            }
+            match match_host_path(path) {
+                Some((name, ip, port)) => match ctx.maintenance.check() {
+                    Some(msg) => res_503_maintenance(msg),
+                    None => delete_host(ctx, name, ip.to_owned(), port),
                 },
-                _ => res_404(),
-            },
-            _ => res_404(),
+                None => res_404(),
+            }
+        }
+    }
+}
+
+fn route_put_req<S: Storage>(ctx: Context<S>, req: Request<Body>) -> BoxFut {
+    let uri = req.uri().to_owned();
+    match match_alias_path(uri.path()) {
+        Some(alias) => match ctx.maintenance.check() {
+            Some(msg) => res_503_maintenance(msg),
+            None => put_alias(ctx, req, alias.to_owned()),
         },
+        None => res_404(),
     }
 }
 
-fn get_registration<S: Storage>(s: &S, _: Request<Body>, name: &str) -> BoxFut {
-    let hosts = match s.query_items(name) {
+fn route_patch_req<S: Storage>(ctx: Context<S>, req: Request<Body>) -> BoxFut {
+    let uri = req.uri().to_owned();
+    match match_tags_path(uri.path()) {
+        Some((name, ip, port)) => match ctx.maintenance.check() {
+            Some(msg) => res_503_maintenance(msg),
+            None => patch_tags(ctx, req, name.to_owned(), ip.to_owned(), port),
+        },
+        None => res_404(),
+    }
+}
+
+// Follows aliases from the requested name to the service to look up.
+fn resolve_service<S: Storage>(ctx: &Context<S>, name: &str) -> Result<String, Response<Body>> {
+    let snapshot = ctx
+        .aliases
+        .get(|| ctx.storage.list_aliases())
+        .map_err(|e| build_storage_error(ctx, e.to_string()))?;
+    aliases::resolve(&snapshot, name).map_err(|e| {
+        error!("Failed to resolve alias: {}", e);
+        build_error_response(StatusCode::LOOP_DETECTED, ErrorId::AliasLoop, e.to_string())
+    })
+}
+
+fn list_aliases<S: Storage>(ctx: &Context<S>) -> BoxFut {
+    let aliases = match ctx.storage.list_aliases() {
         Ok(v) => v,
-        Err(e) => return res_500(e.to_string()),
-    };
-    let registration = Registration {
-        service: name.to_owned(),
-        env: "production".to_owned(),
-        hosts,
+        Err(e) => return wrap_future(build_storage_error(ctx, e.to_string())),
     };
-    let body = match serde_json::to_string(&registration) {
+    let body: Vec<AliasResponse> = aliases
+        .into_iter()
+        .map(|(alias, service)| AliasResponse { alias, service })
+        .collect();
+    let body = match serde_json::to_string(&body) {
         Ok(v) => v,
         Err(e) => return res_500(e.to_string()),
     };
@@ -185,130 +910,1044 @@ fn get_registration<S: Storage>(s: &S, _: Request<Body>, name: &str) -> BoxFut {
     wrap_future(Response::new(Body::from(body)))
 }
 
-fn get_registration_v2<S: Storage>(s: &S, req: Request<Body>) -> BoxFut {
-    let st = s.clone();
-    let f = req
-        .into_body()
-        .concat2()
-        .map(move |buffer| match str::from_utf8(&buffer) {
-            Ok(body) => match serde_json::from_str::<DiscoveryRequest>(&body) {
-                Ok(d_req) => {
-                    let mut resources = Vec::new();
-                    for name in &d_req.resource_names {
-                        let hosts = match st.query_items(&name) {
-                            Ok(v) => v,
-                            Err(e) => return build_500(e.to_string()),
-                        };
-                        let lle_vec = hosts_to_locality_lb_endpoints(hosts);
-                        resources.push(ClusterLoadAssignment {
-                            type_url: EDS_TYPE_URL.to_string(),
-                            cluster_name: name.to_owned(),
-                            endpoints: lle_vec,
-                        });
-                    }
+fn show_alias<S: Storage>(ctx: &Context<S>, alias: &str) -> BoxFut {
+    let service = match ctx.storage.list_aliases() {
+        Ok(mut v) => v.remove(alias),
+        Err(e) => return wrap_future(build_storage_error(ctx, e.to_string())),
+    };
+    match service {
+        Some(service) => build_alias_response(alias.to_owned(), service),
+        None => wrap_future(build_error_response(
+            StatusCode::NOT_FOUND,
+            ErrorId::AliasNotFound,
+            format!("Not found the alias: {}", alias),
+        )),
+    }
+}
 
-                    let d_res = EdsDiscoveryResponse {
-                        version_info: Uuid::new_v4().to_string(),
-                        resources,
-                    };
-                    let body = match serde_json::to_string(&d_res) {
-                        Ok(v) => v,
-                        Err(e) => return build_500(e.to_string()),
-                    };
-                    info!("Build 200 response: body-size={}", body.len());
-                    Response::new(Body::from(body))
-                }
-                Err(m) => {
-                    let mut msg = "Invalid JSON string: ".to_owned();
-                    msg.push_str(&m.to_string());
-                    debug!("invalid json: {:?}", msg);
-                    debug!("invalid request: {:?}", body);
-                    build_400(msg)
+// Points the alias to the service. Aliases making a loop or a chain longer than MAX_ALIAS_DEPTH
+// are rejected with 409.
+// Alias writes are serialized so that two of them can't form a loop the other doesn't see.
+fn put_alias<S: Storage>(ctx: Context<S>, req: Request<Body>, alias: String) -> BoxFut {
+    let f = req.into_body().concat2().and_then(move |buffer| -> BoxFut {
+        let param = match parse_alias_param(&buffer) {
+            Ok(v) => v,
+            Err(msg) => return res_400(msg),
+        };
+        with_write_lock(&ctx, ALIASES_LOCK, move |ctx, _| {
+            let mut aliases = match ctx.storage.list_aliases() {
+                Ok(v) => v,
+                Err(e) => return build_storage_error(ctx, e.to_string()),
+            };
+            aliases.insert(alias.to_owned(), param.service.to_owned());
+            if let Err(e) = aliases::resolve(&aliases, &alias) {
+                let reason = e.to_string();
+                warn!("Reject alias: alias={}, reason={}", alias, reason);
+                return build_error_response(StatusCode::CONFLICT, ErrorId::AliasLoop, reason);
+            }
+            match ctx.storage.put_alias(&alias, &param.service) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return build_error_response(
+                        StatusCode::NOT_IMPLEMENTED,
+                        ErrorId::AliasesNotSupported,
+                        "The storage doesn't support aliases".to_owned(),
+                    )
                 }
-            },
-            Err(_) => build_400("Invalid UTF-8 string".to_owned()),
-        });
+                Err(e) => return build_storage_error(ctx, e.to_string()),
+            }
+            ctx.aliases.invalidate();
+            info!("Put alias: alias={}, service={}", alias, param.service);
+            match serde_json::to_string(&AliasResponse {
+                alias,
+                service: param.service,
+            }) {
+                Ok(body) => Response::new(Body::from(body)),
+                Err(e) => build_500(e.to_string()),
+            }
+        })
+    });
     Box::new(f)
 }
 
-fn register_hosts<S: Storage>(s: S, req: Request<Body>, name: &str) -> BoxFut {
-    let st = s.clone();
-    let name = name.to_owned();
-    let f = req
-        .into_body()
-        .concat2()
-        .map(move |buffer| match str::from_utf8(&buffer) {
-            Ok(body) => match serde_json::from_str::<RegistrationParam>(&body) {
-                Ok(param) => {
-                    let host = match convert_param_to_host(&name, param, s.ttl()) {
-                        Ok(v) => v,
-                        Err(_) => {
-                            error!("Failed to fetch system time");
-                            return build_500("Failed to fetch system time".to_owned());
-                        }
-                    };
-                    if let Err(e) = st.store_item(&name, host) {
-                        return build_500(e.to_string());
-                    }
+fn delete_alias<S: Storage>(ctx: &Context<S>, alias: &str) -> BoxFut {
+    match ctx.storage.delete_alias(alias) {
+        Ok(Some(service)) => {
+            ctx.aliases.invalidate();
+            info!("Delete alias: alias={}, service={}", alias, service);
+            build_alias_response(alias.to_owned(), service)
+        }
+        Ok(None) => wrap_future(build_error_response(
+            StatusCode::NOT_FOUND,
+            ErrorId::AliasNotFound,
+            format!("Not found the alias: {}", alias),
+        )),
+        Err(e) => wrap_future(build_storage_error(ctx, e.to_string())),
+    }
+}
 
-                    info!("Build 202 response");
-                    Response::builder()
-                        .status(StatusCode::ACCEPTED)
-                        .body(Body::empty())
-                        .unwrap()
-                }
-                Err(m) => {
-                    let mut msg = "Invalid JSON string: ".to_owned();
-                    msg.push_str(&m.to_string());
-                    build_400(msg)
-                }
-            },
-            Err(_) => build_400("Invalid UTF-8 string".to_owned()),
-        });
-    Box::new(f)
+fn build_alias_response(alias: String, service: String) -> BoxFut {
+    match serde_json::to_string(&AliasResponse { alias, service }) {
+        Ok(body) => {
+            info!("Build 200 response: body-size={}", body.len());
+            wrap_future(Response::new(Body::from(body)))
+        }
+        Err(e) => res_500(e.to_string()),
+    }
 }
 
-fn delete_host<S: Storage>(s: &S, name: &str, ip: String, port_string: &str) -> BoxFut {
-    let port = match port_string.parse() {
+fn get_registration<S: Storage>(ctx: &Context<S>, req: Request<Body>, name: &str) -> BoxFut {
+    let params = query_params(req.uri());
+    let fields = match query_param(&params, "fields").map(parse_fields) {
+        Some(Ok(v)) => Some(v),
+        Some(Err(msg)) => return res_400(msg),
+        None => None,
+    };
+    let mut excluded = Vec::new();
+    for (_, v) in params.iter().filter(|(k, _)| k == "exclude") {
+        match parse_exclude(v) {
+            Ok(v) => excluded.push(v),
+            Err(msg) => return res_400(msg),
+        }
+    }
+    let sample = match query_param(&params, "sample").map(parse_sample) {
+        Some(Ok(v)) => Some(v),
+        Some(Err(msg)) => return res_400(msg),
+        None => None,
+    };
+    let shuffle = match query_param(&params, "shuffle") {
+        Some("true") => true,
+        Some("false") | None => false,
+        Some(v) => return res_400(format!("shuffle must be true or false: {}", v)),
+    };
+    let region = match query_param(&params, "region") {
+        Some("local") => match ctx.config.region {
+            Some(ref v) => Some(v.to_owned()),
+            None => return res_400("region=local needs SDS_REGION".to_owned()),
+        },
+        Some(v) => Some(v.to_owned()),
+        None => None,
+    };
+    let service = match resolve_service(ctx, name) {
         Ok(v) => v,
-        Err(_e) => return res_400(format!("Given port is invalid as integer: {}", port_string)),
+        Err(res) => return wrap_future(res),
     };
-
-    match s.delete_item(name, ip, port) {
-        Ok(res) => {
-            if res.is_none() {
-                let r = ErrorResponse {
-                    id: ErrorId::HostNotFound,
-                    reason: "Not found the entry".to_owned(),
-                };
-                let body = match serde_json::to_string(&r) {
-                    Ok(v) => v,
-                    Err(e) => return res_500(e.to_string()),
-                };
-                return res_400(body);
-            }
+    let file_config = ctx.config.file.current();
+    // Read before the hosts, so that the response reflects at least this index.
+    let index =
+        match virtual_service::query_index(&ctx.storage, &file_config.virtual_services, &service) {
+            Ok(v) => v,
+            Err(e) => return wrap_future(build_storage_error(ctx, e.to_string())),
+        };
+    let mut hosts =
+        match virtual_service::query_hosts(&ctx.storage, &file_config.virtual_services, &service) {
+            Ok(v) => v,
+            Err(e) => return wrap_future(build_storage_error(ctx, e.to_string())),
+        };
+    let freshness =
+        virtual_service::query_freshness(&ctx.storage, &file_config.virtual_services, &service);
+    // The queries may have outlasted the deadline, the client has given up on the response then.
+    if ctx.deadline_exceeded() {
+        return wrap_future(build_504("Deadline exceeded".to_owned()));
+    }
+    hosts.retain(|h| {
+        !excluded
+            .iter()
+            .any(|(ip, port)| *ip == h.ip_address && *port == h.port)
+    });
+    sort_hosts(&mut hosts);
+    let service_config = file_config.services.get(&service);
+    let pinning = service_config.map_or(RegionPinning::Off, |c| c.region_pinning);
+    // The region of the request restricts hosts unless the service prefers the region.
+    let (region, pinning) = match region {
+        Some(r) if pinning == RegionPinning::Off => (Some(r), RegionPinning::Restrict),
+        Some(r) => (Some(r), pinning),
+        None => (ctx.config.region.clone(), pinning),
+    };
+    if let Some(region) = region {
+        hosts = pin_hosts(hosts, &region, pinning);
+    }
+    truncate_hosts(ctx, &service, &mut hosts, service_config);
+    // Sampled hosts stay sorted unless shuffled.
+    if let Some(n) = sample {
+        if n < hosts.len() {
+            hosts = hosts
+                .choose_multiple(&mut rand::thread_rng(), n)
+                .cloned()
+                .collect();
+            sort_hosts(&mut hosts);
         }
+    }
+    if shuffle {
+        hosts.shuffle(&mut rand::thread_rng());
+    }
+    let now = match ctx.epoch_now() {
+        Ok(v) => v,
+        Err(e) => return res_500(e),
+    };
+    let index_field = match index.map(serde_json::to_value) {
+        Some(Ok(v)) => format!(",\"index\":{}", v),
+        Some(Err(e)) => return res_500(e.to_string()),
+        None => String::new(),
+    };
+    let freshness_field = match serde_json::to_value(freshness) {
+        Ok(v) => v,
         Err(e) => return res_500(e.to_string()),
+    };
+    let chunks = RegistrationChunks {
+        head: Some(format!(
+            "{{\"env\":\"production\",\"freshness\":{},\"hosts\":[",
+            freshness_field
+        )),
+        tail: Some(format!(
+            "]{},\"service\":{}}}",
+            index_field,
+            Value::from(name)
+        )),
+        hosts: hosts.into_iter(),
+        written: 0,
+        now,
+        legacy: ctx.config.legacy_time_fields,
+        fields,
+    };
+    if chunks.hosts.len() <= HOSTS_PER_CHUNK {
+        let body: Result<String, String> = chunks.collect();
+        return match body {
+            Ok(body) => {
+                info!("Build 200 response: body-size={}", body.len());
+                let mut res = Response::new(Body::from(body));
+                set_index_header(&mut res, index.map(|i| i.service));
+                set_freshness_header(&mut res, freshness);
+                wrap_future(res)
+            }
+            Err(e) => res_500(e),
+        };
     }
+    info!("Build 200 response: streaming hosts={}", chunks.hosts.len());
+    let mut res = Response::new(Body::wrap_stream(stream::iter_result(chunks)));
+    set_index_header(&mut res, index.map(|i| i.service));
+    set_freshness_header(&mut res, freshness);
+    wrap_future(res)
+}
 
-    info!("Build 202 response");
-    wrap_future(
-        Response::builder()
-            .status(StatusCode::ACCEPTED)
-            .body(Body::empty())
-            .unwrap(),
-    )
+fn set_index_header(res: &mut Response<Body>, index: Option<u64>) {
+    if let Some(index) = index {
+        res.headers_mut()
+            .insert(INDEX_HEADER, hyper::header::HeaderValue::from(index));
+    }
 }
 
-fn convert_param_to_host(
-    name: &str,
+fn set_freshness_header(res: &mut Response<Body>, freshness: Freshness) {
+    if let Ok(v) = hyper::header::HeaderValue::from_str(&freshness.header_value()) {
+        res.headers_mut().insert(FRESHNESS_HEADER, v);
+    }
+}
+
+// Responses of services with more hosts are streamed in chunks of this many hosts, so that a huge
+// service doesn't need its whole response in memory at once.
+const HOSTS_PER_CHUNK: usize = 1000;
+
+// Serializes a v1 SDS response lazily, a chunk of hosts per item. The body stream polls the next
+// chunk only once the previous one is written out to the client. Keys are in the same order as
+// serializing the whole Registration through Value.
+struct RegistrationChunks {
+    head: Option<String>,
+    tail: Option<String>,
+    hosts: std::vec::IntoIter<Host>,
+    written: usize,
+    now: u64,
+    legacy: bool,
+    fields: Option<Vec<String>>,
+}
+
+impl Iterator for RegistrationChunks {
+    type Item = Result<String, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = match self.head.take() {
+            Some(v) => v,
+            None => String::new(),
+        };
+        for h in self.hosts.by_ref().take(HOSTS_PER_CHUNK) {
+            let mut m = match serde_json::to_value(&h) {
+                Ok(Value::Object(m)) => m,
+                Ok(_) => return Some(Err("Host isn't serialized as an object".to_owned())),
+                Err(e) => return Some(Err(e.to_string())),
+            };
+            add_time_fields(&mut m, self.now, self.legacy);
+            if let Some(ref fields) = self.fields {
+                m = project_host(m, fields);
+            }
+            if self.written > 0 {
+                chunk.push(',');
+            }
+            chunk.push_str(&Value::Object(m).to_string());
+            self.written += 1;
+        }
+        if self.hosts.len() == 0 {
+            if let Some(tail) = self.tail.take() {
+                chunk.push_str(&tail);
+            }
+        }
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(Ok(chunk))
+        }
+    }
+}
+
+// Adds RFC 3339 variants of the timestamps and the seconds left before expiry to the serialized
+// host. The epoch and space separated originals are removed unless `legacy`.
+fn add_time_fields(m: &mut Map<String, Value>, now: u64, legacy: bool) {
+    let checked_in_at = m
+        .get("last_check_in")
+        .and_then(|v| v.as_str())
+        .and_then(|s| chrono::DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%:z").ok())
+        .map(|t| format_rfc3339(t.with_timezone(&chrono::Utc)));
+    if let Some(v) = checked_in_at {
+        m.insert("checked_in_at".to_owned(), Value::from(v));
+    }
+    if let Some(expire_time) = m.get("expire_time").and_then(|v| v.as_u64()) {
+        if let Some(v) = epoch_to_rfc3339(expire_time) {
+            m.insert("expires_at".to_owned(), Value::from(v));
+        }
+        m.insert(
+            "expires_in_seconds".to_owned(),
+            Value::from(expire_time.saturating_sub(now)),
+        );
+    }
+    if !legacy {
+        m.remove("last_check_in");
+        m.remove("expire_time");
+    }
+}
+
+fn epoch_to_rfc3339(secs: u64) -> Option<String> {
+    use chrono::TimeZone;
+
+    if secs > i64::max_value() as u64 {
+        return None;
+    }
+    chrono::Utc
+        .timestamp_opt(secs as i64, 0)
+        .single()
+        .map(format_rfc3339)
+}
+
+fn format_rfc3339(t: chrono::DateTime<chrono::Utc>) -> String {
+    t.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+// Leaves only the given fields in the serialized host.
+fn project_host(m: Map<String, Value>, fields: &[String]) -> Map<String, Value> {
+    m.into_iter()
+        .filter(|(k, _)| fields.iter().any(|f| f == k))
+        .collect()
+}
+
+fn get_registration_v2<S: Storage>(ctx: &Context<S>, req: Request<Body>) -> BoxFut {
+    let ctx = ctx.clone();
+    let f = req.into_body().concat2().and_then(move |buffer| -> BoxFut {
+        match parse_discovery_request(&buffer) {
+            Ok(d_req) => query_clusters(ctx, d_req.resource_names, d_req.node.locality),
+            Err(msg) => {
+                debug!("invalid request: {:?}", String::from_utf8_lossy(&buffer));
+                res_400(msg)
+            }
+        }
+    });
+    Box::new(f)
+}
+
+// Queries the clusters concurrently on the query pool, at most eds_query_concurrency at a time.
+// Resources are returned in the requested order.
+fn query_clusters<S: Storage>(
+    ctx: Context<S>,
+    names: Vec<String>,
+    node_locality: Option<Locality>,
+) -> BoxFut {
+    let concurrency = ctx.config.eds_query_concurrency.max(1);
+    let query_ctx = ctx.clone();
+    let f = stream::iter_ok(names)
+        .map(move |name| {
+            let ctx = query_ctx.clone();
+            query_ctx.query_pool.spawn_fn(move || {
+                if ctx.deadline_exceeded() {
+                    return Err(build_504("Deadline exceeded".to_owned()));
+                }
+                let service = resolve_service(&ctx, &name)?;
+                let file_config = ctx.config.file.current();
+                let vs = &file_config.virtual_services;
+                // Read before the hosts, so that the response reflects at least this index.
+                let index = virtual_service::query_index(&ctx.storage, vs, &service)
+                    .map_err(|e| build_storage_error(&ctx, e.to_string()))?;
+                let hosts = virtual_service::query_hosts(&ctx.storage, vs, &service)
+                    .map_err(|e| build_storage_error(&ctx, e.to_string()))?;
+                let freshness = virtual_service::query_freshness(&ctx.storage, vs, &service);
+                if ctx.deadline_exceeded() {
+                    return Err(build_504("Deadline exceeded".to_owned()));
+                }
+                Ok((name, service, index, freshness, hosts))
+            })
+        })
+        .buffered(concurrency)
+        .collect()
+        .then(move |r| -> Result<Response<Body>, hyper::Error> {
+            let results = match r {
+                Ok(v) => v,
+                Err(res) => return Ok(res),
+            };
+            let now = match ctx.epoch_now() {
+                Ok(v) => v,
+                Err(e) => return Ok(build_500(e)),
+            };
+            let file_config = ctx.config.file.current();
+            // The region of the requesting Envoy, or of sds itself.
+            let region = node_locality
+                .as_ref()
+                .map(|l| l.region.to_owned())
+                .filter(|r| !r.is_empty())
+                .or_else(|| ctx.config.region.clone());
+            let mut resources = Vec::new();
+            // Envoy rejects unknown fields of DiscoveryResponse, so the index is only in the
+            // header. Every resource reflects at least the smallest index read.
+            let mut index = None;
+            let mut freshness = Freshness::live();
+            for (name, service, service_index, service_freshness, mut hosts) in results {
+                freshness = freshness.merge(service_freshness);
+                if let Some(i) = service_index {
+                    index = Some(index.map_or(i.global, |v: u64| v.min(i.global)));
+                }
+                let service_config = file_config.services.get(&service);
+                let pinning = service_config.map_or(RegionPinning::Off, |c| c.region_pinning);
+                sort_hosts(&mut hosts);
+                if pinning == RegionPinning::Restrict {
+                    if let Some(region) = region.as_ref() {
+                        hosts = pin_hosts(hosts, region, pinning);
+                    }
+                }
+                truncate_hosts(&ctx, &service, &mut hosts, service_config);
+                let degraded = ctx.feedback.demoted_endpoints(&service);
+                let mut lle_vec = hosts_to_locality_lb_endpoints(
+                    hosts,
+                    service_config,
+                    &degraded,
+                    now,
+                    node_locality.as_ref(),
+                );
+                if pinning == RegionPinning::Prefer {
+                    if let Some(region) = region.as_ref() {
+                        demote_other_regions(&mut lle_vec, region);
+                    }
+                }
+                resources.push(ClusterLoadAssignment {
+                    type_url: EDS_TYPE_URL.to_string(),
+                    policy: build_policy(service_config),
+                    cluster_name: name,
+                    endpoints: lle_vec,
+                });
+            }
+
+            let d_res = EdsDiscoveryResponse {
+                version_info: Uuid::new_v4().to_string(),
+                resources,
+            };
+            let body = match serde_json::to_string(&d_res) {
+                Ok(v) => v,
+                Err(e) => return Ok(build_500(e.to_string())),
+            };
+            info!("Build 200 response: body-size={}", body.len());
+            let mut res = Response::new(Body::from(body));
+            set_index_header(&mut res, index);
+            set_freshness_header(&mut res, freshness);
+            Ok(res)
+        });
+    Box::new(f)
+}
+
+fn register_hosts<S: Storage>(ctx: Context<S>, req: Request<Body>, name: &str) -> BoxFut {
+    let name = name.to_owned();
+    let f = req.into_body().concat2().and_then(move |buffer| -> BoxFut {
+        let param = match parse_registration_param(&buffer, ctx.config.strict_json) {
+            Ok(v) => v,
+            Err(msg) => return res_400(msg),
+        };
+        if let Err(res) = check_registration_rate(&ctx, &name) {
+            return wrap_future(res);
+        }
+        let ttl = ctx.storage.ttl();
+        let host = match convert_param_to_host(&name, param, ttl, &*ctx.config.clock) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{}", e);
+                return res_500(e);
+            }
+        };
+        let admission = match ctx.admission {
+            Some(ref admission) => admission.review(&name, host),
+            None => {
+                return with_write_lock(&ctx, &name, move |ctx, name| {
+                    store_registration(ctx, name, host)
+                })
+            }
+        };
+        Box::new(admission.then(move |r| -> BoxFut {
+            match r {
+                Ok(Decision::Allow(host)) => with_write_lock(&ctx, &name, move |ctx, name| {
+                    store_registration(ctx, name, host)
+                }),
+                Ok(Decision::Deny(reason)) => {
+                    warn!("Registration denied: service={}, reason={}", name, reason);
+                    wrap_future(build_error_response(
+                        StatusCode::FORBIDDEN,
+                        ErrorId::AdmissionDenied,
+                        reason,
+                    ))
+                }
+                Ok(Decision::Unavailable(reason)) => wrap_future(build_error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    ErrorId::AdmissionUnavailable,
+                    reason,
+                )),
+                Err(()) => res_500("Admission failed".to_owned()),
+            }
+        }))
+    });
+    Box::new(f)
+}
+
+// Runs the write holding the write lock of the service. The thread isn't blocked while waiting
+// for the lock.
+fn with_write_lock<S, F>(ctx: &Context<S>, name: &str, f: F) -> BoxFut
+where
+    S: Storage,
+    F: FnOnce(&Context<S>, &str) -> Response<Body> + Send + 'static,
+{
+    let ctx = ctx.clone();
+    let name = name.to_owned();
+    let lock = ctx.write_locks.lock(&name);
+    Box::new(lock.then(move |r| {
+        Ok(match r {
+            Ok(guard) => {
+                let res = f(&ctx, &name);
+                drop(guard);
+                res
+            }
+            Err(()) => build_500(format!("Lost the write lock of {}", name)),
+        })
+    }))
+}
+
+fn store_registration<S: Storage>(ctx: &Context<S>, name: &str, mut host: Host) -> Response<Body> {
+    // Check-ins of a draining host keep it draining, and keep the time of the first
+    // registration for slow-start.
+    let existing = match ctx.storage.get_item(name, &host.ip_address, host.port) {
+        Ok(v) => v,
+        Err(e) => return build_storage_error(ctx, e.to_string()),
+    };
+    if let Err(res) = check_duplicate_host(ctx, name, &host, existing.is_none()) {
+        return res;
+    }
+    match existing {
+        Some(existing) => {
+            // expire_time of the new record minus TTL is the time of this check-in.
+            let checked_in_at = host.expire_time.saturating_sub(ctx.storage.ttl());
+            ctx.metrics
+                .observe_time_to_expiry(name, existing.expire_time.saturating_sub(checked_in_at));
+            host.drain_started_at = existing.drain_started_at;
+            if existing.registered_at.is_some() {
+                host.registered_at = existing.registered_at;
+            }
+        }
+        None if limits_hosts(ctx, name) => {
+            // Both checks count the hosts of the service, query them once.
+            let hosts = match ctx.storage.query_items(name) {
+                Ok(v) => v,
+                Err(e) => return build_storage_error(ctx, e.to_string()),
+            };
+            if let Err(res) = check_max_hosts(ctx, name, &hosts) {
+                return res;
+            }
+            if let Err(res) = check_quotas(ctx, name, &hosts) {
+                return res;
+            }
+        }
+        None => {}
+    }
+    if let Err(e) = ctx.storage.store_item(name, host.clone()) {
+        return build_storage_error(ctx, e.to_string());
+    }
+    ctx.events.emit(EventKind::Register, name, host);
+
+    info!("Build 202 response");
+    build_response(
+        Response::builder().status(StatusCode::ACCEPTED),
+        Body::empty(),
+    )
+}
+
+// Whether max_hosts or the host quotas of the namespace limit new hosts of the service.
+fn limits_hosts<S>(ctx: &Context<S>, name: &str) -> bool {
+    let file_config = ctx.config.file.current();
+    let limits = file_config
+        .quotas
+        .limits(file_config.quotas.namespace(name));
+    file_config
+        .services
+        .get(name)
+        .and_then(|c| c.max_hosts)
+        .is_some()
+        || limits.max_hosts_per_service.is_some()
+        || limits.max_services.is_some()
+}
+
+// Rejects a new host of the service which already has max_hosts hosts. Check-ins of the
+// registered hosts are always accepted.
+fn check_max_hosts<S>(ctx: &Context<S>, name: &str, hosts: &[Host]) -> Result<(), Response<Body>> {
+    let file_config = ctx.config.file.current();
+    let max_hosts = match file_config.services.get(name).and_then(|c| c.max_hosts) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    if hosts.len() < max_hosts {
+        return Ok(());
+    }
+    warn!(
+        "Reject registration over max_hosts: service={}, hosts={}, max_hosts={}",
+        name,
+        hosts.len(),
+        max_hosts
+    );
+    let r = ErrorResponse {
+        id: ErrorId::TooManyHosts,
+        reason: format!(
+            "The service already has {} hosts, which reaches max_hosts {}",
+            hosts.len(),
+            max_hosts
+        ),
+    };
+    let body = serde_json::to_string(&r).map_err(|e| build_500(e.to_string()))?;
+    info!("Build 403 response");
+    Err(build_response(
+        Response::builder().status(StatusCode::FORBIDDEN),
+        Body::from(body),
+    ))
+}
+
+// Rejects registrations over max_registrations_per_min of the namespace with 429.
+fn check_registration_rate<S>(ctx: &Context<S>, name: &str) -> Result<(), Response<Body>> {
+    let file_config = ctx.config.file.current();
+    let quotas = &file_config.quotas;
+    let namespace = quotas.namespace(name);
+    let per_min = match quotas.limits(namespace).max_registrations_per_min {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    let retry_after = match ctx
+        .registration_rates
+        .try_acquire(namespace, per_min, Instant::now())
+    {
+        Ok(()) => return Ok(()),
+        Err(v) => v,
+    };
+    let violation = QuotaViolation {
+        namespace: namespace.to_owned(),
+        quota: "max_registrations_per_min",
+        limit: per_min,
+        current: None,
+    };
+    // Rounds up so that clients don't retry before a slot is available.
+    let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    Err(build_quota_error(
+        ctx,
+        name,
+        violation,
+        StatusCode::TOO_MANY_REQUESTS,
+        Some(retry_after_secs),
+    ))
+}
+
+// Rejects a new host over max_hosts_per_service or max_services of the namespace with 403.
+fn check_quotas<S: Storage>(
+    ctx: &Context<S>,
+    name: &str,
+    hosts: &[Host],
+) -> Result<(), Response<Body>> {
+    let file_config = ctx.config.file.current();
+    let quotas = &file_config.quotas;
+    let namespace = quotas.namespace(name);
+    let limits = quotas.limits(namespace);
+    if let Some(limit) = limits.max_hosts_per_service {
+        let current = hosts.len() as u64;
+        if current >= limit {
+            let violation = QuotaViolation {
+                namespace: namespace.to_owned(),
+                quota: "max_hosts_per_service",
+                limit,
+                current: Some(current),
+            };
+            return Err(build_quota_error(
+                ctx,
+                name,
+                violation,
+                StatusCode::FORBIDDEN,
+                None,
+            ));
+        }
+    }
+    // Only the first host of a service adds a service to the namespace.
+    if let (Some(limit), true) = (limits.max_services, hosts.is_empty()) {
+        let services = ctx
+            .storage
+            .list_services()
+            .map_err(|e| build_storage_error(ctx, e.to_string()))?;
+        let current = services
+            .iter()
+            .filter(|s| s.as_str() != name && quotas.namespace(s) == namespace)
+            .count() as u64;
+        if current >= limit {
+            let violation = QuotaViolation {
+                namespace: namespace.to_owned(),
+                quota: "max_services",
+                limit,
+                current: Some(current),
+            };
+            return Err(build_quota_error(
+                ctx,
+                name,
+                violation,
+                StatusCode::FORBIDDEN,
+                None,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn build_quota_error<S>(
+    ctx: &Context<S>,
+    name: &str,
+    violation: QuotaViolation,
+    status: StatusCode,
+    retry_after: Option<u64>,
+) -> Response<Body> {
+    warn!(
+        "Reject registration over quota: service={}, namespace={}, quota={}, limit={}",
+        name, violation.namespace, violation.quota, violation.limit
+    );
+    ctx.metrics.inc_quota_rejections(name, violation.quota);
+    let r = QuotaErrorResponse {
+        id: ErrorId::QuotaExceeded,
+        reason: format!(
+            "Namespace {:?} exceeds {} quota {}",
+            violation.namespace, violation.quota, violation.limit
+        ),
+        quota: violation,
+    };
+    let body = match serde_json::to_string(&r) {
+        Ok(v) => v,
+        Err(e) => return build_500(e.to_string()),
+    };
+    info!("Build {} response", status.as_u16());
+    let mut builder = Response::builder();
+    builder.status(status);
+    if let Some(secs) = retry_after {
+        builder.header("retry-after", secs.to_string().as_str());
+    }
+    build_response(&mut builder, Body::from(body))
+}
+
+// Looks for hosts of other services with the same ip:port, or the same ip in the "ip" scope, and
+// records them as a conflict. New hosts are rejected with the "reject" action, while check-ins of
+// registered hosts are only flagged so that both services don't lose the host at once.
+fn check_duplicate_host<S: Storage>(
+    ctx: &Context<S>,
+    name: &str,
+    host: &Host,
+    is_new: bool,
+) -> Result<(), Response<Body>> {
+    let policy = ctx.config.file.current().duplicate_hosts.clone();
+    if policy.action == DuplicateAction::Off {
+        return Ok(());
+    }
+    let hosts = ctx
+        .storage
+        .query_by_ip(&host.ip_address)
+        .map_err(|e| build_storage_error(ctx, e.to_string()))?;
+    let mut services: Vec<String> = hosts
+        .into_iter()
+        .filter(|h| h.service != name)
+        .filter(|h| policy.scope == DuplicateScope::Ip || h.port == host.port)
+        .map(|h| h.service)
+        .collect();
+    services.sort();
+    services.dedup();
+    if services.is_empty() {
+        ctx.conflicts.resolve(name, &host.ip_address, host.port);
+        return Ok(());
+    }
+    let rejected = is_new && policy.action == DuplicateAction::Reject;
+    warn!(
+        "Duplicate host found: service={}, ip={}, port={}, other-services={}, rejected={}",
+        name,
+        host.ip_address,
+        host.port,
+        services.join(","),
+        rejected
+    );
+    let now = ctx.epoch_now().map_err(build_500)?;
+    ctx.conflicts.record(Conflict {
+        service: name.to_owned(),
+        ip_address: host.ip_address.to_owned(),
+        port: host.port,
+        conflicting_services: services.clone(),
+        rejected,
+        last_seen: now,
+    });
+    if !rejected {
+        return Ok(());
+    }
+    let r = ErrorResponse {
+        id: ErrorId::DuplicateHost,
+        reason: format!(
+            "The host is already registered under other services: {}",
+            services.join(", ")
+        ),
+    };
+    let body = serde_json::to_string(&r).map_err(|e| build_500(e.to_string()))?;
+    info!("Build 409 response");
+    Err(build_response(
+        Response::builder().status(StatusCode::CONFLICT),
+        Body::from(body),
+    ))
+}
+
+// Truncates the hosts to max_hosts of the service so that a runaway registration doesn't blow
+// up clients.
+fn truncate_hosts<S>(
+    ctx: &Context<S>,
+    name: &str,
+    hosts: &mut Vec<Host>,
+    service_config: Option<&ServiceConfig>,
+) {
+    if let Some(max_hosts) = service_config.and_then(|c| c.max_hosts) {
+        if hosts.len() > max_hosts {
+            warn!(
+                "Truncate hosts to max_hosts: service={}, hosts={}, max_hosts={}",
+                name,
+                hosts.len(),
+                max_hosts
+            );
+            hosts.truncate(max_hosts);
+            ctx.metrics.inc_truncated_responses(name);
+        }
+    }
+}
+
+fn report_feedback<S: Storage>(ctx: Context<S>, req: Request<Body>, name: &str) -> BoxFut {
+    let name = name.to_owned();
+    let f = req.into_body().concat2().map(move |buffer| {
+        match parse_feedback_param(&buffer, ctx.config.strict_json) {
+            Ok(param) => {
+                let ip_port = format!("{}:{}", param.ip, param.port);
+                let demoted = ctx
+                    .feedback
+                    .report(&name, &ip_port, param.requests, param.failures);
+                info!(
+                    "Build 202 response: service={}, ip_port={}, demoted={}",
+                    name, ip_port, demoted
+                );
+                build_response(
+                    Response::builder().status(StatusCode::ACCEPTED),
+                    Body::empty(),
+                )
+            }
+            Err(msg) => build_400(msg),
+        }
+    });
+    Box::new(f)
+}
+
+// Replays the original response for requests retried with the same Idempotency-Key header.
+// Keys are scoped, so the same key can be used for different resources.
+fn with_idempotency<S, F>(ctx: Context<S>, req: Request<Body>, scope: String, handler: F) -> BoxFut
+where
+    S: Storage,
+    F: FnOnce(Context<S>, Request<Body>) -> BoxFut + Send + 'static,
+{
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        None => return handler(ctx, req),
+        Some(v) => match v.to_str() {
+            Ok(k) if !k.is_empty() => format!("{} {}", scope, k),
+            _ => return res_400(format!("Invalid {} header", IDEMPOTENCY_KEY_HEADER)),
+        },
+    };
+    let (parts, body) = req.into_parts();
+    let f = body.concat2().and_then(move |buffer| -> BoxFut {
+        let pending = match ctx.idempotency.begin(&key, fingerprint(&buffer)) {
+            Lookup::Miss(v) => v,
+            Lookup::Hit(cached) => {
+                info!("Replay response for idempotency key: key={}", key);
+                let mut res = Response::new(Body::from(cached.body));
+                *res.status_mut() = cached.status;
+                *res.headers_mut() = cached.headers;
+                res.headers_mut().insert(
+                    IDEMPOTENT_REPLAYED_HEADER,
+                    hyper::header::HeaderValue::from_static("true"),
+                );
+                return wrap_future(res);
+            }
+            Lookup::InProgress => {
+                return res_409(format!(
+                    "A request with the same {} is in progress",
+                    IDEMPOTENCY_KEY_HEADER
+                ))
+            }
+            Lookup::Mismatch => {
+                return res_422(format!(
+                    "{} is already used for a different request",
+                    IDEMPOTENCY_KEY_HEADER
+                ))
+            }
+        };
+
+        // Failures and dropped futures drop `pending`, which aborts the key.
+        let req = Request::from_parts(parts, Body::from(buffer));
+        Box::new(handler(ctx, req).and_then(move |res| {
+            let (parts, body) = res.into_parts();
+            body.concat2().map(move |buffer| {
+                pending.complete(CachedResponse {
+                    status: parts.status,
+                    headers: parts.headers.clone(),
+                    body: buffer.to_vec(),
+                });
+                Response::from_parts(parts, Body::from(buffer))
+            })
+        }))
+    });
+    Box::new(f)
+}
+
+fn delete_host<S: Storage>(ctx: &Context<S>, name: &str, ip: String, port_string: &str) -> BoxFut {
+    let port = match parse_port(port_string) {
+        Ok(v) => u64::from(v),
+        Err(msg) => return res_400(msg),
+    };
+
+    with_write_lock(ctx, name, move |ctx, name| {
+        match ctx.storage.delete_item(name, ip, port) {
+            Ok(Some(host)) => ctx.events.emit(EventKind::Delete, name, host),
+            Ok(None) => return build_host_not_found(),
+            Err(e) => return build_storage_error(ctx, e.to_string()),
+        }
+
+        info!("Build 202 response");
+        build_response(
+            Response::builder().status(StatusCode::ACCEPTED),
+            Body::empty(),
+        )
+    })
+}
+
+// Starts draining the host. Its EDS weight ramps down over drain_period_sec of the service and
+// it's left out of EDS afterwards until it expires or gets deregistered.
+fn drain_host<S: Storage>(ctx: &Context<S>, name: &str, ip: &str, port_string: &str) -> BoxFut {
+    let port = match parse_port(port_string) {
+        Ok(v) => v,
+        Err(msg) => return res_400(msg),
+    };
+    let ip = ip.to_owned();
+    with_write_lock(ctx, name, move |ctx, name| {
+        let mut host = match ctx.storage.get_item(name, &ip, port) {
+            Ok(Some(v)) => v,
+            Ok(None) => return build_host_not_found(),
+            Err(e) => return build_storage_error(ctx, e.to_string()),
+        };
+        if host.drain_started_at.is_none() {
+            host.drain_started_at = match ctx.epoch_now() {
+                Ok(v) => Some(v),
+                Err(e) => return build_500(e),
+            };
+            if let Err(e) = ctx.storage.store_item(name, host) {
+                return build_storage_error(ctx, e.to_string());
+            }
+            info!("Start draining: service={}, ip={}, port={}", name, ip, port);
+        }
+
+        info!("Build 202 response");
+        build_response(
+            Response::builder().status(StatusCode::ACCEPTED),
+            Body::empty(),
+        )
+    })
+}
+
+// Merges the partial tag map into the tags of the host. Unlike a registration, it doesn't extend
+// expire_time.
+fn patch_tags<S: Storage>(
+    ctx: Context<S>,
+    req: Request<Body>,
+    name: String,
+    ip: String,
+    port_string: &str,
+) -> BoxFut {
+    let port = match parse_port(port_string) {
+        Ok(v) => v,
+        Err(msg) => return res_400(msg),
+    };
+    let f = req.into_body().concat2().and_then(move |buffer| -> BoxFut {
+        let patch = match parse_tag_patch(&buffer) {
+            Ok(v) => v,
+            Err(msg) => return res_400(msg),
+        };
+        with_write_lock(&ctx, &name, move |ctx, name| {
+            let mut host = match ctx.storage.get_item(name, &ip, port) {
+                Ok(Some(v)) => v,
+                Ok(None) => return build_host_not_found(),
+                Err(e) => return build_storage_error(ctx, e.to_string()),
+            };
+            host.tags = match apply_tag_patch(&host.tags, &patch) {
+                Ok(v) => v,
+                Err(msg) => return build_400(msg),
+            };
+            if let Err(e) = ctx.storage.store_item(name, host.clone()) {
+                return build_storage_error(ctx, e.to_string());
+            }
+            info!("Update tags: service={}, ip={}, port={}", name, ip, port);
+            let body = match serde_json::to_string(&host.tags) {
+                Ok(v) => v,
+                Err(e) => return build_500(e.to_string()),
+            };
+            ctx.events.emit(EventKind::Update, name, host);
+            Response::new(Body::from(body))
+        })
+    });
+    Box::new(f)
+}
+
+fn build_host_not_found() -> Response<Body> {
+    let r = ErrorResponse {
+        id: ErrorId::HostNotFound,
+        reason: "Not found the entry".to_owned(),
+    };
+    match serde_json::to_string(&r) {
+        Ok(body) => build_400(body),
+        Err(e) => build_500(e.to_string()),
+    }
+}
+
+fn convert_param_to_host(
+    name: &str,
     p: RegistrationParam,
     ttl: u64,
-) -> Result<Host, time::SystemTimeError> {
-    let last_check_in = chrono::Utc::now()
+    clock: &dyn Clock,
+) -> Result<Host, String> {
+    let last_check_in = chrono::DateTime::<chrono::Utc>::from(clock.now())
         .format("%Y-%m-%d %H:%M:%S%:z")
         .to_string();
-    let expire_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + ttl;
+    let now = clock.epoch_secs()?;
+    let expire_time = now + ttl;
     Ok(Host {
         ip_address: p.ip,
         port: p.port,
@@ -317,12 +1956,28 @@ fn convert_param_to_host(
         revision: p.revision,
         service: name.to_owned(),
         tags: p.tags,
+        drain_started_at: None,
+        registered_at: Some(now),
+    })
+}
+
+fn parse_deadline(req: &Request<Body>) -> Result<Option<Instant>, String> {
+    let v = match req.headers().get(DEADLINE_HEADER) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    request::parse_deadline(v.as_bytes(), Instant::now()).map_err(|_| {
+        format!(
+            "{} header must be an integer in milliseconds",
+            DEADLINE_HEADER
+        )
     })
 }
 
 fn show_usage(_: Request<Body>) -> BoxFut {
     let usage = "GET /v1/registration/:service, POST /v1/registration/:service, DELETE \
-                 /v1/registration/:service/:ip_address";
+                 /v1/registration/:service/:ip_address, POST \
+                 /v1/registration/:service/:ip_address/drain, POST /v1/feedback/:service";
     wrap_future(Response::new(Body::from(usage)))
 }
 
@@ -330,39 +1985,289 @@ fn check_health(_: Request<Body>) -> BoxFut {
     wrap_future(Response::new(Body::from("ok")))
 }
 
+fn reload_config<S>(ctx: &Context<S>) -> BoxFut {
+    match ctx.config.file.reload() {
+        Ok(()) => {
+            info!("Build 200 response");
+            wrap_future(Response::new(Body::from("reloaded")))
+        }
+        Err(e) => {
+            error!("Failed to reload config file: {}", e);
+            res_400(e)
+        }
+    }
+}
+
+fn show_maintenance<S>(ctx: &Context<S>) -> BoxFut {
+    let body = match serde_json::to_string(&ctx.maintenance.state()) {
+        Ok(v) => v,
+        Err(e) => return res_500(e.to_string()),
+    };
+    info!("Build 200 response: body-size={}", body.len());
+    wrap_future(Response::new(Body::from(body)))
+}
+
+// Turns maintenance mode on or off and responds the resulting state.
+fn set_maintenance<S: Storage>(ctx: Context<S>, req: Request<Body>) -> BoxFut {
+    let f = req.into_body().concat2().and_then(move |buffer| -> BoxFut {
+        match parse_maintenance_param(&buffer) {
+            Ok(param) => {
+                if param.enabled {
+                    warn!("Enter maintenance mode");
+                    ctx.maintenance.enable(param.message);
+                } else {
+                    warn!("Leave maintenance mode");
+                    ctx.maintenance.disable();
+                }
+                show_maintenance(&ctx)
+            }
+            Err(msg) => res_400(msg),
+        }
+    });
+    Box::new(f)
+}
+
+fn show_slo<S>(ctx: &Context<S>) -> BoxFut {
+    let file_config = ctx.config.file.current();
+    let statuses = ctx.slo.report(&file_config.slos, Instant::now());
+    let body = match serde_json::to_string(&statuses) {
+        Ok(v) => v,
+        Err(e) => return res_500(e.to_string()),
+    };
+    info!("Build 200 response: body-size={}", body.len());
+    wrap_future(Response::new(Body::from(body)))
+}
+
+fn show_payload_log<S>(ctx: &Context<S>) -> BoxFut {
+    let body = match serde_json::to_string(&*ctx.payload_log.config()) {
+        Ok(v) => v,
+        Err(e) => return res_500(e.to_string()),
+    };
+    info!("Build 200 response: body-size={}", body.len());
+    wrap_future(Response::new(Body::from(body)))
+}
+
+// Replaces the config of payload logging, empty routes turn it off.
+fn set_payload_log<S: Storage>(ctx: Context<S>, req: Request<Body>) -> BoxFut {
+    let f = req.into_body().concat2().and_then(move |buffer| -> BoxFut {
+        match parse_payload_log_config(&buffer) {
+            Ok(config) => {
+                warn!(
+                    "Set payload logging: routes={:?}, redact_keys={:?}, max_bytes={}",
+                    config.routes, config.redact_keys, config.max_bytes
+                );
+                ctx.payload_log.set_config(config);
+                show_payload_log(&ctx)
+            }
+            Err(msg) => res_400(msg),
+        }
+    });
+    Box::new(f)
+}
+
+fn show_metrics<S>(ctx: &Context<S>) -> BoxFut {
+    let mut body = ctx.metrics.render_prometheus();
+    body.push_str(&ctx.limiter.render_prometheus());
+    let file_config = ctx.config.file.current();
+    body.push_str(&ctx.slo.render_prometheus(&file_config.slos, Instant::now()));
+    wrap_future(build_response(
+        Response::builder().header("content-type", "text/plain; version=0.0.4"),
+        Body::from(body),
+    ))
+}
+
+fn show_versions<S>(ctx: &Context<S>) -> BoxFut {
+    let sunset = ctx.config.sds_v1_sunset.as_ref().map(String::as_str);
+    let body = match serde_json::to_string(&versions(sunset)) {
+        Ok(v) => v,
+        Err(e) => return res_500(e.to_string()),
+    };
+    info!("Build 200 response: body-size={}", body.len());
+    wrap_future(Response::new(Body::from(body)))
+}
+
+fn show_info<S: Storage>(ctx: &Context<S>) -> BoxFut {
+    let core_threads = get_core_threads().unwrap_or_else(num_cpus::get).max(1);
+    let info = Info {
+        build: BuildInfo::current(),
+        runtime: RuntimeInfo {
+            uptime_seconds: ctx.started_at.elapsed().as_secs(),
+            ttl_seconds: ctx.storage.ttl(),
+            core_threads,
+            eds_query_threads: ctx.config.eds_query_concurrency.max(1),
+            acceptors: if ctx.config.reuse_port {
+                core_threads
+            } else {
+                1
+            },
+        },
+    };
+    let body = match serde_json::to_string(&info) {
+        Ok(v) => v,
+        Err(e) => return res_500(e.to_string()),
+    };
+    info!("Build 200 response: body-size={}", body.len());
+    wrap_future(Response::new(Body::from(body)))
+}
+
+fn show_stats<S>(ctx: &Context<S>) -> BoxFut {
+    let body = match serde_json::to_string(&ctx.metrics.stats()) {
+        Ok(v) => v,
+        Err(e) => return res_500(e.to_string()),
+    };
+    info!("Build 200 response: body-size={}", body.len());
+    wrap_future(Response::new(Body::from(body)))
+}
+
+#[derive(Serialize, Debug)]
+struct ConflictsResponse {
+    conflicts: Vec<Conflict>,
+}
+
+fn show_conflicts<S>(ctx: &Context<S>) -> BoxFut {
+    let now = match ctx.epoch_now() {
+        Ok(v) => v,
+        Err(msg) => return res_500(msg),
+    };
+    let r = ConflictsResponse {
+        conflicts: ctx.conflicts.list(now),
+    };
+    let body = match serde_json::to_string(&r) {
+        Ok(v) => v,
+        Err(e) => return res_500(e.to_string()),
+    };
+    info!("Build 200 response: body-size={}", body.len());
+    wrap_future(Response::new(Body::from(body)))
+}
+
+fn check_readiness<S>(ctx: &Context<S>) -> BoxFut {
+    let state = ctx.readiness.state();
+    if ctx.readiness.is_ready() {
+        wrap_future(Response::new(Body::from(state.to_string())))
+    } else {
+        wrap_future(build_response(
+            Response::builder().status(StatusCode::SERVICE_UNAVAILABLE),
+            Body::from(state.to_string()),
+        ))
+    }
+}
+
 fn build_400(msg: String) -> Response<Body> {
     info!("Build 400 response");
-    Response::builder()
-        .status(StatusCode::BAD_REQUEST)
-        .body(Body::from(msg))
-        .unwrap()
+    build_response(
+        Response::builder().status(StatusCode::BAD_REQUEST),
+        Body::from(msg),
+    )
 }
 
 fn res_400(msg: String) -> BoxFut {
     wrap_future(build_400(msg))
 }
 
+fn res_409(msg: String) -> BoxFut {
+    info!("Build 409 response");
+    wrap_future(build_response(
+        Response::builder().status(StatusCode::CONFLICT),
+        Body::from(msg),
+    ))
+}
+
+fn res_422(msg: String) -> BoxFut {
+    info!("Build 422 response");
+    wrap_future(build_response(
+        Response::builder().status(StatusCode::UNPROCESSABLE_ENTITY),
+        Body::from(msg),
+    ))
+}
+
 fn res_404() -> BoxFut {
-    wrap_future(
-        Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())
-            .unwrap(),
-    )
+    wrap_future(build_response(
+        Response::builder().status(StatusCode::NOT_FOUND),
+        Body::empty(),
+    ))
 }
 
 fn build_500(msg: String) -> Response<Body> {
     info!("Build 500 response: body={}", msg);
-    Response::builder()
-        .status(StatusCode::INTERNAL_SERVER_ERROR)
-        .body(Body::from(msg))
-        .unwrap()
+    build_response(
+        Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR),
+        Body::from(msg),
+    )
 }
 
 fn res_500(msg: String) -> BoxFut {
     wrap_future(build_500(msg))
 }
 
+fn res_503_overloaded<S>(ctx: &Context<S>, route: &str) -> BoxFut {
+    info!("Build 503 response: route={}", route);
+    wrap_future(build_response(
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(
+                "retry-after",
+                ctx.limiter.retry_after().as_secs().to_string().as_str(),
+            ),
+        Body::from("Too many requests in flight"),
+    ))
+}
+
+fn res_503_maintenance(msg: String) -> BoxFut {
+    let r = ErrorResponse {
+        id: ErrorId::Maintenance,
+        reason: msg,
+    };
+    let body = match serde_json::to_string(&r) {
+        Ok(v) => v,
+        Err(e) => return res_500(e.to_string()),
+    };
+    info!("Build 503 response: maintenance");
+    wrap_future(build_response(
+        Response::builder().status(StatusCode::SERVICE_UNAVAILABLE),
+        Body::from(body),
+    ))
+}
+
+fn build_error_response(status: StatusCode, id: ErrorId, reason: String) -> Response<Body> {
+    let body = match serde_json::to_string(&ErrorResponse { id, reason }) {
+        Ok(v) => v,
+        Err(e) => return build_500(e.to_string()),
+    };
+    info!("Build {} response: body={}", status.as_u16(), body);
+    build_response(Response::builder().status(status), Body::from(body))
+}
+
+fn build_504(msg: String) -> Response<Body> {
+    info!("Build 504 response: body={}", msg);
+    build_response(
+        Response::builder().status(StatusCode::GATEWAY_TIMEOUT),
+        Body::from(msg),
+    )
+}
+
+// Storage failures after the request deadline are reported as 504 rather than 500.
+fn build_storage_error<S>(ctx: &Context<S>, msg: String) -> Response<Body> {
+    if ctx.deadline_exceeded() {
+        build_504(format!("Deadline exceeded: {}", msg))
+    } else {
+        build_500(msg)
+    }
+}
+
+// Finishes the builder without panicking. A builder holding an invalid part, e.g. a malformed
+// header value, falls back to a bare 500 response.
+fn build_response(builder: &mut response::Builder, body: Body) -> Response<Body> {
+    match builder.body(body) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("Failed to build response: {}", e);
+            let mut res = Response::new(Body::from("Internal Server Error"));
+            *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            res
+        }
+    }
+}
+
 fn wrap_future(res: Response<Body>) -> BoxFut {
     Box::new(future::ok(res))
 }
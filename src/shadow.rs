@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use log::{debug, info, warn};
+
+use super::metrics::Metrics;
+use super::storage::StorageError;
+use super::types::{Drift, Freshness, Host, RegistryIndex, SchemaCheck, Storage};
+
+// Reads queued for comparison at most. Reads over it skip the comparison instead of waiting.
+const COMPARE_QUEUE_SIZE: usize = 1024;
+
+// Storage for migrating backends. Writes go to both the primary and the secondary, reads are
+// served by the primary and compared against the secondary in background. Failures and
+// mismatches of the secondary are logged and counted in the metrics but never fail requests.
+#[derive(Clone)]
+pub struct ShadowStorage<P, S> {
+    primary: P,
+    secondary: S,
+    metrics: Metrics,
+    compare_tx: Arc<Mutex<SyncSender<(String, Vec<Host>)>>>,
+}
+
+impl<P: Storage, S: Storage> ShadowStorage<P, S> {
+    pub fn new(primary: P, secondary: S, metrics: Metrics) -> Self {
+        let (tx, rx) = mpsc::sync_channel(COMPARE_QUEUE_SIZE);
+        let s = secondary.clone();
+        let m = metrics.clone();
+        thread::spawn(move || compare(rx, s, m));
+        ShadowStorage {
+            primary,
+            secondary,
+            metrics,
+            compare_tx: Arc::new(Mutex::new(tx)),
+        }
+    }
+
+    fn write_secondary<T, E, F>(&self, op: &str, f: F)
+    where
+        E: std::fmt::Display,
+        F: FnOnce(&S) -> Result<T, E>,
+    {
+        if let Err(e) = f(&self.secondary) {
+            warn!("Failed to {} on secondary storage: {}", op, e);
+            self.metrics.inc_shadow_write_errors();
+        }
+    }
+
+    fn enqueue_compare(&self, name: &str, hosts: &[Host]) {
+        let tx = self.compare_tx.lock().unwrap_or_else(|e| e.into_inner());
+        match tx.try_send((name.to_owned(), hosts.to_vec())) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => debug!("Skip shadow comparison: service={}", name),
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("Shadow comparison stopped: service={}", name)
+            }
+        }
+    }
+}
+
+impl<P: Storage, S: Storage> Storage for ShadowStorage<P, S> {
+    type E = StorageError;
+
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E> {
+        let hosts = self.primary.query_items(name).map_err(primary_error)?;
+        self.enqueue_compare(name, &hosts);
+        Ok(hosts)
+    }
+
+    fn store_item(&self, name: &str, host: Host) -> Result<(), Self::E> {
+        self.primary
+            .store_item(name, host.clone())
+            .map_err(primary_error)?;
+        self.write_secondary("store_item", |s| s.store_item(name, host));
+        Ok(())
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E> {
+        let removed = self
+            .primary
+            .delete_item(name, ip.to_owned(), port)
+            .map_err(primary_error)?;
+        self.write_secondary("delete_item", |s| s.delete_item(name, ip, port));
+        Ok(removed)
+    }
+
+    fn get_item(&self, name: &str, ip: &str, port: u16) -> Result<Option<Host>, Self::E> {
+        self.primary.get_item(name, ip, port).map_err(primary_error)
+    }
+
+    fn reap_expired(&self, now: u64) -> Result<Vec<Host>, Self::E> {
+        let reaped = self.primary.reap_expired(now).map_err(primary_error)?;
+        self.write_secondary("reap_expired", |s| s.reap_expired(now));
+        Ok(reaped)
+    }
+
+    fn query_by_ip(&self, ip: &str) -> Result<Vec<Host>, Self::E> {
+        self.primary.query_by_ip(ip).map_err(primary_error)
+    }
+
+    fn list_services(&self) -> Result<Vec<String>, Self::E> {
+        self.primary.list_services().map_err(primary_error)
+    }
+
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, Self::E> {
+        self.primary.list_aliases().map_err(primary_error)
+    }
+
+    fn put_alias(&self, alias: &str, service: &str) -> Result<bool, Self::E> {
+        let supported = self
+            .primary
+            .put_alias(alias, service)
+            .map_err(primary_error)?;
+        self.write_secondary("put_alias", |s| s.put_alias(alias, service));
+        Ok(supported)
+    }
+
+    fn delete_alias(&self, alias: &str) -> Result<Option<String>, Self::E> {
+        let removed = self.primary.delete_alias(alias).map_err(primary_error)?;
+        self.write_secondary("delete_alias", |s| s.delete_alias(alias));
+        Ok(removed)
+    }
+
+    fn registry_index(&self, name: &str) -> Result<Option<RegistryIndex>, Self::E> {
+        self.primary.registry_index(name).map_err(primary_error)
+    }
+
+    fn freshness(&self, name: &str) -> Freshness {
+        self.primary.freshness(name)
+    }
+
+    fn ttl(&self) -> u64 {
+        self.primary.ttl()
+    }
+
+    fn with_deadline(&self, deadline: Instant) -> Self {
+        ShadowStorage {
+            primary: self.primary.with_deadline(deadline),
+            secondary: self.secondary.with_deadline(deadline),
+            metrics: self.metrics.clone(),
+            compare_tx: self.compare_tx.clone(),
+        }
+    }
+
+    // A broken secondary doesn't keep sds out of service.
+    fn check_schema(&self) -> Result<SchemaCheck, Self::E> {
+        let check = self.primary.check_schema().map_err(primary_error)?;
+        match self.secondary.check_schema() {
+            Ok(SchemaCheck::Compatible) => {}
+            Ok(SchemaCheck::Incompatible(e)) => {
+                warn!("Secondary storage schema is incompatible: {}", e)
+            }
+            Err(e) => warn!("Secondary storage schema check failed: {}", e),
+        }
+        Ok(check)
+    }
+
+    fn init_schema(&self) -> Result<(), Self::E> {
+        self.primary.init_schema().map_err(primary_error)?;
+        if let Err(e) = self.secondary.init_schema() {
+            warn!("Failed to initialize secondary storage schema: {}", e);
+        }
+        Ok(())
+    }
+
+    fn warm_up(&self) -> Result<(), Self::E> {
+        self.primary.warm_up().map_err(primary_error)?;
+        if let Err(e) = self.secondary.warm_up() {
+            warn!("Failed to warm up secondary storage: {}", e);
+        }
+        Ok(())
+    }
+
+    fn resync(&self) -> Result<Vec<Drift>, Self::E> {
+        self.primary.resync().map_err(primary_error)
+    }
+}
+
+fn compare<S: Storage>(rx: Receiver<(String, Vec<Host>)>, secondary: S, metrics: Metrics) {
+    for (name, primary_hosts) in rx {
+        let secondary_hosts = match secondary.query_items(&name) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!(
+                    "Failed to query secondary storage: service={}, error={}",
+                    name, e
+                );
+                metrics.inc_shadow_read_errors();
+                continue;
+            }
+        };
+        let p = by_key(primary_hosts);
+        let s = by_key(secondary_hosts);
+        let missing = p.keys().filter(|k| !s.contains_key(*k)).count();
+        let extra = s.keys().filter(|k| !p.contains_key(*k)).count();
+        let different = p
+            .iter()
+            .filter(|(k, h)| s.get(*k).map_or(false, |sh| sh != *h))
+            .count();
+        if missing + extra + different == 0 {
+            continue;
+        }
+        info!(
+            "Shadow mismatch: service={}, missing={}, extra={}, different={}",
+            name, missing, extra, different
+        );
+        metrics.inc_shadow_mismatches(&name);
+    }
+}
+
+fn by_key(hosts: Vec<Host>) -> BTreeMap<String, Host> {
+    hosts
+        .into_iter()
+        .map(|h| (format!("{}:{}", h.ip_address, h.port), h))
+        .collect()
+}
+
+fn primary_error<E: std::fmt::Display>(e: E) -> StorageError {
+    StorageError::new(e.to_string())
+}
@@ -0,0 +1,107 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info};
+use serde_derive::Serialize;
+use serde_json;
+
+use super::readiness::Readiness;
+use super::webhook::Webhook;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownStep {
+    // Marks the instance draining, so /hc/ready and gRPC health fail and LBs stop routing to it.
+    NotReady,
+    // Sleeps for ShutdownConfig.delay, giving LBs time to notice.
+    Wait,
+    // Posts a pre_shutdown event to ShutdownConfig.webhooks, e.g. to deregister the instance.
+    Webhooks,
+}
+
+impl FromStr for ShutdownStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "not_ready" => Ok(ShutdownStep::NotReady),
+            "wait" => Ok(ShutdownStep::Wait),
+            "webhooks" => Ok(ShutdownStep::Webhooks),
+            _ => Err(format!("Unknown shutdown step: {}", s)),
+        }
+    }
+}
+
+// Parses comma separated steps like "not_ready,wait,webhooks".
+pub fn parse_steps(s: &str) -> Result<Vec<ShutdownStep>, String> {
+    s.split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse())
+        .collect()
+}
+
+// Hooks run in order on SIGTERM or SIGINT before the server stops accepting connections and
+// drains in-flight requests.
+#[derive(Debug, Clone)]
+pub struct ShutdownConfig {
+    pub steps: Vec<ShutdownStep>,
+    pub delay: Duration,
+    pub webhooks: Vec<String>,
+    pub webhook_timeout: Duration,
+    // Connections still open after this are dropped.
+    pub drain_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            steps: vec![
+                ShutdownStep::NotReady,
+                ShutdownStep::Wait,
+                ShutdownStep::Webhooks,
+            ],
+            delay: Duration::from_secs(0),
+            webhooks: Vec::new(),
+            webhook_timeout: Duration::from_secs(5),
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct PreShutdownEvent {
+    kind: &'static str,
+    listen_port: u16,
+}
+
+// Runs the steps blocking the calling thread. Failed webhooks are logged and don't stop the
+// shutdown.
+pub fn run_pre_shutdown(config: &ShutdownConfig, readiness: &Readiness, listen_port: u16) {
+    for step in &config.steps {
+        info!("Run pre-shutdown step: {:?}", step);
+        match step {
+            ShutdownStep::NotReady => readiness.drain(),
+            ShutdownStep::Wait => thread::sleep(config.delay),
+            ShutdownStep::Webhooks => {
+                let body = match serde_json::to_vec(&PreShutdownEvent {
+                    kind: "pre_shutdown",
+                    listen_port,
+                }) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("Failed to serialize pre-shutdown event: {}", e);
+                        continue;
+                    }
+                };
+                for url in &config.webhooks {
+                    let result =
+                        Webhook::new(url, config.webhook_timeout).and_then(|mut w| w.post(&body));
+                    if let Err(e) = result {
+                        error!("Failed pre-shutdown webhook {}: {}", url, e);
+                    }
+                }
+            }
+        }
+    }
+}
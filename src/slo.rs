@@ -0,0 +1,235 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+// Requests are counted in buckets of this many seconds.
+const BUCKET_SECS: u64 = 10;
+
+// Objective on the ratio of good requests, evaluated with a short and a long window like the
+// multiwindow burn rate alerts of the SRE workbook.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SloObjective {
+    pub name: String,
+    // Route name like "sds" or "eds". Requests of every route count when missing.
+    pub route: Option<String>,
+    // Service of the request path. Requests of every service count when missing.
+    pub service: Option<String>,
+    // Requests slower than this are bad besides 5xx responses. Only errors count when missing.
+    pub latency_ms: Option<u64>,
+    // Ratio of good requests to keep, e.g. 0.99.
+    pub target: f64,
+    pub short_window_sec: u64,
+    pub long_window_sec: u64,
+    // Violated when the burn rates of both windows reach this.
+    pub burn_rate_threshold: f64,
+}
+
+impl Default for SloObjective {
+    fn default() -> Self {
+        SloObjective {
+            name: String::new(),
+            route: None,
+            service: None,
+            latency_ms: None,
+            target: 0.99,
+            short_window_sec: 300,
+            long_window_sec: 3600,
+            burn_rate_threshold: 14.4,
+        }
+    }
+}
+
+impl SloObjective {
+    fn matches(&self, route: &str, service: Option<&str>) -> bool {
+        self.route.as_ref().map_or(true, |r| r == route)
+            && self
+                .service
+                .as_ref()
+                .map_or(true, |s| service == Some(s.as_str()))
+    }
+
+    fn is_good(&self, status: u16, elapsed: Duration) -> bool {
+        if status >= 500 {
+            return false;
+        }
+        match self.latency_ms {
+            Some(ms) => elapsed <= Duration::from_millis(ms),
+            None => true,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SloStatus {
+    pub name: String,
+    pub route: Option<String>,
+    pub service: Option<String>,
+    pub latency_ms: Option<u64>,
+    pub target: f64,
+    pub short_window: WindowStatus,
+    pub long_window: WindowStatus,
+    pub violated: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WindowStatus {
+    pub seconds: u64,
+    pub requests: u64,
+    pub bad_requests: u64,
+    // Ratio of the error budget spent in the window to the budget for the window. 1.0 spends
+    // the whole budget by the end of the SLO period.
+    pub burn_rate: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    index: u64,
+    total: u64,
+    bad: u64,
+}
+
+// Counts good and bad requests of each objective in memory of the process.
+#[derive(Debug, Clone)]
+pub struct SloTracker {
+    started: Instant,
+    buckets: Arc<Mutex<HashMap<String, VecDeque<Bucket>>>>,
+}
+
+impl SloTracker {
+    pub fn new() -> Self {
+        SloTracker {
+            started: Instant::now(),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn record(
+        &self,
+        objectives: &[SloObjective],
+        route: &str,
+        service: Option<&str>,
+        status: u16,
+        elapsed: Duration,
+        now: Instant,
+    ) {
+        let index = self.bucket_index(now);
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        for o in objectives.iter().filter(|o| o.matches(route, service)) {
+            let q = buckets
+                .entry(o.name.to_owned())
+                .or_insert_with(VecDeque::new);
+            if q.back().map(|b| b.index) != Some(index) {
+                q.push_back(Bucket {
+                    index,
+                    total: 0,
+                    bad: 0,
+                });
+            }
+            if let Some(b) = q.back_mut() {
+                b.total += 1;
+                if !o.is_good(status, elapsed) {
+                    b.bad += 1;
+                }
+            }
+            let keep = o.long_window_sec.max(o.short_window_sec) / BUCKET_SECS + 1;
+            while q.front().map_or(false, |b| b.index + keep <= index) {
+                q.pop_front();
+            }
+        }
+        // Objectives removed from the config are forgotten.
+        buckets.retain(|name, _| objectives.iter().any(|o| o.name == *name));
+    }
+
+    pub fn report(&self, objectives: &[SloObjective], now: Instant) -> Vec<SloStatus> {
+        let index = self.bucket_index(now);
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let empty = VecDeque::new();
+        objectives
+            .iter()
+            .map(|o| {
+                let q = buckets.get(&o.name).unwrap_or(&empty);
+                let short_window = window_status(o, q, index, o.short_window_sec);
+                let long_window = window_status(o, q, index, o.long_window_sec);
+                let violated = short_window.burn_rate >= o.burn_rate_threshold
+                    && long_window.burn_rate >= o.burn_rate_threshold;
+                SloStatus {
+                    name: o.name.to_owned(),
+                    route: o.route.clone(),
+                    service: o.service.clone(),
+                    latency_ms: o.latency_ms,
+                    target: o.target,
+                    short_window,
+                    long_window,
+                    violated,
+                }
+            })
+            .collect()
+    }
+
+    pub fn render_prometheus(&self, objectives: &[SloObjective], now: Instant) -> String {
+        let statuses = self.report(objectives, now);
+        let mut out = String::new();
+        let name = "sds_slo_burn_rate";
+        let _ = writeln!(out, "# HELP {} Error budget burn rate of SLOs.", name);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for s in &statuses {
+            for (window, w) in &[("short", &s.short_window), ("long", &s.long_window)] {
+                let _ = writeln!(
+                    out,
+                    "{}{{slo=\"{}\",window=\"{}\"}} {}",
+                    name, s.name, window, w.burn_rate
+                );
+            }
+        }
+        let name = "sds_slo_violated";
+        let _ = writeln!(
+            out,
+            "# HELP {} 1 while both windows burn over the threshold.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for s in &statuses {
+            let _ = writeln!(out, "{}{{slo=\"{}\"}} {}", name, s.name, s.violated as u8);
+        }
+        out
+    }
+
+    fn bucket_index(&self, now: Instant) -> u64 {
+        let elapsed = if now > self.started {
+            now - self.started
+        } else {
+            Duration::from_secs(0)
+        };
+        elapsed.as_secs() / BUCKET_SECS
+    }
+}
+
+impl Default for SloTracker {
+    fn default() -> Self {
+        SloTracker::new()
+    }
+}
+
+fn window_status(o: &SloObjective, q: &VecDeque<Bucket>, index: u64, seconds: u64) -> WindowStatus {
+    let n = (seconds / BUCKET_SECS).max(1);
+    let (requests, bad_requests) = q
+        .iter()
+        .filter(|b| b.index + n > index)
+        .fold((0, 0), |(t, b), x| (t + x.total, b + x.bad));
+    let budget = 1.0 - o.target;
+    let burn_rate = if requests == 0 || budget <= 0.0 {
+        0.0
+    } else {
+        (bad_requests as f64 / requests as f64) / budget
+    };
+    WindowStatus {
+        seconds,
+        requests,
+        bad_requests,
+        burn_rate,
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::Write;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsdFlavor {
+    // Labels are sent as DogStatsD tags, e.g. "|#service:user".
+    DogStatsd,
+    // Plain statsd has no tags, so label values are appended to the metric name instead.
+    Statsd,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatsdConfig {
+    // "host:port" of the statsd agent.
+    pub address: String,
+    // Prepended to metric names with a dot, e.g. "sds".
+    pub prefix: String,
+    // Tags added to every metric, e.g. ("env", "prod"). Only sent with DogStatsD.
+    pub tags: Vec<(String, String)>,
+    pub flavor: StatsdFlavor,
+}
+
+// Sends metrics over UDP as they're recorded. Send failures are dropped since metrics must not
+// affect requests.
+#[derive(Debug, Clone)]
+pub struct StatsdClient {
+    socket: Arc<UdpSocket>,
+    config: Arc<StatsdConfig>,
+}
+
+impl StatsdClient {
+    pub fn new(config: StatsdConfig) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(config.address.as_str())?;
+        socket.set_nonblocking(true)?;
+        Ok(StatsdClient {
+            socket: Arc::new(socket),
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn count(&self, name: &str, value: u64, labels: &[(&str, &str)]) {
+        self.send(name, &value.to_string(), "c", labels);
+    }
+
+    pub fn timing(&self, name: &str, duration: Duration, labels: &[(&str, &str)]) {
+        let ms = duration.as_secs() * 1000 + u64::from(duration.subsec_millis());
+        self.send(name, &ms.to_string(), "ms", labels);
+    }
+
+    fn send(&self, name: &str, value: &str, kind: &str, labels: &[(&str, &str)]) {
+        let line = format_line(&self.config, name, value, kind, labels);
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            debug!("Failed to send statsd metric: metric={}, error={}", line, e);
+        }
+    }
+}
+
+pub fn format_line(
+    config: &StatsdConfig,
+    name: &str,
+    value: &str,
+    kind: &str,
+    labels: &[(&str, &str)],
+) -> String {
+    let mut line = String::new();
+    if !config.prefix.is_empty() {
+        line.push_str(&config.prefix);
+        line.push('.');
+    }
+    line.push_str(name);
+    if config.flavor == StatsdFlavor::Statsd {
+        for (_, v) in labels {
+            line.push('.');
+            line.push_str(&sanitize(v));
+        }
+    }
+    let _ = write!(line, ":{}|{}", value, kind);
+    if config.flavor == StatsdFlavor::DogStatsd {
+        let tags: Vec<String> = config
+            .tags
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .chain(labels.iter().cloned())
+            .map(|(k, v)| format!("{}:{}", sanitize(k), sanitize(v)))
+            .collect();
+        if !tags.is_empty() {
+            line.push_str("|#");
+            line.push_str(&tags.join(","));
+        }
+    }
+    line
+}
+
+// Replaces characters meaningful in the statsd line protocol.
+fn sanitize(v: &str) -> String {
+    v.chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | ',' | '#' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+// Parses "env:prod,team:discovery" into tags.
+pub fn parse_tags(s: &str) -> Result<Vec<(String, String)>, String> {
+    s.split(',')
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .map(|t| {
+            let mut parts = t.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) if !k.is_empty() => Ok((k.to_owned(), v.to_owned())),
+                _ => Err(format!("Invalid statsd tag, must be key:value: {}", t)),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,1128 @@
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeDefinition, AttributeValue, CreateTableInput, DeleteItemInput, DescribeTableError,
+    DescribeTableInput, GetItemInput, KeySchemaElement, PutItemInput, QueryInput, ScanInput,
+    TableDescription, TimeToLiveSpecification, UpdateItemInput, UpdateTimeToLiveInput,
+};
+use serde_json::Value;
+
+use super::{DynStorage, ErrorKind, StorageError, StorageSettings};
+use crate::clock::SharedClock;
+use crate::types::{Host, RegistryIndex, SchemaCheck, Storage, Tag};
+
+// Partition key of alias items, with the alias as the sort key. Service names can't contain '#'
+// since it starts the fragment of URLs. Alias items have no expire_time, so scans for hosts filtering
+// by it skip them.
+const ALIAS_PARTITION: &str = "#aliases";
+// Partition key of registry index items, with the service or GLOBAL_INDEX as the sort key. Like
+// alias items, they have no expire_time.
+const INDEX_PARTITION: &str = "#index";
+const GLOBAL_INDEX: &str = "#global";
+// Item recording the version of the table layout, bumped on incompatible changes. Tables created
+// before the item was introduced have the first layout.
+const SCHEMA_PARTITION: &str = "#schema";
+const SCHEMA_VERSION: u64 = 1;
+// How long --init-storage waits for a created table to become active.
+const TABLE_ACTIVE_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Clone)]
+pub struct StorageImpl<DynamoDb> {
+    pub table_name: String,
+    pub ttl: u64,
+    pub dynamodb_client: DynamoDb,
+    pub timeout: std::time::Duration,
+    pub deadline: Option<Instant>,
+    // Global secondary index keyed by ip_address for query_by_ip(). The table is scanned when
+    // missing.
+    pub ip_index: Option<String>,
+    // Time source of expiry, shared with the server.
+    pub clock: SharedClock,
+}
+
+impl<DynamoDb> StorageImpl<DynamoDb> {
+    fn epoch_secs(&self) -> Result<u64, StorageError> {
+        self.clock.epoch_secs().map_err(|msg| StorageError {
+            kind: ErrorKind::System,
+            msg,
+        })
+    }
+
+    fn api_timeout(&self) -> Result<Duration, StorageError> {
+        match self.deadline {
+            None => Ok(self.timeout),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    Err(StorageError {
+                        kind: ErrorKind::Timeout,
+                        msg: "Deadline exceeded before calling API".to_owned(),
+                    })
+                } else {
+                    Ok(cmp::min(self.timeout, deadline - now))
+                }
+            }
+        }
+    }
+}
+
+impl<DynamoDb> StorageImpl<DynamoDb>
+where
+    DynamoDb: rusoto_dynamodb::DynamoDb + Send + Sync + Clone + 'static,
+{
+    // Increments the global index and sets it to the service. Failures are logged since the write
+    // itself has succeeded, and the index catches up on the next change.
+    fn bump_index(&self, name: &str) {
+        if let Err(e) = self.try_bump_index(name) {
+            warn!(
+                "Failed to bump registry index: service={}, error={}",
+                name, e
+            );
+        }
+    }
+
+    fn try_bump_index(&self, name: &str) -> Result<(), StorageError> {
+        let mut input: UpdateItemInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        input.key = build_index_key(GLOBAL_INDEX);
+        input.update_expression = Some("ADD idx :one".to_owned());
+        input.expression_attribute_values = Some(build_idx_attr_values(":one", 1));
+        input.return_values = Some("UPDATED_NEW".to_owned());
+        let out = self
+            .dynamodb_client
+            .update_item(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+            .map_err(|e| StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in update_item: {}", e.to_string()),
+            })?;
+        let global = match out.attributes {
+            Some(mut m) => extract_number(&mut m, "idx")?,
+            None => return Err(build_data_error("Missing the updated index".to_owned())),
+        };
+
+        // Concurrent bumps may finish out of order, so the service index never goes back.
+        let mut input: UpdateItemInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        input.key = build_index_key(name);
+        input.update_expression = Some("SET idx = :idx".to_owned());
+        input.condition_expression = Some("attribute_not_exists(idx) OR idx < :idx".to_owned());
+        input.expression_attribute_values = Some(build_idx_attr_values(":idx", global));
+        if let Err(e) = self
+            .dynamodb_client
+            .update_item(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+        {
+            info!(
+                "Skip setting registry index: service={}, index={}, error={}",
+                name, global, e
+            );
+        }
+        Ok(())
+    }
+
+    // Returns None when the table doesn't exist.
+    fn describe_table(&self) -> Result<Option<TableDescription>, StorageError> {
+        let mut input: DescribeTableInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        match self
+            .dynamodb_client
+            .describe_table(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+        {
+            Ok(out) => Ok(out.table),
+            Err(RusotoError::Service(DescribeTableError::ResourceNotFound(_))) => Ok(None),
+            Err(e) => Err(StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in describe_table: {}", e.to_string()),
+            }),
+        }
+    }
+
+    fn create_table(&self) -> Result<(), StorageError> {
+        let mut input: CreateTableInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        input.billing_mode = Some("PAY_PER_REQUEST".to_owned());
+        input.key_schema = vec![
+            build_key_schema_element("service", "HASH"),
+            build_key_schema_element("ip_port", "RANGE"),
+        ];
+        input.attribute_definitions = vec![
+            build_attribute_definition("service"),
+            build_attribute_definition("ip_port"),
+        ];
+        self.dynamodb_client
+            .create_table(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+            .map_err(|e| StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in create_table: {}", e.to_string()),
+            })?;
+        info!("Created table: table_name={}", self.table_name);
+
+        let started_at = Instant::now();
+        loop {
+            let status = self.describe_table()?.and_then(|t| t.table_status);
+            if status.as_ref().map(|s| s.as_str()) == Some("ACTIVE") {
+                break;
+            }
+            if started_at.elapsed() >= TABLE_ACTIVE_TIMEOUT {
+                return Err(StorageError {
+                    kind: ErrorKind::Timeout,
+                    msg: format!(
+                        "Table {} isn't active in {:?}: status={:?}",
+                        self.table_name, TABLE_ACTIVE_TIMEOUT, status
+                    ),
+                });
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        let mut input: UpdateTimeToLiveInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        input.time_to_live_specification = TimeToLiveSpecification {
+            attribute_name: "expire_time".to_owned(),
+            enabled: true,
+        };
+        self.dynamodb_client
+            .update_time_to_live(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+            .map_err(|e| StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in update_time_to_live: {}", e.to_string()),
+            })?;
+        Ok(())
+    }
+
+    fn get_schema_version(&self) -> Result<Option<u64>, StorageError> {
+        let mut input: GetItemInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        input.key = build_schema_key();
+        input.consistent_read = Some(true);
+        let out = self
+            .dynamodb_client
+            .get_item(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+            .map_err(|e| StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in get_item: {}", e.to_string()),
+            })?;
+        match out.item {
+            Some(mut m) => Ok(Some(extract_number(&mut m, "schema_version")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put_schema_version(&self) -> Result<(), StorageError> {
+        let mut input: PutItemInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        let mut item = build_schema_key();
+        item.extend(build_idx_attr_values("schema_version", SCHEMA_VERSION));
+        input.item = item;
+        self.dynamodb_client
+            .put_item(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+            .map_err(|e| StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in put_item: {}", e.to_string()),
+            })?;
+        Ok(())
+    }
+
+    fn get_index(&self, key: &str) -> Result<u64, StorageError> {
+        let mut input: GetItemInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        input.key = build_index_key(key);
+        input.consistent_read = Some(true);
+        let out = self
+            .dynamodb_client
+            .get_item(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+            .map_err(|e| StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in get_item: {}", e.to_string()),
+            })?;
+        match out.item {
+            Some(mut m) => extract_number(&mut m, "idx"),
+            None => Ok(0),
+        }
+    }
+}
+
+impl<DynamoDb> Storage for StorageImpl<DynamoDb>
+where
+    DynamoDb: rusoto_dynamodb::DynamoDb + Send + Sync + Clone + 'static,
+{
+    type E = StorageError;
+
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E> {
+        let mut hosts = Vec::new();
+        let mut last_evaluated_key: Option<HashMap<String, AttributeValue>> = None;
+        let table_name = self.table_name.to_owned();
+        let epoch_now = self.epoch_secs()?;
+
+        loop {
+            let tn = table_name.to_owned();
+            let mut query_input = build_query_input(tn, &name);
+            query_input.exclusive_start_key = last_evaluated_key;
+            let res = match self
+                .dynamodb_client
+                .query(query_input)
+                .with_timeout(self.api_timeout()?)
+                .sync()
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    return Err(StorageError {
+                        kind: ErrorKind::Api,
+                        msg: format!("API Error in query: {}", e.to_string()),
+                    })
+                }
+            };
+            last_evaluated_key = res.last_evaluated_key;
+            let items = res.items.expect("items of query result is missing");
+            for h in items {
+                let host = convert_ddb_host_to_domain_host(&name, h)?;
+                if host.expire_time >= epoch_now {
+                    hosts.push(host);
+                } else {
+                    info!(
+                        "Expired host found: service={}, ip={}, port={}, expire_time={}, now={}",
+                        name, host.ip_address, host.port, host.expire_time, epoch_now
+                    );
+                }
+            }
+            if last_evaluated_key.is_none() {
+                break;
+            }
+        }
+        info!(
+            "query_items(): succeed to return hosts: service={}, hosts-size={}",
+            name,
+            hosts.len()
+        );
+        Ok(hosts)
+    }
+
+    fn store_item(&self, name: &str, host: Host) -> Result<(), Self::E> {
+        let table_name = self.table_name.to_owned();
+        let ip = host.ip_address.to_owned();
+        let port = host.port;
+        let mut input = build_put_item_input(table_name, &name, host.clone());
+        input.return_values = Some("ALL_OLD".to_owned());
+
+        match self
+            .dynamodb_client
+            .put_item(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+        {
+            Ok(out) => {
+                info!(
+                    "store_item(): succeed to store item: service={}, ip={}, port={}",
+                    name, ip, port
+                );
+                let changed = match out.attributes {
+                    Some(m) => {
+                        let old = convert_ddb_host_to_domain_host(name, m)?;
+                        old.expire_time < self.epoch_secs()? || host.changed_from(&old)
+                    }
+                    None => true,
+                };
+                if changed {
+                    self.bump_index(name);
+                }
+                Ok(())
+            }
+            Err(e) => Err(StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in put_item: {}", e.to_string()),
+            }),
+        }
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E> {
+        let table_name = self.table_name.to_owned();
+
+        match self
+            .dynamodb_client
+            .delete_item(build_delete_item_input(table_name, name, &ip, port))
+            .with_timeout(self.api_timeout()?)
+            .sync()
+        {
+            Ok(out) => {
+                info!(
+                    "delete_item(): succeed to delete_item item: service={}, ip={}, port={}",
+                    name, ip, port
+                );
+                match out.attributes {
+                    Some(m) => {
+                        self.bump_index(name);
+                        let h = convert_ddb_host_to_domain_host(name, m)?;
+                        if h.expire_time >= self.epoch_secs()? {
+                            Ok(Some(h))
+                        } else {
+                            Ok(None)
+                        }
+                    }
+                    None => Ok(None),
+                }
+            }
+            Err(e) => Err(StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in delete_item: {}", e.to_string()),
+            }),
+        }
+    }
+
+    // Scans the table for expired items and deletes each with a condition on the expiry, so that
+    // hosts checked in meanwhile survive and concurrent reapers don't reap the same item twice.
+    fn reap_expired(&self, now: u64) -> Result<Vec<Host>, Self::E> {
+        let mut reaped: Vec<Host> = Vec::new();
+        let mut last_evaluated_key: Option<HashMap<String, AttributeValue>> = None;
+        loop {
+            let mut scan_input = build_scan_expired_input(self.table_name.to_owned(), now);
+            scan_input.exclusive_start_key = last_evaluated_key;
+            let res = match self
+                .dynamodb_client
+                .scan(scan_input)
+                .with_timeout(self.api_timeout()?)
+                .sync()
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    return Err(StorageError {
+                        kind: ErrorKind::Api,
+                        msg: format!("API Error in scan: {}", e.to_string()),
+                    })
+                }
+            };
+            last_evaluated_key = res.last_evaluated_key;
+            for mut item in res.items.unwrap_or_default() {
+                let name = extract_string(&mut item, "service")?;
+                let host = convert_ddb_host_to_domain_host(&name, item)?;
+                let mut input = build_delete_item_input(
+                    self.table_name.to_owned(),
+                    &name,
+                    &host.ip_address,
+                    u64::from(host.port),
+                );
+                input.condition_expression = Some("expire_time < :now".to_owned());
+                input.expression_attribute_values = Some(build_now_attr_values(now));
+                match self
+                    .dynamodb_client
+                    .delete_item(input)
+                    .with_timeout(self.api_timeout()?)
+                    .sync()
+                {
+                    Ok(out) => {
+                        if let Some(m) = out.attributes {
+                            reaped.push(convert_ddb_host_to_domain_host(&name, m)?);
+                        }
+                    }
+                    // Mostly the condition failed since the host checked in or was reaped by
+                    // another reaper.
+                    Err(e) => warn!(
+                        "Skip reaping host: service={}, ip={}, port={}, error={}",
+                        name, host.ip_address, host.port, e
+                    ),
+                }
+            }
+            if last_evaluated_key.is_none() {
+                break;
+            }
+        }
+        info!(
+            "reap_expired(): succeed to reap hosts: hosts-size={}",
+            reaped.len()
+        );
+        let services: BTreeSet<&str> = reaped.iter().map(|h| h.service.as_str()).collect();
+        for name in services {
+            self.bump_index(name);
+        }
+        Ok(reaped)
+    }
+
+    fn query_by_ip(&self, ip: &str) -> Result<Vec<Host>, Self::E> {
+        let epoch_now = self.epoch_secs()?;
+        let mut hosts = Vec::new();
+        let mut last_evaluated_key: Option<HashMap<String, AttributeValue>> = None;
+        loop {
+            let (items, next_key) = match self.ip_index {
+                Some(ref index) => {
+                    let mut input = build_query_by_ip_input(self.table_name.to_owned(), index, ip);
+                    input.exclusive_start_key = last_evaluated_key;
+                    match self
+                        .dynamodb_client
+                        .query(input)
+                        .with_timeout(self.api_timeout()?)
+                        .sync()
+                    {
+                        Ok(res) => (res.items, res.last_evaluated_key),
+                        Err(e) => {
+                            return Err(StorageError {
+                                kind: ErrorKind::Api,
+                                msg: format!("API Error in query: {}", e.to_string()),
+                            })
+                        }
+                    }
+                }
+                None => {
+                    let mut input = build_scan_by_ip_input(self.table_name.to_owned(), ip);
+                    input.exclusive_start_key = last_evaluated_key;
+                    match self
+                        .dynamodb_client
+                        .scan(input)
+                        .with_timeout(self.api_timeout()?)
+                        .sync()
+                    {
+                        Ok(res) => (res.items, res.last_evaluated_key),
+                        Err(e) => {
+                            return Err(StorageError {
+                                kind: ErrorKind::Api,
+                                msg: format!("API Error in scan: {}", e.to_string()),
+                            })
+                        }
+                    }
+                }
+            };
+            last_evaluated_key = next_key;
+            for mut item in items.unwrap_or_default() {
+                let name = extract_string(&mut item, "service")?;
+                if name == ALIAS_PARTITION || name == INDEX_PARTITION || name == SCHEMA_PARTITION {
+                    continue;
+                }
+                let host = convert_ddb_host_to_domain_host(&name, item)?;
+                if host.ip_address == ip && host.expire_time >= epoch_now {
+                    hosts.push(host);
+                }
+            }
+            if last_evaluated_key.is_none() {
+                break;
+            }
+        }
+        info!(
+            "query_by_ip(): succeed to return hosts: ip={}, hosts-size={}",
+            ip,
+            hosts.len()
+        );
+        Ok(hosts)
+    }
+
+    // Scans only the service and the expiry of items.
+    fn list_services(&self) -> Result<Vec<String>, Self::E> {
+        let epoch_now = self.epoch_secs()?;
+        let mut services = BTreeSet::new();
+        let mut last_evaluated_key: Option<HashMap<String, AttributeValue>> = None;
+        loop {
+            let mut scan_input = build_scan_services_input(self.table_name.to_owned(), epoch_now);
+            scan_input.exclusive_start_key = last_evaluated_key;
+            let res = match self
+                .dynamodb_client
+                .scan(scan_input)
+                .with_timeout(self.api_timeout()?)
+                .sync()
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    return Err(StorageError {
+                        kind: ErrorKind::Api,
+                        msg: format!("API Error in scan: {}", e.to_string()),
+                    })
+                }
+            };
+            last_evaluated_key = res.last_evaluated_key;
+            for mut item in res.items.unwrap_or_default() {
+                services.insert(extract_string(&mut item, "service")?);
+            }
+            if last_evaluated_key.is_none() {
+                break;
+            }
+        }
+        info!(
+            "list_services(): succeed to return services: services-size={}",
+            services.len()
+        );
+        Ok(services.into_iter().collect())
+    }
+
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, Self::E> {
+        let mut aliases = BTreeMap::new();
+        let mut last_evaluated_key: Option<HashMap<String, AttributeValue>> = None;
+        loop {
+            let mut input = build_query_input(self.table_name.to_owned(), ALIAS_PARTITION);
+            input.exclusive_start_key = last_evaluated_key;
+            let res = match self
+                .dynamodb_client
+                .query(input)
+                .with_timeout(self.api_timeout()?)
+                .sync()
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    return Err(StorageError {
+                        kind: ErrorKind::Api,
+                        msg: format!("API Error in query: {}", e.to_string()),
+                    })
+                }
+            };
+            last_evaluated_key = res.last_evaluated_key;
+            for mut item in res.items.unwrap_or_default() {
+                let alias = extract_string(&mut item, "ip_port")?;
+                let service = extract_string(&mut item, "alias_target")?;
+                aliases.insert(alias, service);
+            }
+            if last_evaluated_key.is_none() {
+                break;
+            }
+        }
+        Ok(aliases)
+    }
+
+    fn put_alias(&self, alias: &str, service: &str) -> Result<bool, Self::E> {
+        let mut input: PutItemInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        let mut item = build_alias_key(alias);
+        item.insert(
+            "alias_target".to_owned(),
+            build_string_attr(service.to_owned()),
+        );
+        input.item = item;
+        match self
+            .dynamodb_client
+            .put_item(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+        {
+            Ok(_) => {
+                info!(
+                    "put_alias(): succeed to store alias: alias={}, service={}",
+                    alias, service
+                );
+                Ok(true)
+            }
+            Err(e) => Err(StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in put_item: {}", e.to_string()),
+            }),
+        }
+    }
+
+    fn delete_alias(&self, alias: &str) -> Result<Option<String>, Self::E> {
+        let mut input: DeleteItemInput = Default::default();
+        input.table_name = self.table_name.to_owned();
+        input.key = build_alias_key(alias);
+        input.return_values = Some("ALL_OLD".to_owned());
+        match self
+            .dynamodb_client
+            .delete_item(input)
+            .with_timeout(self.api_timeout()?)
+            .sync()
+        {
+            Ok(out) => match out.attributes {
+                Some(mut m) => Ok(Some(extract_string(&mut m, "alias_target")?)),
+                None => Ok(None),
+            },
+            Err(e) => Err(StorageError {
+                kind: ErrorKind::Api,
+                msg: format!("API Error in delete_item: {}", e.to_string()),
+            }),
+        }
+    }
+
+    fn registry_index(&self, name: &str) -> Result<Option<RegistryIndex>, Self::E> {
+        Ok(Some(RegistryIndex {
+            service: self.get_index(name)?,
+            global: self.get_index(GLOBAL_INDEX)?,
+        }))
+    }
+
+    fn check_schema(&self) -> Result<SchemaCheck, Self::E> {
+        let table = match self.describe_table()? {
+            Some(v) => v,
+            None => {
+                return Ok(SchemaCheck::Incompatible(format!(
+                    "Table {} doesn't exist, create it or run with --init-storage",
+                    self.table_name
+                )))
+            }
+        };
+        let key_schema = table.key_schema.unwrap_or_default();
+        let has_key = |name: &str, key_type: &str| {
+            key_schema
+                .iter()
+                .any(|k| k.attribute_name == name && k.key_type == key_type)
+        };
+        let is_string = |name: &str| {
+            table.attribute_definitions.as_ref().map_or(false, |defs| {
+                defs.iter()
+                    .any(|d| d.attribute_name == name && d.attribute_type == "S")
+            })
+        };
+        if key_schema.len() != 2
+            || !has_key("service", "HASH")
+            || !has_key("ip_port", "RANGE")
+            || !is_string("service")
+            || !is_string("ip_port")
+        {
+            return Ok(SchemaCheck::Incompatible(format!(
+                "Table {} must have the partition key service and the sort key ip_port as String",
+                self.table_name
+            )));
+        }
+        match self.get_schema_version()? {
+            None => warn!(
+                "Table {} has no schema version, assume version 1",
+                self.table_name
+            ),
+            Some(SCHEMA_VERSION) => (),
+            Some(v) => {
+                return Ok(SchemaCheck::Incompatible(format!(
+                    "Table {} has schema version {} but this sds expects {}",
+                    self.table_name, v, SCHEMA_VERSION
+                )))
+            }
+        }
+        info!(
+            "check_schema(): table {} has schema version {}",
+            self.table_name, SCHEMA_VERSION
+        );
+        Ok(SchemaCheck::Compatible)
+    }
+
+    fn init_schema(&self) -> Result<(), Self::E> {
+        if self.describe_table()?.is_none() {
+            self.create_table()?;
+        }
+        if self.get_schema_version()?.is_none() {
+            self.put_schema_version()?;
+        }
+        match self.check_schema()? {
+            SchemaCheck::Compatible => Ok(()),
+            SchemaCheck::Incompatible(msg) => Err(build_schema_error(msg)),
+        }
+    }
+
+    fn ttl(&self) -> u64 {
+        self.ttl
+    }
+
+    fn with_deadline(&self, deadline: Instant) -> Self {
+        let mut s = self.clone();
+        s.deadline = Some(deadline);
+        s
+    }
+}
+
+fn build_query_input(table_name: String, name: &str) -> QueryInput {
+    let mut attr_service: AttributeValue = Default::default();
+    attr_service.s = Some(name.to_owned());
+    let mut expression_attribute_values: HashMap<String, AttributeValue> = HashMap::new();
+    expression_attribute_values.insert(":service_val".to_owned(), attr_service);
+
+    let mut query_input: QueryInput = Default::default();
+    query_input.table_name = table_name;
+    query_input.expression_attribute_values = Some(expression_attribute_values);
+    query_input.key_condition_expression = Some("service = :service_val".to_owned());
+    query_input
+}
+
+fn build_query_by_ip_input(table_name: String, index: &str, ip: &str) -> QueryInput {
+    let mut values = HashMap::new();
+    values.insert(":ip".to_owned(), build_string_attr(ip.to_owned()));
+    let mut query_input: QueryInput = Default::default();
+    query_input.table_name = table_name;
+    query_input.index_name = Some(index.to_owned());
+    query_input.expression_attribute_values = Some(values);
+    query_input.key_condition_expression = Some("ip_address = :ip".to_owned());
+    query_input
+}
+
+fn build_scan_by_ip_input(table_name: String, ip: &str) -> ScanInput {
+    let mut values = HashMap::new();
+    values.insert(":prefix".to_owned(), build_string_attr(format!("{}:", ip)));
+    let mut scan_input: ScanInput = Default::default();
+    scan_input.table_name = table_name;
+    scan_input.filter_expression = Some("begins_with(ip_port, :prefix)".to_owned());
+    scan_input.expression_attribute_values = Some(values);
+    scan_input
+}
+
+fn build_put_item_input(table_name: String, name: &str, host: Host) -> PutItemInput {
+    let mut put_item_input: PutItemInput = Default::default();
+    put_item_input.table_name = table_name;
+    put_item_input.item = convert_domain_host_to_ddb_host(&name, host);
+    put_item_input
+}
+
+fn build_scan_expired_input(table_name: String, now: u64) -> ScanInput {
+    let mut scan_input: ScanInput = Default::default();
+    scan_input.table_name = table_name;
+    scan_input.filter_expression = Some("expire_time < :now".to_owned());
+    scan_input.expression_attribute_values = Some(build_now_attr_values(now));
+    scan_input
+}
+
+fn build_scan_services_input(table_name: String, now: u64) -> ScanInput {
+    let mut scan_input: ScanInput = Default::default();
+    scan_input.table_name = table_name;
+    scan_input.projection_expression = Some("service".to_owned());
+    scan_input.filter_expression = Some("expire_time >= :now".to_owned());
+    scan_input.expression_attribute_values = Some(build_now_attr_values(now));
+    scan_input
+}
+
+fn build_alias_key(alias: &str) -> HashMap<String, AttributeValue> {
+    let mut key = HashMap::new();
+    key.insert(
+        "service".to_owned(),
+        build_string_attr(ALIAS_PARTITION.to_owned()),
+    );
+    key.insert("ip_port".to_owned(), build_string_attr(alias.to_owned()));
+    key
+}
+
+fn build_index_key(sort_key: &str) -> HashMap<String, AttributeValue> {
+    let mut key = HashMap::new();
+    key.insert(
+        "service".to_owned(),
+        build_string_attr(INDEX_PARTITION.to_owned()),
+    );
+    key.insert("ip_port".to_owned(), build_string_attr(sort_key.to_owned()));
+    key
+}
+
+fn build_schema_key() -> HashMap<String, AttributeValue> {
+    let mut key = HashMap::new();
+    key.insert(
+        "service".to_owned(),
+        build_string_attr(SCHEMA_PARTITION.to_owned()),
+    );
+    key.insert(
+        "ip_port".to_owned(),
+        build_string_attr("version".to_owned()),
+    );
+    key
+}
+
+fn build_key_schema_element(name: &str, key_type: &str) -> KeySchemaElement {
+    KeySchemaElement {
+        attribute_name: name.to_owned(),
+        key_type: key_type.to_owned(),
+    }
+}
+
+fn build_attribute_definition(name: &str) -> AttributeDefinition {
+    AttributeDefinition {
+        attribute_name: name.to_owned(),
+        attribute_type: "S".to_owned(),
+    }
+}
+
+fn build_idx_attr_values(name: &str, idx: u64) -> HashMap<String, AttributeValue> {
+    let mut v: AttributeValue = Default::default();
+    v.n = Some(idx.to_string());
+    let mut values = HashMap::new();
+    values.insert(name.to_owned(), v);
+    values
+}
+
+fn build_now_attr_values(now: u64) -> HashMap<String, AttributeValue> {
+    let mut v: AttributeValue = Default::default();
+    v.n = Some(now.to_string());
+    let mut values = HashMap::new();
+    values.insert(":now".to_owned(), v);
+    values
+}
+
+fn build_delete_item_input(table_name: String, name: &str, ip: &str, port: u64) -> DeleteItemInput {
+    let mut delete_item_input: DeleteItemInput = Default::default();
+    delete_item_input.table_name = table_name;
+    let mut pk = HashMap::new();
+    pk.insert("service".to_owned(), build_string_attr(name.to_owned()));
+    let ip_and_port = format!("{}:{}", ip, port);
+    pk.insert("ip_port".to_owned(), build_string_attr(ip_and_port));
+    delete_item_input.key = pk;
+    delete_item_input.return_values = Some("ALL_OLD".to_owned());
+    delete_item_input
+}
+
+fn convert_domain_host_to_ddb_host(name: &str, host: Host) -> HashMap<String, AttributeValue> {
+    let mut map = HashMap::new();
+    map.insert("service".to_owned(), build_string_attr(name.to_owned()));
+    let ip_port = format!("{}:{}", host.ip_address, host.port);
+    map.insert("ip_port".to_owned(), build_string_attr(ip_port));
+    // Redundant with ip_port, but allows an index for query_by_ip().
+    map.insert(
+        "ip_address".to_owned(),
+        build_string_attr(host.ip_address.to_owned()),
+    );
+    map.insert(
+        "last_check_in".to_owned(),
+        build_string_attr(host.last_check_in),
+    );
+    let mut v: AttributeValue = Default::default();
+    v.n = Some(host.expire_time.to_string());
+    map.insert("expire_time".to_owned(), v);
+    map.insert("revision".to_owned(), build_string_attr(host.revision));
+    if let Some(t) = host.drain_started_at {
+        let v = AttributeValue {
+            n: Some(t.to_string()),
+            ..Default::default()
+        };
+        map.insert("drain_started_at".to_owned(), v);
+    }
+    if let Some(t) = host.registered_at {
+        let v = AttributeValue {
+            n: Some(t.to_string()),
+            ..Default::default()
+        };
+        map.insert("registered_at".to_owned(), v);
+    }
+    let mut v: AttributeValue = Default::default();
+    v.m = Some(convert_domain_tag_to_ddb_tag(host.tags));
+    map.insert("tags".to_owned(), v);
+    map
+}
+
+fn convert_domain_tag_to_ddb_tag(tag: Tag) -> HashMap<String, AttributeValue> {
+    let mut map = HashMap::new();
+    map.insert("az".to_owned(), build_string_attr(tag.az));
+    map.insert("region".to_owned(), build_string_attr(tag.region));
+    map.insert("instance_id".to_owned(), build_string_attr(tag.instance_id));
+    let mut v: AttributeValue = Default::default();
+    v.bool = Some(tag.canary);
+    map.insert("canary".to_owned(), v);
+
+    if let Some(weight) = tag.load_balancing_weight {
+        let v = AttributeValue {
+            n: Some(weight.to_string()),
+            ..Default::default()
+        };
+        map.insert("load_balancing_weight".to_owned(), v);
+    }
+    for (k, v) in tag.extra {
+        let attr = match v {
+            Value::String(s) => build_string_attr(s),
+            Value::Bool(b) => AttributeValue {
+                bool: Some(b),
+                ..Default::default()
+            },
+            Value::Number(n) => AttributeValue {
+                n: Some(n.to_string()),
+                ..Default::default()
+            },
+            // Nulls, arrays and objects are kept as JSON text.
+            v => build_string_attr(v.to_string()),
+        };
+        map.insert(k, attr);
+    }
+
+    map
+}
+
+fn build_string_attr(s: String) -> AttributeValue {
+    let mut v: AttributeValue = Default::default();
+    v.s = Some(s);
+    v
+}
+
+fn convert_ddb_host_to_domain_host(
+    name: &str,
+    mut h: HashMap<String, AttributeValue>,
+) -> Result<Host, StorageError> {
+    let tag = convert_ddb_tags_to_domain_tag(extract_map(&mut h, "tags")?)?;
+
+    let addr_and_port_string = extract_string(&mut h, "ip_port")?;
+    let addr_and_port: Vec<&str> = addr_and_port_string.split(':').collect();
+    if addr_and_port.len() != 2 {
+        return Err(build_data_error(format!(
+            "\"{}\" must be formated with colon like \"ip:port\"",
+            addr_and_port_string
+        )));
+    }
+    let port_string = addr_and_port[1].to_string();
+    let port = match port_string.parse() {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(build_data_error(format!(
+                "port value must be a valid integer: {}",
+                port_string
+            )))
+        }
+    };
+    Ok(Host {
+        ip_address: addr_and_port[0].to_string(),
+        port,
+        last_check_in: extract_string(&mut h, "last_check_in")?,
+        expire_time: extract_number(&mut h, "expire_time")?,
+        revision: extract_string(&mut h, "revision")?,
+        service: name.to_owned(),
+        tags: tag,
+        drain_started_at: extract_optional_number(&mut h, "drain_started_at")?,
+        registered_at: extract_optional_number(&mut h, "registered_at")?,
+    })
+}
+
+fn build_schema_error(msg: String) -> StorageError {
+    StorageError {
+        kind: ErrorKind::Schema,
+        msg,
+    }
+}
+
+fn build_data_error(msg: String) -> StorageError {
+    StorageError {
+        kind: ErrorKind::Data,
+        msg,
+    }
+}
+
+fn convert_ddb_tags_to_domain_tag(
+    mut tag_map: HashMap<String, AttributeValue>,
+) -> Result<Tag, StorageError> {
+    let az = extract_string(&mut tag_map, "az")?;
+    let region = extract_string(&mut tag_map, "region")?;
+    let instance_id = extract_string(&mut tag_map, "instance_id")?;
+    let canary = extract_bool(&mut tag_map, "canary")?;
+    let load_balancing_weight = extract_u8(&mut tag_map, "load_balancing_weight")?;
+    // The rest are arbitrary tags.
+    let extra = tag_map
+        .into_iter()
+        .filter_map(|(k, v)| {
+            let v = if let Some(s) = v.s {
+                Value::String(s)
+            } else if let Some(b) = v.bool {
+                Value::Bool(b)
+            } else {
+                Value::Number(v.n?.parse().ok()?)
+            };
+            Some((k, v))
+        })
+        .collect();
+    Ok(Tag {
+        az,
+        region,
+        instance_id,
+        canary,
+        load_balancing_weight,
+        extra,
+    })
+}
+
+fn extract_string(
+    m: &mut HashMap<String, AttributeValue>,
+    k: &str,
+) -> Result<String, StorageError> {
+    extract(m, k)?.s.ok_or_else(|| {
+        build_data_error(format!(
+            "Key \"{}\" is expected to be a String but is not",
+            k
+        ))
+    })
+}
+
+fn extract_bool(m: &mut HashMap<String, AttributeValue>, k: &str) -> Result<bool, StorageError> {
+    extract(m, k)?.bool.ok_or_else(|| {
+        build_data_error(format!(
+            "Key \"{}\" is expected to be a Boolean but is not",
+            k
+        ))
+    })
+}
+
+fn extract_number(m: &mut HashMap<String, AttributeValue>, k: &str) -> Result<u64, StorageError> {
+    extract(m, k)?
+        .n
+        .ok_or_else(|| {
+            build_data_error(format!(
+                "Key \"{}\" is expected to be a Number but is not",
+                k
+            ))
+        })
+        .and_then(|s| match s.parse() {
+            Ok(u) => Ok(u),
+            Err(_e) => Err(build_data_error(format!(
+                "Key \"{}\" is expected to be a Number (u64) value but is not: {}",
+                k, s,
+            ))),
+        })
+}
+
+fn extract_optional_number(
+    m: &mut HashMap<String, AttributeValue>,
+    k: &str,
+) -> Result<Option<u64>, StorageError> {
+    if !m.contains_key(k) {
+        return Ok(None);
+    }
+    extract_number(m, k).map(Some)
+}
+
+fn extract_map(
+    m: &mut HashMap<String, AttributeValue>,
+    k: &str,
+) -> Result<HashMap<String, AttributeValue>, StorageError> {
+    extract(m, k)?.m.ok_or_else(|| {
+        build_data_error(format!("Key \"{}\" is expected to be a Map but is not", k))
+    })
+}
+
+fn extract_u8(
+    m: &mut HashMap<String, AttributeValue>,
+    k: &str,
+) -> Result<Option<u8>, StorageError> {
+    match m.remove(k).and_then(|v| v.n) {
+        Some(s) => match s.parse() {
+            Ok(u) => Ok(Some(u)),
+            Err(_e) => Err(build_data_error(format!(
+                "Key \"{}\" is expected to be a Number (u8) value but is not: {}",
+                k, s,
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn extract(
+    m: &mut HashMap<String, AttributeValue>,
+    k: &str,
+) -> Result<AttributeValue, StorageError> {
+    m.remove(k)
+        .ok_or_else(|| build_data_error(format!("Missing required value for key: {}", k)))
+}
+
+pub fn create(settings: &StorageSettings) -> Result<DynStorage, StorageError> {
+    let table_name = match settings.options.get("table_name") {
+        Some(v) => v.to_owned(),
+        None => {
+            return Err(StorageError::new(
+                "table_name option is required for dynamodb storage".to_owned(),
+            ))
+        }
+    };
+    // rusoto requires AWS_DEFAULT_REGION env.
+    let dynamodb_client = rusoto_dynamodb::DynamoDbClient::new(Default::default());
+    Ok(DynStorage::new(StorageImpl {
+        table_name,
+        ttl: settings.ttl,
+        dynamodb_client,
+        timeout: settings.timeout,
+        deadline: None,
+        ip_index: settings.options.get("ip_index").cloned(),
+        clock: settings.clock.clone(),
+    }))
+}
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use log::info;
+
+use super::{DynStorage, ErrorKind, StorageError, StorageSettings};
+use crate::clock::{system_clock, SharedClock};
+use crate::types::{Host, RegistryIndex, Storage};
+
+// Keeps hosts in the process memory. Useful for development and tests, but the data is neither
+// persisted nor shared between sds processes.
+#[derive(Debug, Clone)]
+pub struct MemoryStorage {
+    ttl: u64,
+    // service -> "ip:port" -> host
+    hosts: Arc<Mutex<HashMap<String, BTreeMap<String, Host>>>>,
+    // alias -> service
+    aliases: Arc<Mutex<BTreeMap<String, String>>>,
+    indexes: Arc<Mutex<Indexes>>,
+    clock: SharedClock,
+}
+
+#[derive(Debug, Default)]
+struct Indexes {
+    global: u64,
+    // service -> global index at its last change
+    services: HashMap<String, u64>,
+}
+
+impl Indexes {
+    fn bump(&mut self, name: &str) {
+        self.global += 1;
+        self.services.insert(name.to_owned(), self.global);
+    }
+}
+
+impl MemoryStorage {
+    pub fn new(ttl: u64) -> Self {
+        MemoryStorage::with_clock(ttl, system_clock())
+    }
+
+    // Hosts expire by the given clock instead of the wall clock.
+    pub fn with_clock(ttl: u64, clock: SharedClock) -> Self {
+        MemoryStorage {
+            ttl,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+            aliases: Arc::new(Mutex::new(BTreeMap::new())),
+            indexes: Arc::new(Mutex::new(Indexes::default())),
+            clock,
+        }
+    }
+
+    fn lock_indexes(&self) -> MutexGuard<Indexes> {
+        self.indexes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn epoch_now(&self) -> Result<u64, StorageError> {
+        self.clock.epoch_secs().map_err(|msg| StorageError {
+            kind: ErrorKind::System,
+            msg,
+        })
+    }
+}
+
+impl Storage for MemoryStorage {
+    type E = StorageError;
+
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E> {
+        let now = self.epoch_now()?;
+        let hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let hosts: Vec<Host> = match hosts.get(name) {
+            Some(m) => m
+                .values()
+                .filter(|h| h.expire_time >= now)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        info!(
+            "query_items(): succeed to return hosts: service={}, hosts-size={}",
+            name,
+            hosts.len()
+        );
+        Ok(hosts)
+    }
+
+    fn store_item(&self, name: &str, host: Host) -> Result<(), Self::E> {
+        let now = self.epoch_now()?;
+        let ip_port = format!("{}:{}", host.ip_address, host.port);
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let changed = {
+            let m = hosts.entry(name.to_owned()).or_insert_with(BTreeMap::new);
+            let changed = m
+                .get(&ip_port)
+                .map_or(true, |old| old.expire_time < now || host.changed_from(old));
+            m.insert(ip_port.to_owned(), host);
+            changed
+        };
+        if changed {
+            self.lock_indexes().bump(name);
+        }
+        info!(
+            "store_item(): succeed to store item: service={}, ip_port={}",
+            name, ip_port
+        );
+        Ok(())
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E> {
+        let now = self.epoch_now()?;
+        let ip_port = format!("{}:{}", ip, port);
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let removed = hosts.get_mut(name).and_then(|m| m.remove(&ip_port));
+        if removed.is_some() {
+            self.lock_indexes().bump(name);
+        }
+        info!(
+            "delete_item(): succeed to delete_item item: service={}, ip_port={}",
+            name, ip_port
+        );
+        Ok(removed.filter(|h| h.expire_time >= now))
+    }
+
+    fn reap_expired(&self, now: u64) -> Result<Vec<Host>, Self::E> {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let mut reaped = Vec::new();
+        let mut indexes = self.lock_indexes();
+        for (name, m) in hosts.iter_mut() {
+            let expired: Vec<String> = m
+                .iter()
+                .filter(|(_, h)| h.expire_time < now)
+                .map(|(k, _)| k.to_owned())
+                .collect();
+            if expired.is_empty() {
+                continue;
+            }
+            for k in expired {
+                if let Some(h) = m.remove(&k) {
+                    reaped.push(h);
+                }
+            }
+            indexes.bump(name);
+        }
+        hosts.retain(|_, m| !m.is_empty());
+        Ok(reaped)
+    }
+
+    fn query_by_ip(&self, ip: &str) -> Result<Vec<Host>, Self::E> {
+        let now = self.epoch_now()?;
+        let hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(hosts
+            .values()
+            .flat_map(|m| m.values())
+            .filter(|h| h.ip_address == ip && h.expire_time >= now)
+            .cloned()
+            .collect())
+    }
+
+    fn list_services(&self) -> Result<Vec<String>, Self::E> {
+        let now = self.epoch_now()?;
+        let hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(hosts
+            .iter()
+            .filter(|(_, m)| m.values().any(|h| h.expire_time >= now))
+            .map(|(name, _)| name.to_owned())
+            .collect())
+    }
+
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, Self::E> {
+        Ok(self
+            .aliases
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone())
+    }
+
+    fn put_alias(&self, alias: &str, service: &str) -> Result<bool, Self::E> {
+        let mut aliases = self.aliases.lock().unwrap_or_else(|e| e.into_inner());
+        aliases.insert(alias.to_owned(), service.to_owned());
+        Ok(true)
+    }
+
+    fn delete_alias(&self, alias: &str) -> Result<Option<String>, Self::E> {
+        let mut aliases = self.aliases.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(aliases.remove(alias))
+    }
+
+    fn registry_index(&self, name: &str) -> Result<Option<RegistryIndex>, Self::E> {
+        let indexes = self.lock_indexes();
+        Ok(Some(RegistryIndex {
+            service: indexes.services.get(name).cloned().unwrap_or(0),
+            global: indexes.global,
+        }))
+    }
+
+    fn ttl(&self) -> u64 {
+        self.ttl
+    }
+}
+
+pub fn create(settings: &StorageSettings) -> Result<DynStorage, StorageError> {
+    Ok(DynStorage::new(MemoryStorage::with_clock(
+        settings.ttl,
+        settings.clock.clone(),
+    )))
+}
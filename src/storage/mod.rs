@@ -0,0 +1,321 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::clock::SharedClock;
+use super::types::{Drift, Freshness, Host, RegistryIndex, SchemaCheck, Storage};
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb;
+#[cfg(feature = "memory")]
+pub mod memory;
+
+#[cfg(feature = "dynamodb")]
+pub use self::dynamodb::StorageImpl;
+#[cfg(feature = "memory")]
+pub use self::memory::MemoryStorage;
+
+#[derive(Debug, Clone)]
+enum ErrorKind {
+    Api,
+    Data,
+    System,
+    Timeout,
+    // The table or keyspace is missing or has an unexpected schema.
+    Schema,
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageError {
+    kind: ErrorKind,
+    msg: String,
+}
+
+impl StorageError {
+    pub fn new(msg: String) -> Self {
+        StorageError {
+            kind: ErrorKind::Api,
+            msg,
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl error::Error for StorageError {
+    fn cause(&self) -> Option<&error::Error> {
+        // TODO
+        None
+    }
+}
+
+// Object safe counterpart of Storage so that backends can be chosen at runtime.
+trait ErasedStorage: Send + Sync {
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, StorageError>;
+    fn store_item(&self, name: &str, host: Host) -> Result<(), StorageError>;
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, StorageError>;
+    fn get_item(&self, name: &str, ip: &str, port: u16) -> Result<Option<Host>, StorageError>;
+    fn reap_expired(&self, now: u64) -> Result<Vec<Host>, StorageError>;
+    fn query_by_ip(&self, ip: &str) -> Result<Vec<Host>, StorageError>;
+    fn list_services(&self) -> Result<Vec<String>, StorageError>;
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, StorageError>;
+    fn put_alias(&self, alias: &str, service: &str) -> Result<bool, StorageError>;
+    fn delete_alias(&self, alias: &str) -> Result<Option<String>, StorageError>;
+    fn registry_index(&self, name: &str) -> Result<Option<RegistryIndex>, StorageError>;
+    fn freshness(&self, name: &str) -> Freshness;
+    fn ttl(&self) -> u64;
+    fn with_deadline(&self, deadline: Instant) -> Arc<dyn ErasedStorage>;
+    fn check_schema(&self) -> Result<SchemaCheck, StorageError>;
+    fn init_schema(&self) -> Result<(), StorageError>;
+    fn warm_up(&self) -> Result<(), StorageError>;
+    fn resync(&self) -> Result<Vec<Drift>, StorageError>;
+}
+
+impl<S: Storage> ErasedStorage for S {
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, StorageError> {
+        Storage::query_items(self, name).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn store_item(&self, name: &str, host: Host) -> Result<(), StorageError> {
+        Storage::store_item(self, name, host).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, StorageError> {
+        Storage::delete_item(self, name, ip, port).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn get_item(&self, name: &str, ip: &str, port: u16) -> Result<Option<Host>, StorageError> {
+        Storage::get_item(self, name, ip, port).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn reap_expired(&self, now: u64) -> Result<Vec<Host>, StorageError> {
+        Storage::reap_expired(self, now).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn query_by_ip(&self, ip: &str) -> Result<Vec<Host>, StorageError> {
+        Storage::query_by_ip(self, ip).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn list_services(&self) -> Result<Vec<String>, StorageError> {
+        Storage::list_services(self).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, StorageError> {
+        Storage::list_aliases(self).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn put_alias(&self, alias: &str, service: &str) -> Result<bool, StorageError> {
+        Storage::put_alias(self, alias, service).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn delete_alias(&self, alias: &str) -> Result<Option<String>, StorageError> {
+        Storage::delete_alias(self, alias).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn registry_index(&self, name: &str) -> Result<Option<RegistryIndex>, StorageError> {
+        Storage::registry_index(self, name).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn freshness(&self, name: &str) -> Freshness {
+        Storage::freshness(self, name)
+    }
+
+    fn ttl(&self) -> u64 {
+        Storage::ttl(self)
+    }
+
+    fn with_deadline(&self, deadline: Instant) -> Arc<dyn ErasedStorage> {
+        Arc::new(Storage::with_deadline(self, deadline))
+    }
+
+    fn check_schema(&self) -> Result<SchemaCheck, StorageError> {
+        Storage::check_schema(self).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn init_schema(&self) -> Result<(), StorageError> {
+        Storage::init_schema(self).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn warm_up(&self) -> Result<(), StorageError> {
+        Storage::warm_up(self).map_err(|e| StorageError::new(e.to_string()))
+    }
+
+    fn resync(&self) -> Result<Vec<Drift>, StorageError> {
+        Storage::resync(self).map_err(|e| StorageError::new(e.to_string()))
+    }
+}
+
+// Storage backend chosen at runtime, created by StorageRegistry.
+#[derive(Clone)]
+pub struct DynStorage(Arc<dyn ErasedStorage>);
+
+impl DynStorage {
+    pub fn new<S: Storage>(s: S) -> Self {
+        DynStorage(Arc::new(s))
+    }
+}
+
+impl Storage for DynStorage {
+    type E = StorageError;
+
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E> {
+        self.0.query_items(name)
+    }
+
+    fn store_item(&self, name: &str, host: Host) -> Result<(), Self::E> {
+        self.0.store_item(name, host)
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E> {
+        self.0.delete_item(name, ip, port)
+    }
+
+    fn get_item(&self, name: &str, ip: &str, port: u16) -> Result<Option<Host>, Self::E> {
+        self.0.get_item(name, ip, port)
+    }
+
+    fn reap_expired(&self, now: u64) -> Result<Vec<Host>, Self::E> {
+        self.0.reap_expired(now)
+    }
+
+    fn query_by_ip(&self, ip: &str) -> Result<Vec<Host>, Self::E> {
+        self.0.query_by_ip(ip)
+    }
+
+    fn list_services(&self) -> Result<Vec<String>, Self::E> {
+        self.0.list_services()
+    }
+
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, Self::E> {
+        self.0.list_aliases()
+    }
+
+    fn put_alias(&self, alias: &str, service: &str) -> Result<bool, Self::E> {
+        self.0.put_alias(alias, service)
+    }
+
+    fn delete_alias(&self, alias: &str) -> Result<Option<String>, Self::E> {
+        self.0.delete_alias(alias)
+    }
+
+    fn registry_index(&self, name: &str) -> Result<Option<RegistryIndex>, Self::E> {
+        self.0.registry_index(name)
+    }
+
+    fn freshness(&self, name: &str) -> Freshness {
+        self.0.freshness(name)
+    }
+
+    fn ttl(&self) -> u64 {
+        self.0.ttl()
+    }
+
+    fn with_deadline(&self, deadline: Instant) -> Self {
+        DynStorage(self.0.with_deadline(deadline))
+    }
+
+    fn check_schema(&self) -> Result<SchemaCheck, Self::E> {
+        self.0.check_schema()
+    }
+
+    fn init_schema(&self) -> Result<(), Self::E> {
+        self.0.init_schema()
+    }
+
+    fn warm_up(&self) -> Result<(), Self::E> {
+        self.0.warm_up()
+    }
+
+    fn resync(&self) -> Result<Vec<Drift>, Self::E> {
+        self.0.resync()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageSettings {
+    pub ttl: u64,
+    pub timeout: Duration,
+    // Time source of expiry, shared with Config.clock so that a MockClock controls both.
+    pub clock: SharedClock,
+    // Backend specific options, e.g. "table_name" of dynamodb.
+    pub options: HashMap<String, String>,
+}
+
+pub trait StorageFactory: Send + Sync {
+    fn create(&self, settings: &StorageSettings) -> Result<DynStorage, StorageError>;
+}
+
+impl<F> StorageFactory for F
+where
+    F: Fn(&StorageSettings) -> Result<DynStorage, StorageError> + Send + Sync,
+{
+    fn create(&self, settings: &StorageSettings) -> Result<DynStorage, StorageError> {
+        self(settings)
+    }
+}
+
+// Storage backends keyed by the storage type string of the config.
+pub struct StorageRegistry {
+    factories: HashMap<String, Box<dyn StorageFactory>>,
+}
+
+impl StorageRegistry {
+    // Returns a registry with the backends enabled by cargo features.
+    pub fn new() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = StorageRegistry::empty();
+        #[cfg(feature = "dynamodb")]
+        registry.register("dynamodb", dynamodb::create);
+        #[cfg(feature = "memory")]
+        registry.register("memory", memory::create);
+        registry
+    }
+
+    pub fn empty() -> Self {
+        StorageRegistry {
+            factories: HashMap::new(),
+        }
+    }
+
+    // Registers a backend. A backend registered with an existing type replaces the old one.
+    pub fn register<F>(&mut self, storage_type: &str, factory: F)
+    where
+        F: StorageFactory + 'static,
+    {
+        self.factories
+            .insert(storage_type.to_owned(), Box::new(factory));
+    }
+
+    pub fn create(
+        &self,
+        storage_type: &str,
+        settings: &StorageSettings,
+    ) -> Result<DynStorage, StorageError> {
+        match self.factories.get(storage_type) {
+            Some(factory) => factory.create(settings),
+            None => Err(StorageError::new(format!(
+                "Unknown storage type: {} (available: {})",
+                storage_type,
+                self.storage_types().join(", ")
+            ))),
+        }
+    }
+
+    pub fn storage_types(&self) -> Vec<&str> {
+        let mut types: Vec<&str> = self.factories.keys().map(|k| k.as_str()).collect();
+        types.sort();
+        types
+    }
+}
+
+impl Default for StorageRegistry {
+    fn default() -> Self {
+        StorageRegistry::new()
+    }
+}
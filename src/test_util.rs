@@ -0,0 +1,183 @@
+use std::net::{SocketAddr, TcpListener};
+use std::thread::{self, JoinHandle};
+
+use futures::sync::oneshot;
+use futures::{Future, Stream};
+use hyper::server::conn::AddrStream;
+use hyper::service::make_service_fn;
+use hyper::{Body, Client, HeaderMap, Method, Request, Server, StatusCode};
+use log::error;
+use tokio::runtime::current_thread;
+
+use super::server::SdsService;
+use super::storage::MemoryStorage;
+use super::types::Config;
+
+const DEFAULT_TTL: u64 = 30;
+
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+// sds serving on an ephemeral port of localhost with the in-memory backend, for end-to-end tests.
+// The server stops when the handle is shut down or dropped.
+pub struct TestServer {
+    addr: SocketAddr,
+    storage: MemoryStorage,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub fn start() -> Result<Self, String> {
+        TestServer::start_with(Config::default())
+    }
+
+    // Starts with the given config. listen_port and reuse_port are ignored.
+    pub fn start_with(config: Config) -> Result<Self, String> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|e| format!("Failed to bind test server: {}", e))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to get local address: {}", e))?;
+        let storage = MemoryStorage::with_clock(DEFAULT_TTL, config.clock.clone());
+        let service = SdsService::builder(storage.clone()).config(config).build();
+        let (tx, rx) = oneshot::channel::<()>();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            let mut rt = match current_thread::Runtime::new() {
+                Ok(v) => v,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(format!("Failed to start runtime: {}", e)));
+                    return;
+                }
+            };
+            // from_tcp() needs the reactor of the runtime, so the server is built inside it.
+            let server = futures::future::lazy(move || {
+                let server = match Server::from_tcp(listener) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(format!("Failed to start test server: {}", e)));
+                        return futures::future::Either::A(futures::future::ok(()));
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                futures::future::Either::B(
+                    server
+                        .serve(make_service_fn(move |conn: &AddrStream| {
+                            Ok::<_, hyper::Error>(service.with_remote_addr(conn.remote_addr()))
+                        }))
+                        .with_graceful_shutdown(rx)
+                        .map_err(|e| error!("test server error: {}", e)),
+                )
+            });
+            let _ = rt.block_on(server);
+        });
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(TestServer {
+                addr,
+                storage,
+                shutdown: Some(tx),
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err("Test server thread exited unexpectedly".to_owned()),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // e.g. "http://127.0.0.1:12345"
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    // The backend of the server, to seed or inspect hosts directly.
+    pub fn storage(&self) -> &MemoryStorage {
+        &self.storage
+    }
+
+    // Sends a request to the server and waits for the whole response.
+    pub fn request(&self, method: Method, path: &str, body: &str) -> Result<TestResponse, String> {
+        self.request_with_headers(method, path, body, &[])
+    }
+
+    // Same as request() with additional request headers, e.g. Idempotency-Key.
+    pub fn request_with_headers(
+        &self,
+        method: Method,
+        path: &str,
+        body: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<TestResponse, String> {
+        let mut builder = Request::builder();
+        builder
+            .method(method)
+            .uri(format!("{}{}", self.base_url(), path));
+        for (name, value) in headers {
+            builder.header(*name, *value);
+        }
+        let req = builder
+            .body(Body::from(body.to_owned()))
+            .map_err(|e| e.to_string())?;
+        let mut rt = current_thread::Runtime::new().map_err(|e| e.to_string())?;
+        let client = Client::new();
+        let f = client.request(req).and_then(|res| {
+            let status = res.status();
+            let headers = res.headers().clone();
+            res.into_body().concat2().map(move |body| TestResponse {
+                status,
+                headers,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            })
+        });
+        rt.block_on(f).map_err(|e| e.to_string())
+    }
+
+    pub fn get(&self, path: &str) -> Result<TestResponse, String> {
+        self.request(Method::GET, path, "")
+    }
+
+    pub fn post(&self, path: &str, body: &str) -> Result<TestResponse, String> {
+        self.request(Method::POST, path, body)
+    }
+
+    pub fn put(&self, path: &str, body: &str) -> Result<TestResponse, String> {
+        self.request(Method::PUT, path, body)
+    }
+
+    pub fn patch(&self, path: &str, body: &str) -> Result<TestResponse, String> {
+        self.request(Method::PATCH, path, body)
+    }
+
+    pub fn delete(&self, path: &str) -> Result<TestResponse, String> {
+        self.request(Method::DELETE, path, "")
+    }
+
+    // Stops accepting connections and waits for the server thread to finish.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -1,18 +1,274 @@
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::error;
 use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::admission::AdmissionConfig;
+use super::clock::{system_clock, SharedClock};
+use super::config::{FileConfig, ReloadableConfig};
+use super::events::EventConfig;
+use super::feedback::FeedbackConfig;
+use super::limiter::LimitConfig;
+use super::maintenance::MaintenanceConfig;
+use super::metrics::Metrics;
+use super::payload_log::PayloadLogConfig;
+use super::shutdown::ShutdownConfig;
+use super::xds_file::XdsFileConfig;
 
 pub trait Storage: Send + Sync + Clone + 'static {
     type E: fmt::Display + error::Error;
     fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E>;
     fn store_item(&self, name: &str, host: Host) -> Result<(), Self::E>;
     fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E>;
+    // Storages supporting point lookups should override this.
+    fn get_item(&self, name: &str, ip: &str, port: u16) -> Result<Option<Host>, Self::E> {
+        let hosts = self.query_items(name)?;
+        Ok(hosts
+            .into_iter()
+            .find(|h| h.ip_address == ip && h.port == port))
+    }
+    // Removes hosts expired before `now` (epoch seconds) and returns the removed records. Each
+    // record must be returned by only one of concurrent callers. Storages that can't enumerate
+    // hosts return nothing.
+    fn reap_expired(&self, _now: u64) -> Result<Vec<Host>, Self::E> {
+        Ok(Vec::new())
+    }
+    // Returns live hosts with the ip address across all services, used to detect hosts
+    // registered under more than one service. Storages that can't look up hosts by ip return
+    // nothing, which disables the detection.
+    fn query_by_ip(&self, _ip: &str) -> Result<Vec<Host>, Self::E> {
+        Ok(Vec::new())
+    }
+    // Returns names of services with live hosts, used to evaluate quotas on the number of
+    // services. Storages that can't enumerate services return nothing.
+    fn list_services(&self) -> Result<Vec<String>, Self::E> {
+        Ok(Vec::new())
+    }
+    // Returns alias -> service records. Storages without alias support return nothing.
+    fn list_aliases(&self) -> Result<BTreeMap<String, String>, Self::E> {
+        Ok(BTreeMap::new())
+    }
+    // Points the alias to the service. Returns false when the storage doesn't support aliases.
+    fn put_alias(&self, _alias: &str, _service: &str) -> Result<bool, Self::E> {
+        Ok(false)
+    }
+    // Removes the alias and returns the service it pointed to.
+    fn delete_alias(&self, _alias: &str) -> Result<Option<String>, Self::E> {
+        Ok(None)
+    }
+    // Returns the change counters of the registry and the service. Storages not tracking changes
+    // return None.
+    fn registry_index(&self, _name: &str) -> Result<Option<RegistryIndex>, Self::E> {
+        Ok(None)
+    }
+    // Describes where query_items() of the service reads from and how old the data is. Storages
+    // serving from memory or replicas should override this.
+    fn freshness(&self, _name: &str) -> Freshness {
+        Freshness::live()
+    }
     fn ttl(&self) -> u64;
+    // Returns a storage whose API calls give up once the deadline passes. Storages without
+    // remote calls ignore the deadline, wrappers should pass it to their inner storage.
+    fn with_deadline(&self, _deadline: Instant) -> Self {
+        self.clone()
+    }
+    // Verifies the table or keyspace exists with the schema this version of sds expects. Called
+    // on startup before warm_up(), readiness stays down until it's compatible. Errors, e.g.
+    // throttling or timeouts, don't tell about the schema and are retried.
+    fn check_schema(&self) -> Result<SchemaCheck, Self::E> {
+        Ok(SchemaCheck::Compatible)
+    }
+    // Creates the table or keyspace when missing and records the schema version, for
+    // `--init-storage`.
+    fn init_schema(&self) -> Result<(), Self::E> {
+        Ok(())
+    }
+    // Called in background on startup, readiness stays down until it succeeds. Storages with a
+    // cache or replication layer should complete their initial sync here.
+    fn warm_up(&self) -> Result<(), Self::E> {
+        Ok(())
+    }
+    // Called periodically by the anti-entropy job. Storages with a cache or replication layer
+    // should re-read the authoritative storage, repair their view and report the drift found. A
+    // service failing to resync shouldn't stop the others, report it by Drift.error instead.
+    fn resync(&self) -> Result<Vec<Drift>, Self::E> {
+        Ok(Vec::new())
+    }
+}
+
+// Sorts hosts by ip and port so that responses are stable across calls. IP addresses are compared
+// numerically, e.g. 10.0.0.2 comes before 10.0.0.10.
+pub fn sort_hosts(hosts: &mut [Host]) {
+    hosts.sort_by(|a, b| {
+        let a_ip = a.ip_address.parse::<IpAddr>().ok();
+        let b_ip = b.ip_address.parse::<IpAddr>().ok();
+        (a_ip, &a.ip_address, a.port).cmp(&(b_ip, &b.ip_address, b.port))
+    });
+}
+
+// Counters of changes to the registry, bumped on registrations that change a host, deregistrations
+// and reaps but not on plain check-ins. The index of a service is the global index at its last
+// change, so both only grow and compare across services.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegistryIndex {
+    pub service: u64,
+    pub global: u64,
+}
+
+// Where served hosts come from, from the most to the least trustworthy.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum DataSource {
+    // Read from the authoritative storage for the request.
+    Live,
+    // Served from memory, refreshed by resyncs.
+    Cached,
+    // Served from memory since the last refresh failed.
+    StaleFallback,
+}
+
+impl fmt::Display for DataSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataSource::Live => write!(f, "live"),
+            DataSource::Cached => write!(f, "cached"),
+            DataSource::StaleFallback => write!(f, "stale-fallback"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Freshness {
+    pub source: DataSource,
+    // Seconds since the data was read from the authoritative storage.
+    pub age_seconds: u64,
+}
+
+impl Freshness {
+    pub fn live() -> Self {
+        Freshness {
+            source: DataSource::Live,
+            age_seconds: 0,
+        }
+    }
+
+    // Freshness of a response combining both, i.e. the less trustworthy source and the older age.
+    pub fn merge(self, other: Freshness) -> Freshness {
+        Freshness {
+            source: self.source.max(other.source),
+            age_seconds: self.age_seconds.max(other.age_seconds),
+        }
+    }
+
+    // Value of the X-SDS-Data-Freshness header, e.g. "cached; age=12".
+    pub fn header_value(&self) -> String {
+        format!("{}; age={}", self.source, self.age_seconds)
+    }
+}
+
+// Outcome of a schema check which reached the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaCheck {
+    Compatible,
+    // The table or keyspace is missing or has a schema this version of sds can't use.
+    Incompatible(String),
+}
+
+// Discrepancies of a service found by a resync.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Drift {
+    pub service: String,
+    // Hosts in the storage but missing in the view.
+    pub missing: u64,
+    // Hosts in the view but not in the storage anymore.
+    pub extra: u64,
+    // Hosts whose record in the view differs from the storage.
+    pub stale: u64,
+    // Why the resync of the service failed, the view of the service is kept as is then.
+    pub error: Option<String>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.missing == 0 && self.extra == 0 && self.stale == 0 && self.error.is_none()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub listen_port: u16,
+    pub file: ReloadableConfig,
+    pub feedback: FeedbackConfig,
+    pub idempotency_window: Duration,
+    pub readiness_timeout: Duration,
+    // Maximum number of clusters of an EDS request queried at the same time.
+    pub eds_query_concurrency: usize,
+    // Binds a socket with SO_REUSEPORT per core thread instead of a single listening socket.
+    pub reuse_port: bool,
+    pub events: EventConfig,
+    // Interval of removing expired hosts from the storage. The reaper is disabled when missing.
+    pub reaper_interval: Option<Duration>,
+    // Interval of Storage::resync(). The anti-entropy job is disabled when missing.
+    pub resync_interval: Option<Duration>,
+    // Renders EDS resources into files for Envoys using filesystem xDS. Disabled when missing.
+    pub xds_file: Option<XdsFileConfig>,
+    // Rejects registration and feedback requests with unknown fields.
+    pub strict_json: bool,
+    // HTTP-date when the deprecated v1 SDS API is removed, sent in Sunset headers.
+    pub sds_v1_sunset: Option<String>,
+    pub limits: LimitConfig,
+    // Time source of expiry, reaping, draining and slow-start.
+    pub clock: SharedClock,
+    // Keeps last_check_in and expire_time in responses besides their RFC 3339 variants.
+    pub legacy_time_fields: bool,
+    // Shared with storages which record their own metrics, like ShadowStorage.
+    pub metrics: Metrics,
+    // Serves /metrics in Prometheus format. Disabled when metrics only go to statsd.
+    pub metrics_endpoint: bool,
+    pub maintenance: MaintenanceConfig,
+    // Initial config of payload logging, which can be changed by the admin API.
+    pub payload_log: PayloadLogConfig,
+    // Port serving grpc.health.v1.Health over HTTP/2. Disabled when missing.
+    pub grpc_health_port: Option<u16>,
+    pub shutdown: ShutdownConfig,
+    // Region this sds runs in, used by region pinning for clients not telling their region.
+    pub region: Option<String>,
+    // Webhook reviewing registrations before they are stored. Disabled when missing.
+    pub admission: Option<AdmissionConfig>,
+}
+
+impl Default for Config {
+    // Listens on an ephemeral port without config file.
+    fn default() -> Self {
+        Config {
+            listen_port: 0,
+            file: ReloadableConfig::new(None, FileConfig::default()),
+            feedback: FeedbackConfig::default(),
+            idempotency_window: Duration::from_secs(300),
+            readiness_timeout: Duration::from_secs(30),
+            eds_query_concurrency: 8,
+            reuse_port: false,
+            events: EventConfig::default(),
+            reaper_interval: None,
+            resync_interval: None,
+            xds_file: None,
+            strict_json: false,
+            sds_v1_sunset: None,
+            limits: LimitConfig::default(),
+            clock: system_clock(),
+            legacy_time_fields: true,
+            metrics: Metrics::new(),
+            metrics_endpoint: true,
+            maintenance: MaintenanceConfig::default(),
+            payload_log: PayloadLogConfig::default(),
+            grpc_health_port: None,
+            shutdown: ShutdownConfig::default(),
+            region: None,
+            admission: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -22,7 +278,7 @@ pub struct Registration {
     pub hosts: Vec<Host>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Host {
     pub ip_address: String,
     pub port: u16,
@@ -31,9 +287,27 @@ pub struct Host {
     pub revision: String,
     pub service: String,
     pub tags: Tag,
+    // Epoch seconds when the host started draining. Its EDS weight ramps down from then on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drain_started_at: Option<u64>,
+    // Epoch seconds of the first registration, kept across check-ins for slow-start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registered_at: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Host {
+    // Whether the host differs from the old record other than by a check-in extending its expiry.
+    pub fn changed_from(&self, old: &Host) -> bool {
+        self.ip_address != old.ip_address
+            || self.port != old.port
+            || self.revision != old.revision
+            || self.service != old.service
+            || self.tags != old.tags
+            || self.drain_started_at != old.drain_started_at
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Tag {
     pub az: String,
     pub region: String,
@@ -41,4 +315,8 @@ pub struct Tag {
     pub canary: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub load_balancing_weight: Option<u8>,
+    // Arbitrary tags like "version". String values of the keys allowed by the service's
+    // metadata_keys are exposed as endpoint metadata in EDS.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
 }
@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet};
 
 use serde_derive::{Deserialize, Serialize};
 use serde_json;
 
+use super::config::ServiceConfig;
 use super::types::Host;
 
 pub const EDS_TYPE_URL: &str = "type.googleapis.com/envoy.api.v2.ClusterLoadAssignment";
 
+const DEFAULT_DRAIN_PERIOD_SEC: u64 = 60;
+// Weight factor of a host just registered, so that it still takes a share of the traffic.
+const SLOW_START_MIN_FACTOR: f64 = 0.1;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DiscoveryRequest {
     pub version_info: Option<String>,
@@ -27,6 +32,8 @@ pub struct EdsDiscoveryResponse {
 pub struct Node {
     pub id: String,
     pub cluster: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locality: Option<Locality>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -40,6 +47,8 @@ pub struct Status {
 pub struct ClusterLoadAssignment {
     pub cluster_name: String,
     pub endpoints: Vec<LocalityLbEndpoints>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
     #[serde(rename = "@type")]
     pub type_url: String,
 }
@@ -48,9 +57,18 @@ pub struct ClusterLoadAssignment {
 pub struct LocalityLbEndpoints {
     pub locality: Locality,
     pub lb_endpoints: Vec<LbEndpoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Policy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overprovisioning_factor: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(default)]
 pub struct Locality {
     pub region: String,
     pub zone: String,
@@ -61,9 +79,17 @@ pub struct LbEndpoint {
     pub endpoint: Endpoint,
     pub metadata: Metadata,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_status: Option<HealthStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub load_balancing_weight: Option<u8>,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HealthStatus {
+    Degraded,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Endpoint {
     pub address: Address,
@@ -82,55 +108,180 @@ pub struct SocketAddress {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Metadata {
-    pub filter_metadata: HashMap<String, LbFilterMetadata>,
+    pub filter_metadata: BTreeMap<String, LbFilterMetadata>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LbFilterMetadata {
     pub canary: bool,
+    #[serde(flatten)]
+    pub tags: BTreeMap<String, String>,
 }
 
-pub fn hosts_to_locality_lb_endpoints(mut hosts: Vec<Host>) -> Vec<LocalityLbEndpoints> {
-    let mut lle_map: HashMap<Locality, Vec<LbEndpoint>> = HashMap::new();
-    for h in hosts.drain(..) {
+pub fn build_policy(service_config: Option<&ServiceConfig>) -> Option<Policy> {
+    service_config
+        .and_then(|c| c.overprovisioning_factor)
+        .map(|factor| Policy {
+            overprovisioning_factor: Some(factor),
+        })
+}
+
+// `degraded` holds "ip:port" of endpoints to be served with DEGRADED health status. `now` is epoch
+// seconds, weights of draining and slow-starting hosts are computed against it. `node_locality`
+// is the locality of the requesting Envoy, used for zone-aware priorities.
+pub fn hosts_to_locality_lb_endpoints(
+    hosts: Vec<Host>,
+    service_config: Option<&ServiceConfig>,
+    degraded: &HashSet<String>,
+    now: u64,
+    node_locality: Option<&Locality>,
+) -> Vec<LocalityLbEndpoints> {
+    // Sorted by locality so that responses are stable across calls. Endpoints keep the order of
+    // `hosts`, see sort_hosts().
+    let mut lle_map: BTreeMap<Locality, Vec<LbEndpoint>> = BTreeMap::new();
+    for (h, weight) in compute_weights(hosts, service_config, now) {
         let locality = Locality {
             region: h.tags.region.to_owned(),
             zone: h.tags.az.to_owned(),
         };
-        let le = convert_host_to_le(h);
-
-        match lle_map.entry(locality) {
-            std::collections::hash_map::Entry::Vacant(e) => {
-                e.insert(vec![le]);
-            }
-            std::collections::hash_map::Entry::Occupied(mut e) => {
-                e.get_mut().push(le);
-            }
-        }
+        let is_degraded = degraded.contains(&format!("{}:{}", h.ip_address, h.port));
+        let le = convert_host_to_le(h, weight, is_degraded, service_config);
+
+        lle_map.entry(locality).or_insert_with(Vec::new).push(le);
     }
 
     let mut lle_vec = Vec::new();
     for (k, v) in lle_map {
+        let priority = service_config.and_then(|c| locality_priority(c, &k, node_locality));
         lle_vec.push(LocalityLbEndpoints {
             locality: k,
             lb_endpoints: v,
+            priority,
         });
     }
     lle_vec
 }
 
-fn convert_host_to_le(h: Host) -> LbEndpoint {
-    let mut filter_metadata = HashMap::new();
+// Returns the hosts to serve with their load_balancing_weight. Hosts with a reduced weight factor
+// make the weights of the whole cluster scaled up so that the reduction can be expressed in
+// Envoy's integer weights. Hosts whose factor reached zero are left out.
+fn compute_weights(
+    hosts: Vec<Host>,
+    service_config: Option<&ServiceConfig>,
+    now: u64,
+) -> Vec<(Host, Option<u8>)> {
+    let factors: Vec<(Host, f64)> = hosts
+        .into_iter()
+        .map(|h| {
+            let f = weight_factor(&h, service_config, now);
+            (h, f)
+        })
+        .filter(|(_, f)| *f > 0.0)
+        .collect();
+    if factors.iter().all(|(_, f)| *f >= 1.0) {
+        return factors
+            .into_iter()
+            .map(|(h, _)| {
+                let w = h.tags.load_balancing_weight;
+                (h, w)
+            })
+            .collect();
+    }
+
+    let max_weight = factors
+        .iter()
+        .map(|(h, _)| u32::from(h.tags.load_balancing_weight.unwrap_or(1)))
+        .max()
+        .unwrap_or(1);
+    let scale = (u32::from(u8::max_value()) / max_weight).max(1);
+    factors
+        .into_iter()
+        .map(|(h, f)| {
+            let base = u32::from(h.tags.load_balancing_weight.unwrap_or(1)) * scale;
+            let w = (f64::from(base) * f).round().max(1.0).min(255.0) as u8;
+            (h, Some(w))
+        })
+        .collect()
+}
+
+// Returns the fraction (0.0 - 1.0) of the host's weight to serve at `now`.
+fn weight_factor(h: &Host, service_config: Option<&ServiceConfig>, now: u64) -> f64 {
+    let mut factor = 1.0;
+    if let Some(started_at) = h.drain_started_at {
+        let period = service_config
+            .and_then(|c| c.drain_period_sec)
+            .unwrap_or(DEFAULT_DRAIN_PERIOD_SEC);
+        factor *= 1.0 - ramp_progress(started_at, period, now);
+    }
+    let slow_start = service_config.and_then(|c| c.slow_start_sec);
+    if let (Some(period), Some(registered_at)) = (slow_start, h.registered_at) {
+        factor *= ramp_progress(registered_at, period, now).max(SLOW_START_MIN_FACTOR);
+    }
+    factor
+}
+
+// Progress (0.0 - 1.0) of a ramp taking `period` seconds from `started_at`.
+fn ramp_progress(started_at: u64, period: u64, now: u64) -> f64 {
+    if period == 0 {
+        return 1.0;
+    }
+    (now.saturating_sub(started_at) as f64 / period as f64).min(1.0)
+}
+
+fn locality_priority(
+    service_config: &ServiceConfig,
+    locality: &Locality,
+    node_locality: Option<&Locality>,
+) -> Option<u32> {
+    if service_config.zone_aware {
+        if let Some(node) = node_locality {
+            return Some(zone_aware_priority(locality, node));
+        }
+    }
+    if service_config.priorities.is_empty() {
+        return None;
+    }
+    match service_config.priorities.get(&locality.zone) {
+        Some(p) => Some(*p),
+        None => service_config.priorities.values().max().map(|p| p + 1),
+    }
+}
+
+// Prefers the node's zone, then the rest of its region, then anywhere.
+fn zone_aware_priority(locality: &Locality, node: &Locality) -> u32 {
+    if locality.region != node.region {
+        2
+    } else if locality.zone != node.zone {
+        1
+    } else {
+        0
+    }
+}
+
+fn convert_host_to_le(
+    h: Host,
+    weight: Option<u8>,
+    is_degraded: bool,
+    service_config: Option<&ServiceConfig>,
+) -> LbEndpoint {
+    let metadata_keys = service_config.map_or(&[][..], |c| &c.metadata_keys[..]);
+    let mut filter_metadata = BTreeMap::new();
     filter_metadata.insert(
         "envoy.lb".to_owned(),
         LbFilterMetadata {
             canary: h.tags.canary,
+            tags: select_tags(&h, metadata_keys),
         },
     );
 
     LbEndpoint {
-        load_balancing_weight: h.tags.load_balancing_weight,
+        load_balancing_weight: weight,
         metadata: Metadata { filter_metadata },
+        health_status: if is_degraded {
+            Some(HealthStatus::Degraded)
+        } else {
+            None
+        },
         endpoint: Endpoint {
             address: Address {
                 socket_address: SocketAddress {
@@ -141,3 +292,22 @@ fn convert_host_to_le(h: Host) -> LbEndpoint {
         },
     }
 }
+
+// Returns the tags of the allowed keys. "canary" is always in the metadata as a bool.
+fn select_tags(h: &Host, keys: &[String]) -> BTreeMap<String, String> {
+    let mut tags = BTreeMap::new();
+    for k in keys {
+        let v = match k.as_str() {
+            "az" => Some(h.tags.az.as_str()),
+            "region" => Some(h.tags.region.as_str()),
+            "instance_id" => Some(h.tags.instance_id.as_str()),
+            "revision" => Some(h.revision.as_str()),
+            "canary" => None,
+            _ => h.tags.extra.get(k).and_then(|v| v.as_str()),
+        };
+        if let Some(v) = v {
+            tags.insert(k.to_owned(), v.to_owned());
+        }
+    }
+    tags
+}
@@ -0,0 +1,139 @@
+use hyper::header::HeaderValue;
+use hyper::{Body, Method, Response};
+use log::warn;
+use serde_derive::Serialize;
+
+use super::request::{
+    match_drain_path, match_feedback_path, match_host_path, match_registration_path,
+    match_tags_path,
+};
+
+const API_VERSION_HEADER: &str = "x-sds-api-version";
+const DEPRECATION_HEADER: &str = "deprecation";
+const SUNSET_HEADER: &str = "sunset";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Stable,
+    // Still served but going to be removed, clients should move to the successor.
+    Deprecated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Api {
+    RegistrationV1,
+    FeedbackV1,
+    // v1 SDS for Envoy's v1 API, superseded by v2 EDS.
+    SdsV1,
+    EdsV2,
+}
+
+const APIS: &[Api] = &[Api::RegistrationV1, Api::FeedbackV1, Api::SdsV1, Api::EdsV2];
+
+#[derive(Serialize, Debug)]
+pub struct ApiVersion {
+    pub api: &'static str,
+    pub version: &'static str,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<String>,
+    pub routes: &'static [&'static str],
+}
+
+#[derive(Serialize, Debug)]
+pub struct Versions {
+    pub versions: Vec<ApiVersion>,
+}
+
+impl Api {
+    // Returns the API serving the request, None for health checks and admin endpoints.
+    pub fn of(method: &Method, path: &str) -> Option<Api> {
+        match *method {
+            Method::GET if match_registration_path(path).is_some() => Some(Api::SdsV1),
+            Method::POST if path == "/v2/discovery:endpoints" => Some(Api::EdsV2),
+            Method::POST if match_feedback_path(path).is_some() => Some(Api::FeedbackV1),
+            Method::POST
+                if match_registration_path(path).is_some() || match_drain_path(path).is_some() =>
+            {
+                Some(Api::RegistrationV1)
+            }
+            Method::DELETE if match_host_path(path).is_some() => Some(Api::RegistrationV1),
+            Method::PATCH if match_tags_path(path).is_some() => Some(Api::RegistrationV1),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Api::RegistrationV1 => "registration",
+            Api::FeedbackV1 => "feedback",
+            Api::SdsV1 => "sds",
+            Api::EdsV2 => "eds",
+        }
+    }
+
+    pub fn version(self) -> &'static str {
+        match self {
+            Api::RegistrationV1 | Api::FeedbackV1 | Api::SdsV1 => "v1",
+            Api::EdsV2 => "v2",
+        }
+    }
+
+    pub fn status(self) -> Status {
+        match self {
+            Api::SdsV1 => Status::Deprecated,
+            _ => Status::Stable,
+        }
+    }
+
+    fn routes(self) -> &'static [&'static str] {
+        match self {
+            Api::RegistrationV1 => &[
+                "POST /v1/registration/:service",
+                "DELETE /v1/registration/:service/:ip_port",
+                "POST /v1/registration/:service/:ip_port/drain",
+                "PATCH /v1/registration/:service/:ip_port/tags",
+            ],
+            Api::FeedbackV1 => &["POST /v1/feedback/:service"],
+            Api::SdsV1 => &["GET /v1/registration/:service"],
+            Api::EdsV2 => &["POST /v2/discovery:endpoints"],
+        }
+    }
+}
+
+// `sunset` is the HTTP-date deprecated APIs are removed at, if it's decided.
+pub fn versions(sunset: Option<&str>) -> Versions {
+    let versions = APIS
+        .iter()
+        .map(|api| ApiVersion {
+            api: api.name(),
+            version: api.version(),
+            status: api.status(),
+            sunset: match api.status() {
+                Status::Deprecated => sunset.map(|s| s.to_owned()),
+                Status::Stable => None,
+            },
+            routes: api.routes(),
+        })
+        .collect();
+    Versions { versions }
+}
+
+// Adds X-SDS-API-Version, and Deprecation and Sunset headers for deprecated APIs.
+pub fn set_headers(res: &mut Response<Body>, api: Api, sunset: Option<&str>) {
+    let headers = res.headers_mut();
+    headers.insert(API_VERSION_HEADER, HeaderValue::from_static(api.version()));
+    if api.status() != Status::Deprecated {
+        return;
+    }
+    headers.insert(DEPRECATION_HEADER, HeaderValue::from_static("true"));
+    if let Some(s) = sunset {
+        match HeaderValue::from_str(s) {
+            Ok(v) => {
+                headers.insert(SUNSET_HEADER, v);
+            }
+            Err(_) => warn!("Invalid sunset date for header: {}", s),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde_derive::Deserialize;
+
+use super::types::{Freshness, Host, RegistryIndex, Storage};
+
+// A service served under its own name with the hosts of other services, e.g. the blue and green
+// pools of a deployment registered as separate services.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct VirtualServiceConfig {
+    pub sources: Vec<VirtualSource>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct VirtualSource {
+    pub service: String,
+    // Multiplies load_balancing_weight of the hosts of the source. Hosts of a source weighted 0
+    // are left out.
+    pub weight: Option<u8>,
+}
+
+// Returns the hosts of the service, merging the sources when it's a virtual service. A host
+// registered under more than one source is served once with the weight of the first source.
+pub fn query_hosts<S: Storage>(
+    storage: &S,
+    virtual_services: &HashMap<String, VirtualServiceConfig>,
+    name: &str,
+) -> Result<Vec<Host>, S::E> {
+    let config = match virtual_services.get(name) {
+        Some(v) => v,
+        None => return storage.query_items(name),
+    };
+    let mut seen = BTreeSet::new();
+    let mut merged = Vec::new();
+    for source in &config.sources {
+        if source.weight == Some(0) {
+            continue;
+        }
+        for mut host in storage.query_items(&source.service)? {
+            if !seen.insert((host.ip_address.to_owned(), host.port)) {
+                continue;
+            }
+            if let Some(w) = source.weight {
+                let base = u32::from(host.tags.load_balancing_weight.unwrap_or(1));
+                let weight = (base * u32::from(w)).min(u32::from(u8::max_value()));
+                host.tags.load_balancing_weight = Some(weight as u8);
+            }
+            merged.push(host);
+        }
+    }
+    Ok(merged)
+}
+
+// Returns the registry index of the service. A virtual service changes whenever one of its sources
+// does, so its index is the largest of theirs.
+pub fn query_index<S: Storage>(
+    storage: &S,
+    virtual_services: &HashMap<String, VirtualServiceConfig>,
+    name: &str,
+) -> Result<Option<RegistryIndex>, S::E> {
+    let config = match virtual_services.get(name) {
+        Some(v) => v,
+        None => return storage.registry_index(name),
+    };
+    let mut merged: Option<RegistryIndex> = None;
+    for source in &config.sources {
+        if let Some(index) = storage.registry_index(&source.service)? {
+            let m = merged.get_or_insert(index);
+            m.service = m.service.max(index.service);
+            m.global = m.global.max(index.global);
+        }
+    }
+    Ok(merged)
+}
+
+// Returns the freshness of the hosts of the service, the least fresh of the sources of a virtual
+// service.
+pub fn query_freshness<S: Storage>(
+    storage: &S,
+    virtual_services: &HashMap<String, VirtualServiceConfig>,
+    name: &str,
+) -> Freshness {
+    match virtual_services.get(name) {
+        Some(config) => config
+            .sources
+            .iter()
+            .filter(|s| s.weight != Some(0))
+            .map(|s| storage.freshness(&s.service))
+            .fold(Freshness::live(), Freshness::merge),
+        None => storage.freshness(name),
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use futures::Future;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use tokio::runtime::current_thread::Runtime;
+use tokio::timer::Timeout;
+
+const ATTEMPTS: usize = 3;
+
+// Posts JSON payloads to a URL, blocking the calling thread. Only plain HTTP is supported.
+pub struct Webhook {
+    url: Uri,
+    timeout: Duration,
+    client: Client<HttpConnector>,
+    runtime: Runtime,
+}
+
+impl Webhook {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, String> {
+        let url: Uri = url
+            .parse()
+            .map_err(|e| format!("Invalid webhook URL {}: {}", url, e))?;
+        if url.scheme_part().map(|s| s.as_str()) != Some("http") {
+            return Err(format!("Webhook URL must be http: {}", url));
+        }
+        let runtime = Runtime::new().map_err(|e| format!("Failed to start runtime: {}", e))?;
+        Ok(Webhook {
+            url,
+            timeout,
+            client: Client::new(),
+            runtime,
+        })
+    }
+
+    // Retries a few times on errors and non 2xx responses.
+    pub fn post(&mut self, body: &[u8]) -> Result<(), String> {
+        let mut last_error = String::new();
+        for _ in 0..ATTEMPTS {
+            match self.post_once(body) {
+                Ok(()) => return Ok(()),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn post_once(&mut self, body: &[u8]) -> Result<(), String> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_vec()))
+            .map_err(|e| e.to_string())?;
+        let f = Timeout::new(self.client.request(req), self.timeout).map_err(|e| e.to_string());
+        let res = self.runtime.block_on(f)?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Webhook responded {}", res.status()))
+        }
+    }
+}
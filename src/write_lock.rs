@@ -0,0 +1,139 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use futures::sync::oneshot;
+use futures::{future, Async, Future, Poll};
+
+use super::metrics::Metrics;
+
+// Key of the lock serializing alias writes. Service names can't contain "/", so it doesn't
+// collide with a service.
+pub const ALIASES_LOCK: &str = "/aliases";
+
+#[derive(Debug, Default)]
+struct Inner {
+    // Services whose lock is held -> waiters in arrival order.
+    held: Mutex<HashMap<String, VecDeque<oneshot::Sender<()>>>>,
+}
+
+// Serializes writes of a service which read the storage before writing, like check-ins keeping
+// drain_started_at, draining and tag updates, so that one doesn't overwrite or resurrect a host
+// another has just changed. Waiting for the lock doesn't block the thread, waiters are resolved
+// one by one in arrival order. Reads don't take the lock. The lock is local to this process,
+// writes of other sds processes sharing the storage aren't serialized.
+#[derive(Debug, Clone)]
+pub struct ServiceLocks {
+    inner: Arc<Inner>,
+    metrics: Metrics,
+}
+
+// Holds the lock of the service, and hands it over to the next waiter when dropped.
+#[derive(Debug)]
+pub struct ServiceGuard {
+    inner: Arc<Inner>,
+    service: String,
+}
+
+impl Drop for ServiceGuard {
+    fn drop(&mut self) {
+        let mut held = self.inner.held.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(waiters) = held.get_mut(&self.service) {
+            // Waiters whose request has gone away are skipped.
+            while let Some(tx) = waiters.pop_front() {
+                if tx.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        held.remove(&self.service);
+    }
+}
+
+impl ServiceLocks {
+    pub fn new(metrics: Metrics) -> Self {
+        ServiceLocks {
+            inner: Arc::new(Inner::default()),
+            metrics,
+        }
+    }
+
+    // Resolves to the guard once the lock of the service is available and records how long it
+    // waited. It fails only when the lock is lost, which shouldn't happen.
+    pub fn lock(&self, service: &str) -> Box<Future<Item = ServiceGuard, Error = ()> + Send> {
+        let started = Instant::now();
+        let mut held = self.inner.held.lock().unwrap_or_else(|e| e.into_inner());
+        let rx = match held.get_mut(service) {
+            Some(waiters) => {
+                let (tx, rx) = oneshot::channel();
+                waiters.push_back(tx);
+                rx
+            }
+            None => {
+                held.insert(service.to_owned(), VecDeque::new());
+                self.metrics
+                    .observe_write_lock_wait(service, started.elapsed());
+                return Box::new(future::ok(ServiceGuard {
+                    inner: self.inner.clone(),
+                    service: service.to_owned(),
+                }));
+            }
+        };
+        Box::new(Waiting {
+            rx,
+            inner: self.inner.clone(),
+            service: service.to_owned(),
+            metrics: self.metrics.clone(),
+            started,
+            acquired: false,
+        })
+    }
+}
+
+// Waits for the lock to be handed over.
+struct Waiting {
+    rx: oneshot::Receiver<()>,
+    inner: Arc<Inner>,
+    service: String,
+    metrics: Metrics,
+    started: Instant,
+    acquired: bool,
+}
+
+impl Future for Waiting {
+    type Item = ServiceGuard;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<ServiceGuard, ()> {
+        match self.rx.poll() {
+            Ok(Async::Ready(())) => {
+                self.acquired = true;
+                self.metrics
+                    .observe_write_lock_wait(&self.service, self.started.elapsed());
+                Ok(Async::Ready(ServiceGuard {
+                    inner: self.inner.clone(),
+                    service: self.service.clone(),
+                }))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(()),
+        }
+    }
+}
+
+impl Drop for Waiting {
+    // The lock may have been handed over after the request went away, pass it on to the next
+    // waiter then.
+    fn drop(&mut self) {
+        if self.acquired {
+            return;
+        }
+        self.rx.close();
+        if let Ok(Async::Ready(())) = self.rx.poll() {
+            drop(ServiceGuard {
+                inner: self.inner.clone(),
+                service: self.service.clone(),
+            });
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde_json;
+
+use super::clock::SharedClock;
+use super::config::ReloadableConfig;
+use super::types::{sort_hosts, Storage};
+use super::v2xds::{
+    build_policy, hosts_to_locality_lb_endpoints, ClusterLoadAssignment, EdsDiscoveryResponse,
+    EDS_TYPE_URL,
+};
+use super::virtual_service;
+
+#[derive(Debug, Clone)]
+pub struct XdsFileConfig {
+    // Directory ClusterLoadAssignment files are written into as "<service>.json".
+    pub dir: PathBuf,
+    pub interval: Duration,
+}
+
+// Renders a DiscoveryResponse file per service every interval, for Envoys reading EDS from the
+// filesystem. Files are replaced by rename only when their content changes, so that Envoy's
+// file watch sees complete files.
+pub fn start_xds_file_writer<S: Storage>(
+    storage: S,
+    file: ReloadableConfig,
+    clock: SharedClock,
+    config: XdsFileConfig,
+) {
+    thread::spawn(move || {
+        let mut writer = XdsFileWriter::new(config.dir);
+        loop {
+            if let Err(e) = writer.render(&storage, &file, &clock) {
+                warn!("Failed to render xDS files: {}", e);
+            }
+            thread::sleep(config.interval);
+        }
+    });
+}
+
+pub struct XdsFileWriter {
+    dir: PathBuf,
+    // service -> hash of the written resource
+    written: HashMap<String, u64>,
+}
+
+impl XdsFileWriter {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        XdsFileWriter {
+            dir: dir.into(),
+            written: HashMap::new(),
+        }
+    }
+
+    // Renders services with live hosts, services of the config file, and services rendered
+    // before, which are kept with no endpoints instead of disappearing under Envoy. Returns the
+    // number of files written.
+    pub fn render<S: Storage>(
+        &mut self,
+        storage: &S,
+        file: &ReloadableConfig,
+        clock: &SharedClock,
+    ) -> Result<usize, String> {
+        let mut names: BTreeSet<String> = storage
+            .list_services()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+        let file_config = file.current();
+        names.extend(file_config.services.keys().cloned());
+        names.extend(file_config.virtual_services.keys().cloned());
+        names.extend(self.written.keys().cloned());
+
+        let now = clock.epoch_secs()?;
+        let mut count = 0;
+        for name in names {
+            if !is_safe_file_name(&name) {
+                warn!("Skip service unsafe for a file name: service={}", name);
+                continue;
+            }
+            let mut hosts =
+                virtual_service::query_hosts(storage, &file_config.virtual_services, &name)
+                    .map_err(|e| e.to_string())?;
+            sort_hosts(&mut hosts);
+            let service_config = file_config.services.get(&name);
+            if let Some(max_hosts) = service_config.and_then(|c| c.max_hosts) {
+                hosts.truncate(max_hosts);
+            }
+            let endpoints =
+                hosts_to_locality_lb_endpoints(hosts, service_config, &HashSet::new(), now, None);
+            let cla = ClusterLoadAssignment {
+                type_url: EDS_TYPE_URL.to_string(),
+                policy: build_policy(service_config),
+                cluster_name: name.to_owned(),
+                endpoints,
+            };
+            let resource = serde_json::to_string(&cla).map_err(|e| e.to_string())?;
+            let hash = hash_of(&resource);
+            if self.written.get(&name) == Some(&hash) {
+                continue;
+            }
+            // The version follows the content, so that restarts don't make Envoy reload.
+            let res = EdsDiscoveryResponse {
+                version_info: format!("{:016x}", hash),
+                resources: vec![cla],
+            };
+            let body = serde_json::to_vec_pretty(&res).map_err(|e| e.to_string())?;
+            write_atomically(&self.dir, &format!("{}.json", name), &body)?;
+            info!("Wrote xDS file: service={}", name);
+            self.written.insert(name, hash);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+fn is_safe_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains('/') && !name.contains('\\')
+}
+
+fn hash_of(s: &str) -> u64 {
+    let mut h = DefaultHasher::new();
+    s.hash(&mut h);
+    h.finish()
+}
+
+// Writes a temporary file in the same directory and renames it over the target.
+fn write_atomically(dir: &Path, file_name: &str, body: &[u8]) -> Result<(), String> {
+    let path = dir.join(file_name);
+    let tmp = dir.join(format!(".{}.tmp", file_name));
+    let mut f =
+        fs::File::create(&tmp).map_err(|e| format!("Failed to create {}: {}", tmp.display(), e))?;
+    f.write_all(body)
+        .and_then(|_| f.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, &path).map_err(|e| format!("Failed to rename to {}: {}", path.display(), e))
+}
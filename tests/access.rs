@@ -0,0 +1,82 @@
+use hyper::Method;
+use serde_json;
+
+use sds::access::{AccessConfig, Cidr, IpFilter};
+
+#[test]
+fn cidr_matches_networks() {
+    let net = Cidr::parse("10.1.0.0/16").unwrap();
+    assert!(net.contains("10.1.2.3".parse().unwrap()));
+    assert!(!net.contains("10.2.0.1".parse().unwrap()));
+    // IPv4-mapped IPv6 addresses are matched as IPv4.
+    assert!(net.contains("::ffff:10.1.0.1".parse().unwrap()));
+
+    let host = Cidr::parse("192.168.0.1").unwrap();
+    assert!(host.contains("192.168.0.1".parse().unwrap()));
+    assert!(!host.contains("192.168.0.2".parse().unwrap()));
+
+    assert!(Cidr::parse("0.0.0.0/0")
+        .unwrap()
+        .contains("8.8.8.8".parse().unwrap()));
+    assert!(Cidr::parse("fd00::/8")
+        .unwrap()
+        .contains("fd12::1".parse().unwrap()));
+    assert!(!Cidr::parse("fd00::/8")
+        .unwrap()
+        .contains("10.0.0.1".parse().unwrap()));
+
+    assert!(Cidr::parse("10.0.0.0/33").is_err());
+    assert!(Cidr::parse("10.0.0/8").is_err());
+}
+
+#[test]
+fn deny_takes_precedence_over_allow() {
+    let filter: IpFilter =
+        serde_json::from_str(r#"{"allow": ["10.0.0.0/16"], "deny": ["10.0.99.0/24"]}"#).unwrap();
+    assert!(filter.permits("10.0.1.1".parse().unwrap()));
+    assert!(!filter.permits("10.0.99.1".parse().unwrap()));
+    assert!(!filter.permits("172.16.0.1".parse().unwrap()));
+
+    let deny_only: IpFilter = serde_json::from_str(r#"{"deny": ["172.16.0.0/12"]}"#).unwrap();
+    assert!(deny_only.permits("10.0.0.1".parse().unwrap()));
+    assert!(!deny_only.permits("172.16.0.1".parse().unwrap()));
+
+    assert!(serde_json::from_str::<IpFilter>(r#"{"allow": ["nope"]}"#).is_err());
+}
+
+#[test]
+fn only_writes_and_admin_are_restricted() {
+    let config: AccessConfig = serde_json::from_str(
+        r#"{"mutations": {"allow": ["10.0.0.0/8"]}, "admin": {"allow": ["127.0.0.1"]}}"#,
+    )
+    .unwrap();
+    let is_mutation =
+        |method: Method, path: &str| config.filter_of(&method, path) == Some(&config.mutations);
+    assert!(is_mutation(Method::POST, "/v1/registration/user"));
+    assert!(is_mutation(
+        Method::DELETE,
+        "/v1/registration/user/10.0.0.1:80"
+    ));
+    assert!(is_mutation(
+        Method::POST,
+        "/v1/registration/user/10.0.0.1:80/drain"
+    ));
+    assert!(is_mutation(
+        Method::PATCH,
+        "/v1/registration/user/10.0.0.1:80/tags"
+    ));
+    assert!(is_mutation(Method::PUT, "/v1/aliases/legacy"));
+    assert!(is_mutation(Method::POST, "/v1/feedback/user"));
+
+    assert!(config
+        .filter_of(&Method::GET, "/v1/registration/user")
+        .is_none());
+    assert!(config
+        .filter_of(&Method::POST, "/v2/discovery:endpoints")
+        .is_none());
+    assert!(config.filter_of(&Method::GET, "/hc").is_none());
+    assert_eq!(
+        config.filter_of(&Method::GET, "/admin/conflicts"),
+        Some(&config.admin)
+    );
+}
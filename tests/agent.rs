@@ -0,0 +1,75 @@
+#![cfg(feature = "test-util")]
+
+use std::time::{Duration, Instant};
+
+use sds::agent::{backoff, Agent, AgentConfig, AgentService};
+use sds::test_util::TestServer;
+use sds::types::{Storage, Tag};
+
+fn service(port: u16) -> AgentService {
+    AgentService {
+        name: "user".to_owned(),
+        ip: "10.0.0.1".to_owned(),
+        port,
+        revision: "abc".to_owned(),
+        tags: Tag {
+            az: "us-east-1a".to_owned(),
+            region: "us-east-1".to_owned(),
+            instance_id: "i-1".to_owned(),
+            canary: false,
+            load_balancing_weight: None,
+            extra: Default::default(),
+        },
+    }
+}
+
+#[test]
+fn backoff_doubles_up_to_max() {
+    let max = Duration::from_secs(10);
+    assert_eq!(backoff(1, max), Duration::from_secs(1));
+    assert_eq!(backoff(2, max), Duration::from_secs(2));
+    assert_eq!(backoff(4, max), Duration::from_secs(8));
+    assert_eq!(backoff(5, max), max);
+    assert_eq!(backoff(100, max), max);
+}
+
+#[test]
+fn agent_registers_and_deregisters() {
+    let server = TestServer::start().unwrap();
+    let mut agent = Agent::new(AgentConfig {
+        sds_url: server.base_url(),
+        services: vec![service(8080), service(8081)],
+        ..Default::default()
+    })
+    .unwrap();
+
+    let wait = agent.tick(Instant::now());
+    assert_eq!(wait, Duration::from_secs(30));
+    assert_eq!(server.storage().query_items("user").unwrap().len(), 2);
+
+    agent.deregister_all().unwrap();
+    assert!(server.storage().query_items("user").unwrap().is_empty());
+    // Hosts already gone are fine.
+    agent.deregister_all().unwrap();
+}
+
+#[test]
+fn agent_backs_off_on_failures() {
+    let server = TestServer::start().unwrap();
+    let url = server.base_url();
+    server.shutdown();
+    let mut agent = Agent::new(AgentConfig {
+        sds_url: url,
+        timeout_ms: 500,
+        services: vec![service(8080)],
+        ..Default::default()
+    })
+    .unwrap();
+
+    let now = Instant::now();
+    assert_eq!(agent.tick(now), Duration::from_secs(1));
+    assert_eq!(
+        agent.tick(now + Duration::from_secs(1)),
+        Duration::from_secs(2)
+    );
+}
@@ -0,0 +1,46 @@
+use sds::bootstrap::{parse_args, render};
+
+fn args(v: &[&str]) -> Vec<String> {
+    v.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn renders_eds_clusters_pointing_at_sds() {
+    let opts = parse_args(&args(&[
+        "--cluster",
+        "user",
+        "--cluster",
+        "on",
+        "--sds-address",
+        "sds.internal:8080",
+        "--refresh-delay-ms",
+        "1500",
+    ]))
+    .unwrap();
+    assert_eq!(opts.clusters, vec!["user", "on"]);
+    assert_eq!(opts.sds_host, "sds.internal");
+    assert_eq!(opts.sds_port, 8080);
+
+    let yaml = render(&opts);
+    assert!(yaml.contains("  - name: \"user\"\n"));
+    assert!(yaml.contains("  - name: \"on\"\n"));
+    assert!(yaml.contains("refresh_delay: 1.500s"));
+    assert!(yaml.contains("        address: \"sds.internal\"\n        port_value: 8080\n"));
+}
+
+#[test]
+fn rejects_missing_or_invalid_arguments() {
+    assert!(parse_args(&args(&["--sds-address", "sds:8080"])).is_err());
+    assert!(parse_args(&args(&["--cluster", "user"])).is_err());
+    assert!(parse_args(&args(&["--cluster", "user", "--sds-address", "sds"])).is_err());
+    assert!(parse_args(&args(&["--cluster", "user", "--sds-address", "sds:http"])).is_err());
+    assert!(parse_args(&args(&["--cluster"])).is_err());
+    assert!(parse_args(&args(&[
+        "--cluster",
+        "user",
+        "--sds-address",
+        "sds:80",
+        "--foo"
+    ]))
+    .is_err());
+}
@@ -0,0 +1,238 @@
+#![cfg(feature = "memory")]
+
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sds::cache::CachedStorage;
+use sds::clock::{system_clock, MockClock};
+use sds::storage::{MemoryStorage, StorageError};
+use sds::types::{DataSource, Drift, Freshness, Host, Storage};
+
+fn host(port: u16, revision: &str) -> Host {
+    Host {
+        revision: revision.to_owned(),
+        ..common::host(port)
+    }
+}
+
+#[test]
+fn resync_repairs_drift_from_other_writers() {
+    let inner = MemoryStorage::new(30);
+    let cache = CachedStorage::new(inner.clone(), system_clock());
+    cache.store_item("user", host(1, "a")).unwrap();
+    cache.store_item("user", host(2, "a")).unwrap();
+    assert_eq!(cache.query_items("user").unwrap().len(), 2);
+
+    // Writes of another sds process go to the storage directly.
+    inner.store_item("user", host(2, "b")).unwrap();
+    inner.store_item("user", host(3, "a")).unwrap();
+    inner.delete_item("user", "10.0.0.1".to_owned(), 1).unwrap();
+    assert_eq!(cache.query_items("user").unwrap().len(), 2);
+
+    let drifts = cache.resync().unwrap();
+    assert_eq!(
+        drifts,
+        vec![Drift {
+            service: "user".to_owned(),
+            missing: 1,
+            extra: 1,
+            stale: 1,
+            error: None,
+        }]
+    );
+    let mut ports: Vec<u16> = cache
+        .query_items("user")
+        .unwrap()
+        .iter()
+        .map(|h| h.port)
+        .collect();
+    ports.sort();
+    assert_eq!(ports, vec![2, 3]);
+    assert!(cache.resync().unwrap().is_empty());
+}
+
+// MemoryStorage whose queries fail while `down`.
+#[derive(Clone)]
+struct FlakyStorage {
+    inner: MemoryStorage,
+    down: Arc<AtomicBool>,
+}
+
+impl Storage for FlakyStorage {
+    type E = StorageError;
+
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E> {
+        if self.down.load(Ordering::SeqCst) {
+            return Err(StorageError::new("Storage is down".to_owned()));
+        }
+        self.inner.query_items(name)
+    }
+
+    fn store_item(&self, name: &str, host: Host) -> Result<(), Self::E> {
+        self.inner.store_item(name, host)
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E> {
+        self.inner.delete_item(name, ip, port)
+    }
+
+    fn ttl(&self) -> u64 {
+        self.inner.ttl()
+    }
+}
+
+#[test]
+fn freshness_tracks_resyncs() {
+    let clock = MockClock::new(1_000);
+    let down = Arc::new(AtomicBool::new(false));
+    let memory = MemoryStorage::new(30);
+    memory.store_item("user", host(1, "a")).unwrap();
+    memory.store_item("order", host(1, "a")).unwrap();
+    let inner = FlakyStorage {
+        inner: memory,
+        down: down.clone(),
+    };
+    let cache = CachedStorage::new(inner, Arc::new(clock.clone()));
+    assert_eq!(cache.freshness("user"), Freshness::live());
+
+    cache.query_items("user").unwrap();
+    clock.advance(Duration::from_secs(12));
+    assert_eq!(
+        cache.freshness("user"),
+        Freshness {
+            source: DataSource::Cached,
+            age_seconds: 12,
+        }
+    );
+
+    cache.query_items("order").unwrap();
+    down.store(true, Ordering::SeqCst);
+    // Every service is tried and reported even though the first one fails.
+    let mut drifts = cache.resync().unwrap();
+    drifts.sort_by(|a, b| a.service.cmp(&b.service));
+    assert_eq!(
+        drifts
+            .iter()
+            .map(|d| (d.service.as_str(), d.error.as_ref().map(|e| e.as_str())))
+            .collect::<Vec<_>>(),
+        vec![
+            ("order", Some("Storage is down")),
+            ("user", Some("Storage is down"))
+        ]
+    );
+    assert_eq!(cache.freshness("order").source, DataSource::StaleFallback);
+    clock.advance(Duration::from_secs(3));
+    let freshness = cache.freshness("user");
+    assert_eq!(freshness.source, DataSource::StaleFallback);
+    assert_eq!(freshness.age_seconds, 15);
+    assert_eq!(freshness.header_value(), "stale-fallback; age=15");
+
+    down.store(false, Ordering::SeqCst);
+    cache.resync().unwrap();
+    assert_eq!(
+        cache.freshness("user"),
+        Freshness {
+            source: DataSource::Cached,
+            age_seconds: 0,
+        }
+    );
+}
+
+// MemoryStorage running `during_query` once right after the next query reads the hosts, like a
+// check-in landing while a resync is reading the storage.
+#[derive(Clone)]
+struct RacingStorage {
+    inner: MemoryStorage,
+    during_query: Arc<Mutex<Option<Box<dyn FnOnce() + Send>>>>,
+}
+
+impl Storage for RacingStorage {
+    type E = StorageError;
+
+    fn query_items(&self, name: &str) -> Result<Vec<Host>, Self::E> {
+        let hosts = self.inner.query_items(name);
+        let f = self.during_query.lock().unwrap().take();
+        if let Some(f) = f {
+            f();
+        }
+        hosts
+    }
+
+    fn store_item(&self, name: &str, host: Host) -> Result<(), Self::E> {
+        self.inner.store_item(name, host)
+    }
+
+    fn delete_item(&self, name: &str, ip: String, port: u64) -> Result<Option<Host>, Self::E> {
+        self.inner.delete_item(name, ip, port)
+    }
+
+    fn ttl(&self) -> u64 {
+        self.inner.ttl()
+    }
+}
+
+#[test]
+fn resync_keeps_writes_made_while_reading() {
+    let memory = MemoryStorage::new(30);
+    memory.store_item("user", host(1, "a")).unwrap();
+    memory.store_item("user", host(2, "a")).unwrap();
+    let during_query = Arc::new(Mutex::new(None));
+    let cache = CachedStorage::new(
+        RacingStorage {
+            inner: memory.clone(),
+            during_query: during_query.clone(),
+        },
+        system_clock(),
+    );
+    assert_eq!(cache.query_items("user").unwrap().len(), 2);
+    memory.store_item("user", host(3, "a")).unwrap();
+
+    let writer = cache.clone();
+    let f: Box<dyn FnOnce() + Send> = Box::new(move || {
+        writer.store_item("user", host(1, "b")).unwrap();
+        writer
+            .delete_item("user", "10.0.0.1".to_owned(), 2)
+            .unwrap();
+    });
+    *during_query.lock().unwrap() = Some(f);
+
+    // The drift of the other writer is repaired, the writes missing in the read aren't undone.
+    assert_eq!(
+        cache.resync().unwrap(),
+        vec![Drift {
+            service: "user".to_owned(),
+            missing: 1,
+            ..Default::default()
+        }]
+    );
+    let mut hosts: Vec<(u16, String)> = cache
+        .query_items("user")
+        .unwrap()
+        .into_iter()
+        .map(|h| (h.port, h.revision))
+        .collect();
+    hosts.sort();
+    assert_eq!(hosts, vec![(1, "b".to_owned()), (3, "a".to_owned())]);
+}
+
+#[test]
+fn services_without_hosts_are_not_cached() {
+    let memory = MemoryStorage::new(30);
+    let cache = CachedStorage::new(memory.clone(), system_clock());
+    assert!(cache.query_items("unknown").unwrap().is_empty());
+    assert_eq!(cache.freshness("unknown"), Freshness::live());
+
+    memory.store_item("user", host(1, "a")).unwrap();
+    assert_eq!(cache.query_items("user").unwrap().len(), 1);
+    assert_eq!(cache.freshness("user").source, DataSource::Cached);
+    // Deregistered through another sds process.
+    memory
+        .delete_item("user", "10.0.0.1".to_owned(), 1)
+        .unwrap();
+    assert_eq!(cache.resync().unwrap()[0].extra, 1);
+    assert_eq!(cache.freshness("user"), Freshness::live());
+    assert!(cache.resync().unwrap().is_empty());
+}
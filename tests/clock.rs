@@ -0,0 +1,54 @@
+#![cfg(feature = "memory")]
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use sds::clock::{Clock, MockClock};
+use sds::storage::MemoryStorage;
+use sds::types::{Host, Storage};
+
+const TTL: u64 = 30;
+
+fn host(expire_time: u64) -> Host {
+    Host {
+        expire_time,
+        ..common::host(8080)
+    }
+}
+
+#[test]
+fn hosts_expire_by_the_mock_clock() {
+    let clock = MockClock::new(1_000);
+    let storage = MemoryStorage::with_clock(TTL, Arc::new(clock.clone()));
+    storage.store_item("user", host(1_000 + TTL)).unwrap();
+
+    clock.advance(Duration::from_secs(TTL));
+    assert_eq!(storage.query_items("user").unwrap().len(), 1);
+
+    clock.advance(Duration::from_secs(1));
+    assert!(storage.query_items("user").unwrap().is_empty());
+    assert!(storage
+        .get_item("user", "10.0.0.1", 8080)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn reap_expired_removes_only_expired_hosts() {
+    let clock = MockClock::new(1_000);
+    let storage = MemoryStorage::with_clock(TTL, Arc::new(clock.clone()));
+    storage.store_item("user", host(1_010)).unwrap();
+    let mut live = host(1_100);
+    live.port = 8081;
+    storage.store_item("user", live).unwrap();
+
+    clock.set(1_050);
+    let reaped = storage.reap_expired(clock.epoch_secs().unwrap()).unwrap();
+    assert_eq!(reaped.len(), 1);
+    assert_eq!(reaped[0].port, 8080);
+    let hosts = storage.query_items("user").unwrap();
+    assert_eq!(hosts.len(), 1);
+    assert_eq!(hosts[0].port, 8081);
+}
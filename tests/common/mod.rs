@@ -0,0 +1,25 @@
+use std::collections::BTreeMap;
+
+use sds::types::{Host, Tag};
+
+// Live host of the "user" service, tests override the fields they care about.
+pub fn host(port: u16) -> Host {
+    Host {
+        ip_address: "10.0.0.1".to_owned(),
+        port,
+        last_check_in: String::new(),
+        expire_time: u64::max_value(),
+        revision: "abc".to_owned(),
+        service: "user".to_owned(),
+        tags: Tag {
+            az: "us-east-1a".to_owned(),
+            region: "us-east-1".to_owned(),
+            instance_id: "i-1".to_owned(),
+            canary: false,
+            load_balancing_weight: None,
+            extra: BTreeMap::new(),
+        },
+        drain_started_at: None,
+        registered_at: None,
+    }
+}
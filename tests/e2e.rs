@@ -0,0 +1,787 @@
+#![cfg(feature = "test-util")]
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use hyper::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use sds::admission::AdmissionConfig;
+use sds::clock::MockClock;
+use sds::config::{load_file_config, FileConfig, ReloadableConfig};
+use sds::quota::{QuotaConfig, QuotaLimits};
+use sds::test_util::TestServer;
+use sds::types::{Config, Host, Storage, Tag};
+use sds::virtual_service::{VirtualServiceConfig, VirtualSource};
+
+fn registration_body(ip: &str, port: u16, az: &str) -> String {
+    json!({
+        "ip": ip,
+        "port": port,
+        "revision": "abc",
+        "tags": {
+            "az": az,
+            "region": "us-east-1",
+            "instance_id": "i-1",
+            "canary": false,
+        },
+    })
+    .to_string()
+}
+
+fn hosts(server: &TestServer, name: &str) -> Vec<Value> {
+    let res = server.get(&format!("/v1/registration/{}", name)).unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    v["hosts"].as_array().unwrap().to_owned()
+}
+
+#[test]
+fn register_get_and_delete() {
+    let server = TestServer::start().unwrap();
+    let res = server
+        .post(
+            "/v1/registration/user",
+            &registration_body("10.0.0.1", 8080, "us-east-1a"),
+        )
+        .unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+
+    let hs = hosts(&server, "user");
+    assert_eq!(hs.len(), 1);
+    assert_eq!(hs[0]["ip_address"], "10.0.0.1");
+    assert_eq!(hs[0]["port"], 8080);
+    assert_eq!(hs[0]["tags"]["az"], "us-east-1a");
+
+    let res = server
+        .delete("/v1/registration/user/10.0.0.1:8080")
+        .unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    assert!(hosts(&server, "user").is_empty());
+
+    let res = server
+        .delete("/v1/registration/user/10.0.0.1:8080")
+        .unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    server.shutdown();
+}
+
+#[test]
+fn invalid_registration_is_rejected() {
+    let server = TestServer::start().unwrap();
+    let res = server.post("/v1/registration/user", "{").unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert!(hosts(&server, "user").is_empty());
+}
+
+#[test]
+fn eds_returns_registered_endpoints() {
+    let server = TestServer::start().unwrap();
+    for (port, az) in &[(8080, "us-east-1a"), (8081, "us-east-1c")] {
+        let res = server
+            .post(
+                "/v1/registration/user",
+                &registration_body("10.0.0.1", *port, az),
+            )
+            .unwrap();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+
+    let req = json!({
+        "node": {"id": "node-1", "cluster": "front"},
+        "resource_names": ["user", "unknown"],
+    });
+    let res = server
+        .post("/v2/discovery:endpoints", &req.to_string())
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    let resources = v["resources"].as_array().unwrap();
+    assert_eq!(resources.len(), 2);
+    assert_eq!(resources[0]["cluster_name"], "user");
+    assert_eq!(resources[1]["cluster_name"], "unknown");
+    assert!(resources[1]["endpoints"].as_array().unwrap().is_empty());
+
+    let mut ports: Vec<u64> = resources[0]["endpoints"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|lle| lle["lb_endpoints"].as_array().unwrap().to_owned())
+        .map(|e| {
+            e["endpoint"]["address"]["socket_address"]["port_value"]
+                .as_u64()
+                .unwrap()
+        })
+        .collect();
+    ports.sort();
+    assert_eq!(ports, vec![8080, 8081]);
+}
+
+#[test]
+fn eds_rejects_invalid_request() {
+    let server = TestServer::start().unwrap();
+    let res = server.post("/v2/discovery:endpoints", "{}").unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn maintenance_mode_rejects_writes() {
+    let server = TestServer::start().unwrap();
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    let res = server.post("/v1/registration/user", &body).unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+
+    let res = server
+        .post(
+            "/admin/maintenance",
+            r#"{"enabled": true, "message": "migrating"}"#,
+        )
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = server.post("/v1/registration/user", &body).unwrap();
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["id"], "Maintenance");
+    assert_eq!(v["reason"], "migrating");
+    let res = server
+        .delete("/v1/registration/user/10.0.0.1:8080")
+        .unwrap();
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(hosts(&server, "user").len(), 1);
+
+    let res = server
+        .post("/admin/maintenance", r#"{"enabled": false}"#)
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["enabled"], false);
+    let res = server
+        .delete("/v1/registration/user/10.0.0.1:8080")
+        .unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+}
+
+#[test]
+fn aliases_resolve_to_target_service() {
+    let server = TestServer::start().unwrap();
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    let res = server.post("/v1/registration/user-v2", &body).unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+
+    let res = server
+        .put("/v1/aliases/user", r#"{"service": "user-v2"}"#)
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let res = server.get("/v1/registration/user").unwrap();
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["service"], "user");
+    assert_eq!(v["hosts"].as_array().unwrap().len(), 1);
+
+    let res = server
+        .put("/v1/aliases/user-v2", r#"{"service": "user"}"#)
+        .unwrap();
+    assert_eq!(res.status, StatusCode::CONFLICT);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["id"], "AliasLoop");
+
+    let res = server.delete("/v1/aliases/user").unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(hosts(&server, "user").is_empty());
+    let res = server.delete("/v1/aliases/user").unwrap();
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn exclude_filters_hosts() {
+    let server = TestServer::start().unwrap();
+    for ip in &["10.0.0.1", "10.0.0.2", "10.0.0.3"] {
+        let body = registration_body(ip, 8080, "us-east-1a");
+        let res = server.post("/v1/registration/user", &body).unwrap();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+    let res = server
+        .get("/v1/registration/user?exclude=10.0.0.1:8080&exclude=10.0.0.3:8080")
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    let hosts = v["hosts"].as_array().unwrap();
+    assert_eq!(hosts.len(), 1);
+    assert_eq!(hosts[0]["ip_address"], "10.0.0.2");
+
+    let res = server
+        .get("/v1/registration/user?exclude=10.0.0.1")
+        .unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn sample_and_shuffle_hosts() {
+    let server = TestServer::start().unwrap();
+    for port in 8080..8090 {
+        let body = registration_body("10.0.0.1", port, "us-east-1a");
+        let res = server.post("/v1/registration/user", &body).unwrap();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+    let res = server.get("/v1/registration/user?sample=3").unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    let ports: Vec<u64> = v["hosts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| h["port"].as_u64().unwrap())
+        .collect();
+    assert_eq!(ports.len(), 3);
+    let mut sorted = ports.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(ports, sorted);
+
+    let res = server.get("/v1/registration/user?shuffle=true").unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["hosts"].as_array().unwrap().len(), 10);
+
+    let res = server.get("/v1/registration/user?sample=-1").unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn region_restricts_hosts_with_fallback() {
+    let server = TestServer::start().unwrap();
+    for (ip, region) in &[("10.0.0.1", "us-east-1"), ("10.1.0.1", "us-west-2")] {
+        let body = json!({
+            "ip": ip,
+            "port": 8080,
+            "revision": "abc",
+            "tags": {"az": "a", "region": region, "instance_id": "i-1", "canary": false},
+        });
+        let res = server
+            .post("/v1/registration/user", &body.to_string())
+            .unwrap();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+    let res = server
+        .get("/v1/registration/user?region=us-west-2")
+        .unwrap();
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    let hosts = v["hosts"].as_array().unwrap();
+    assert_eq!(hosts.len(), 1);
+    assert_eq!(hosts[0]["ip_address"], "10.1.0.1");
+
+    let res = server
+        .get("/v1/registration/user?region=eu-west-1")
+        .unwrap();
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["hosts"].as_array().unwrap().len(), 2);
+
+    // SDS_REGION isn't set.
+    let res = server.get("/v1/registration/user?region=local").unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn virtual_services_merge_sources() {
+    let mut file_config = FileConfig::default();
+    file_config.virtual_services.insert(
+        "user".to_owned(),
+        VirtualServiceConfig {
+            sources: vec![
+                VirtualSource {
+                    service: "user-blue".to_owned(),
+                    weight: Some(3),
+                },
+                VirtualSource {
+                    service: "user-green".to_owned(),
+                    weight: None,
+                },
+            ],
+        },
+    );
+    let server = TestServer::start_with(Config {
+        file: ReloadableConfig::new(None, file_config),
+        ..Default::default()
+    })
+    .unwrap();
+    for (name, ip) in &[("user-blue", "10.0.0.1"), ("user-green", "10.0.0.2")] {
+        let body = registration_body(ip, 8080, "us-east-1a");
+        let res = server
+            .post(&format!("/v1/registration/{}", name), &body)
+            .unwrap();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+
+    let hosts = hosts(&server, "user");
+    assert_eq!(hosts.len(), 2);
+    assert_eq!(hosts[0]["service"], "user-blue");
+    assert_eq!(hosts[0]["tags"]["load_balancing_weight"], 3);
+    assert_eq!(hosts[1]["service"], "user-green");
+    assert!(hosts[1]["tags"].get("load_balancing_weight").is_none());
+}
+
+// Serves an admission webhook denying 10.0.0.2 and moving other hosts to us-east-1c, counting
+// the reviews.
+fn start_admission_webhook() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/admit", listener.local_addr().unwrap());
+    let reviews = Arc::new(AtomicUsize::new(0));
+    let counter = reviews.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            let mut body_len = None;
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let len = *body_len.get_or_insert_with(|| {
+                        text[..end]
+                            .lines()
+                            .filter_map(|l| {
+                                let l = l.to_lowercase();
+                                if l.starts_with("content-length:") {
+                                    l["content-length:".len()..].trim().parse::<usize>().ok()
+                                } else {
+                                    None
+                                }
+                            })
+                            .next()
+                            .unwrap_or(0)
+                    });
+                    if request.len() >= end + 4 + len {
+                        break;
+                    }
+                }
+            }
+            counter.fetch_add(1, Ordering::SeqCst);
+            let response = if String::from_utf8_lossy(&request).contains("10.0.0.2") {
+                json!({"allowed": false, "reason": "10.0.0.2 is blocked"})
+            } else {
+                json!({
+                    "allowed": true,
+                    "tags": {
+                        "az": "us-east-1c",
+                        "region": "us-east-1",
+                        "instance_id": "i-1",
+                        "canary": false,
+                    },
+                })
+            }
+            .to_string();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            );
+        }
+    });
+    (url, reviews)
+}
+
+fn start_with_admission(url: String, fail_open: bool) -> TestServer {
+    TestServer::start_with(Config {
+        admission: Some(AdmissionConfig {
+            url,
+            timeout: Duration::from_millis(500),
+            cache_ttl: Duration::from_secs(60),
+            fail_open,
+        }),
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+#[test]
+fn admission_webhook_reviews_registrations() {
+    let (url, reviews) = start_admission_webhook();
+    let server = start_with_admission(url, false);
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    for _ in 0..2 {
+        let res = server.post("/v1/registration/user", &body).unwrap();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+    // The check-in reuses the cached decision.
+    assert_eq!(reviews.load(Ordering::SeqCst), 1);
+    let hosts = hosts(&server, "user");
+    assert_eq!(hosts.len(), 1);
+    assert_eq!(hosts[0]["tags"]["az"], "us-east-1c");
+
+    let res = server
+        .post(
+            "/v1/registration/user",
+            &registration_body("10.0.0.2", 8080, "us-east-1a"),
+        )
+        .unwrap();
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["id"], "AdmissionDenied");
+    assert_eq!(v["reason"], "10.0.0.2 is blocked");
+}
+
+#[test]
+fn admission_webhook_failures_follow_fail_open() {
+    // Nothing listens on the port once the listener is dropped.
+    let url = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/admit", listener.local_addr().unwrap())
+    };
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+
+    let server = start_with_admission(url.clone(), false);
+    let res = server.post("/v1/registration/user", &body).unwrap();
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["id"], "AdmissionUnavailable");
+
+    let server = start_with_admission(url, true);
+    let res = server.post("/v1/registration/user", &body).unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    assert_eq!(hosts(&server, "user")[0]["tags"]["az"], "us-east-1a");
+}
+
+fn registry_index(server: &TestServer, name: &str) -> (u64, Value) {
+    let res = server.get(&format!("/v1/registration/{}", name)).unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let header = res.headers["x-sds-index"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    (header, v["index"].to_owned())
+}
+
+#[test]
+fn registry_index_tracks_changes() {
+    let server = TestServer::start().unwrap();
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    server.post("/v1/registration/user", &body).unwrap();
+    let (header, index) = registry_index(&server, "user");
+    assert_eq!(header, 1);
+    assert_eq!(index, json!({"service": 1, "global": 1}));
+
+    // Check-ins don't change the registry.
+    server.post("/v1/registration/user", &body).unwrap();
+    assert_eq!(registry_index(&server, "user").0, 1);
+
+    server.post("/v1/registration/search", &body).unwrap();
+    let (header, index) = registry_index(&server, "user");
+    assert_eq!(header, 1);
+    assert_eq!(index, json!({"service": 1, "global": 2}));
+
+    let res = server
+        .post(
+            "/v1/registration/user",
+            &registration_body("10.0.0.1", 8080, "us-east-1b"),
+        )
+        .unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    assert_eq!(registry_index(&server, "user").0, 3);
+    server
+        .delete("/v1/registration/user/10.0.0.1:8080/")
+        .unwrap();
+    assert_eq!(registry_index(&server, "user").0, 4);
+
+    let res = server
+        .post(
+            "/v2/discovery:endpoints",
+            &json!({
+                "node": {"id": "node-1", "cluster": "front"},
+                "resource_names": ["user", "search"],
+            })
+            .to_string(),
+        )
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.headers["x-sds-index"], "4");
+}
+
+#[test]
+fn responses_describe_data_freshness() {
+    let server = TestServer::start().unwrap();
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    server.post("/v1/registration/user", &body).unwrap();
+    let res = server.get("/v1/registration/user").unwrap();
+    assert_eq!(res.headers["x-sds-data-freshness"], "live; age=0");
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["freshness"], json!({"source": "live", "age_seconds": 0}));
+}
+
+#[test]
+fn patch_merges_tags_without_check_in() {
+    let clock = MockClock::new(1_000);
+    let server = TestServer::start_with(Config {
+        clock: Arc::new(clock.clone()),
+        ..Default::default()
+    })
+    .unwrap();
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    server.post("/v1/registration/user", &body).unwrap();
+    let expire_time = hosts(&server, "user")[0]["expire_time"].clone();
+    clock.advance(Duration::from_secs(10));
+
+    let path = "/v1/registration/user/10.0.0.1:8080/tags";
+    let res = server
+        .patch(path, r#"{"version": "1.2.4", "az": "us-east-1b"}"#)
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let res = server
+        .patch(path, r#"{"version": null, "canary": true}"#)
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let tags: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(
+        tags,
+        json!({"az": "us-east-1b", "region": "us-east-1", "instance_id": "i-1", "canary": true})
+    );
+
+    let hs = hosts(&server, "user");
+    assert_eq!(hs[0]["tags"], tags);
+    assert_eq!(hs[0]["expire_time"], expire_time);
+
+    let res = server.patch(path, r#"{"az": null}"#).unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = server.patch(path, r#"["az"]"#).unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = server
+        .patch("/v1/registration/user/10.0.0.2:8080/tags", "{}")
+        .unwrap();
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert!(res.body.contains("HostNotFound"));
+}
+
+#[test]
+fn access_config_restricts_writes_and_admin() {
+    let file_config: FileConfig = serde_json::from_value(json!({
+        "access": {
+            "mutations": {"allow": ["10.0.0.0/8"]},
+            "admin": {"deny": ["127.0.0.1"]},
+        }
+    }))
+    .unwrap();
+    let server = TestServer::start_with(Config {
+        file: ReloadableConfig::new(None, file_config),
+        ..Default::default()
+    })
+    .unwrap();
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    let res = server.post("/v1/registration/user", &body).unwrap();
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["id"], "AccessDenied");
+
+    assert!(hosts(&server, "user").is_empty());
+    let res = server.get("/admin/maintenance").unwrap();
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let res = server.get("/hc").unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[test]
+fn admin_info_describes_build_and_runtime() {
+    let server = TestServer::start().unwrap();
+    let res = server.get("/admin/info").unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["build"]["version"], env!("CARGO_PKG_VERSION"));
+    assert!(!v["build"]["git_sha"].as_str().unwrap().is_empty());
+    let features = v["build"]["features"].as_array().unwrap();
+    assert!(features.contains(&json!("test-util")));
+    assert!(v["build"]["storage_backends"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("memory")));
+    assert_eq!(v["runtime"]["ttl_seconds"], 30);
+    assert_eq!(v["runtime"]["acceptors"], 1);
+}
+
+fn start_with_quotas(quotas: QuotaConfig) -> TestServer {
+    let file_config = FileConfig {
+        quotas,
+        ..Default::default()
+    };
+    TestServer::start_with(Config {
+        file: ReloadableConfig::new(None, file_config),
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+#[test]
+fn quotas_reject_new_services_and_hosts() {
+    let mut quotas = QuotaConfig::default();
+    quotas.default.max_hosts_per_service = Some(1);
+    quotas.namespaces.insert(
+        "payments".to_owned(),
+        QuotaLimits {
+            max_services: Some(1),
+            ..Default::default()
+        },
+    );
+    let server = start_with_quotas(quotas);
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    let res = server.post("/v1/registration/payments.api", &body).unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    // Check-ins are accepted.
+    let res = server.post("/v1/registration/payments.api", &body).unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+
+    let res = server
+        .post(
+            "/v1/registration/payments.api",
+            &registration_body("10.0.0.2", 8080, "us-east-1a"),
+        )
+        .unwrap();
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["id"], "QuotaExceeded");
+    assert_eq!(v["quota"]["quota"], "max_hosts_per_service");
+
+    let res = server
+        .post("/v1/registration/payments.worker", &body)
+        .unwrap();
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["quota"]["namespace"], "payments");
+    assert_eq!(v["quota"]["quota"], "max_services");
+    assert_eq!(v["quota"]["current"], 1);
+
+    let res = server.post("/v1/registration/search.api", &body).unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+}
+
+#[test]
+fn quotas_limit_registration_rate() {
+    let mut quotas = QuotaConfig::default();
+    quotas.default.max_registrations_per_min = Some(2);
+    let server = start_with_quotas(quotas);
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    for _ in 0..2 {
+        let res = server.post("/v1/registration/user", &body).unwrap();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+    let res = server.post("/v1/registration/user", &body).unwrap();
+    assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["quota"]["quota"], "max_registrations_per_min");
+    assert_eq!(v["quota"]["limit"], 2);
+}
+
+#[test]
+fn rate_limited_registrations_are_retried_with_the_same_key() {
+    let path = std::env::temp_dir().join(format!("sds-e2e-rate-{}.json", std::process::id()));
+    fs::write(
+        &path,
+        r#"{"quotas": {"default": {"max_registrations_per_min": 1}}}"#,
+    )
+    .unwrap();
+    let path = path.to_str().unwrap().to_owned();
+    let server = TestServer::start_with(Config {
+        file: ReloadableConfig::new(Some(path.clone()), load_file_config(&path).unwrap()),
+        ..Default::default()
+    })
+    .unwrap();
+    let body = registration_body("10.0.0.1", 8080, "us-east-1a");
+    let register = || {
+        server
+            .request_with_headers(
+                Method::POST,
+                "/v1/registration/user",
+                &body,
+                &[("idempotency-key", "check-in-1")],
+            )
+            .unwrap()
+    };
+    let res = server.post("/v1/registration/user", &body).unwrap();
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    assert_eq!(register().status, StatusCode::TOO_MANY_REQUESTS);
+
+    // The limit is lifted, the retry isn't answered by the 429 of the first attempt.
+    fs::write(&path, "{}").unwrap();
+    let res = server.post("/admin/reload", "").unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(register().status, StatusCode::ACCEPTED);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn hosts_are_sorted_by_ip_and_port() {
+    let server = TestServer::start().unwrap();
+    for (ip, port) in &[("10.0.0.10", 8080), ("10.0.0.2", 8081), ("10.0.0.2", 8080)] {
+        let res = server
+            .post(
+                "/v1/registration/user",
+                &registration_body(ip, *port, "us-east-1a"),
+            )
+            .unwrap();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+    let addrs: Vec<(String, u64)> = hosts(&server, "user")
+        .iter()
+        .map(|h| {
+            (
+                h["ip_address"].as_str().unwrap().to_owned(),
+                h["port"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        addrs,
+        vec![
+            ("10.0.0.2".to_owned(), 8080),
+            ("10.0.0.2".to_owned(), 8081),
+            ("10.0.0.10".to_owned(), 8080),
+        ]
+    );
+}
+
+#[test]
+fn large_services_are_streamed() {
+    let server = TestServer::start().unwrap();
+    for i in 0..2500u32 {
+        let host = Host {
+            ip_address: format!("10.0.{}.{}", i / 256, i % 256),
+            port: 8080,
+            last_check_in: "2019-01-01 00:00:00+00:00".to_owned(),
+            expire_time: 4_102_444_800,
+            revision: "abc".to_owned(),
+            service: "user".to_owned(),
+            tags: Tag {
+                az: "us-east-1a".to_owned(),
+                region: "us-east-1".to_owned(),
+                instance_id: format!("i-{}", i),
+                canary: false,
+                load_balancing_weight: None,
+                extra: Default::default(),
+            },
+            drain_started_at: None,
+            registered_at: None,
+        };
+        server.storage().store_item("user", host).unwrap();
+    }
+
+    let res = server
+        .get("/v1/registration/user?fields=ip_address,port")
+        .unwrap();
+    assert_eq!(res.status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&res.body).unwrap();
+    assert_eq!(v["service"], "user");
+    assert_eq!(v["env"], "production");
+    let hs = v["hosts"].as_array().unwrap();
+    assert_eq!(hs.len(), 2500);
+    assert_eq!(hs[0], json!({"ip_address": "10.0.0.0", "port": 8080}));
+    assert_eq!(hs[2499], json!({"ip_address": "10.0.9.195", "port": 8080}));
+}
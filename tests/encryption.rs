@@ -0,0 +1,58 @@
+#![cfg(all(feature = "encryption", feature = "memory"))]
+
+mod common;
+
+use sds::encryption::{EncryptedStorage, TagCipher};
+use sds::storage::MemoryStorage;
+use sds::types::{Host, Storage};
+
+const KEY: [u8; 32] = [7; 32];
+
+fn host() -> Host {
+    let mut host = common::host(8080);
+    host.tags.canary = true;
+    host.tags.load_balancing_weight = Some(3);
+    host.tags
+        .extra
+        .insert("owner".to_owned(), "payments-team".into());
+    host
+}
+
+#[test]
+fn tag_values_are_encrypted_in_storage_only() {
+    let inner = MemoryStorage::new(30);
+    let storage = EncryptedStorage::new(inner.clone(), TagCipher::new(&KEY).unwrap());
+    storage.store_item("user", host()).unwrap();
+
+    let stored = &inner.query_items("user").unwrap()[0];
+    assert!(stored.tags.az.starts_with("enc:v1:"));
+    assert!(stored.tags.extra["owner"]
+        .as_str()
+        .unwrap()
+        .starts_with("enc:v1:"));
+    assert!(stored.tags.canary);
+
+    let hosts = storage.query_items("user").unwrap();
+    assert_eq!(hosts[0].tags.az, "us-east-1a");
+    assert_eq!(hosts[0].tags.extra["owner"], "payments-team");
+    assert_eq!(hosts[0].tags.load_balancing_weight, Some(3));
+}
+
+#[test]
+fn plain_values_stay_readable() {
+    let inner = MemoryStorage::new(30);
+    inner.store_item("user", host()).unwrap();
+    let storage = EncryptedStorage::new(inner, TagCipher::new(&KEY).unwrap());
+    let h = storage.get_item("user", "10.0.0.1", 8080).unwrap().unwrap();
+    assert_eq!(h.tags.az, "us-east-1a");
+}
+
+#[test]
+fn wrong_key_fails_to_decrypt() {
+    let cipher = TagCipher::new(&KEY).unwrap();
+    let encrypted = cipher.encrypt("secret").unwrap();
+    assert_eq!(cipher.decrypt(&encrypted).unwrap(), "secret");
+    let other = TagCipher::new(&[8; 32]).unwrap();
+    assert!(other.decrypt(&encrypted).is_err());
+    assert!(TagCipher::new(&[0; 16]).is_err());
+}
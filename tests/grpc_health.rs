@@ -0,0 +1,30 @@
+use sds::grpc_health::{decode_check_request, decode_frame, encode_check_response, encode_frame};
+
+#[test]
+fn frames_round_trip() {
+    let frame = encode_frame(b"abc");
+    assert_eq!(frame, vec![0, 0, 0, 0, 3, b'a', b'b', b'c']);
+    assert_eq!(decode_frame(&frame), Ok(&b"abc"[..]));
+    assert!(decode_frame(&frame[..6]).is_err());
+    assert!(decode_frame(&[1, 0, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn check_request_returns_service() {
+    assert_eq!(decode_check_request(&[]), Ok(String::new()));
+    assert_eq!(
+        decode_check_request(&[0x0a, 3, b's', b'd', b's']),
+        Ok("sds".to_owned())
+    );
+    // Unknown varint field 2 is skipped.
+    assert_eq!(
+        decode_check_request(&[0x10, 0x96, 0x01, 0x0a, 1, b'x']),
+        Ok("x".to_owned())
+    );
+    assert!(decode_check_request(&[0x0a, 5, b's']).is_err());
+}
+
+#[test]
+fn check_response_encodes_status() {
+    assert_eq!(encode_check_response(1), vec![0x08, 1]);
+}
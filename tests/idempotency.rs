@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use hyper::{HeaderMap, StatusCode};
+
+use sds::idempotency::{CachedResponse, IdempotencyCache, Lookup};
+
+#[test]
+fn dropped_keys_are_aborted() {
+    let cache = IdempotencyCache::new(Duration::from_secs(300));
+    let pending = match cache.begin("POST /v1/registration/user k1", 1) {
+        Lookup::Miss(v) => v,
+        l => panic!("unexpected lookup: {:?}", l),
+    };
+    match cache.begin("POST /v1/registration/user k1", 1) {
+        Lookup::InProgress => {}
+        l => panic!("unexpected lookup: {:?}", l),
+    }
+
+    // e.g. the client disconnected before the response was built.
+    drop(pending);
+    let pending = match cache.begin("POST /v1/registration/user k1", 1) {
+        Lookup::Miss(v) => v,
+        l => panic!("unexpected lookup: {:?}", l),
+    };
+
+    pending.complete(CachedResponse {
+        status: StatusCode::ACCEPTED,
+        headers: HeaderMap::new(),
+        body: Vec::new(),
+    });
+    match cache.begin("POST /v1/registration/user k1", 1) {
+        Lookup::Hit(res) => assert_eq!(res.status, StatusCode::ACCEPTED),
+        l => panic!("unexpected lookup: {:?}", l),
+    }
+}
+
+#[test]
+fn rate_limited_responses_are_not_cached() {
+    let cache = IdempotencyCache::new(Duration::from_secs(300));
+    let pending = match cache.begin("POST /v1/registration/user k1", 1) {
+        Lookup::Miss(v) => v,
+        l => panic!("unexpected lookup: {:?}", l),
+    };
+    pending.complete(CachedResponse {
+        status: StatusCode::TOO_MANY_REQUESTS,
+        headers: HeaderMap::new(),
+        body: Vec::new(),
+    });
+    match cache.begin("POST /v1/registration/user k1", 1) {
+        Lookup::Miss(_) => {}
+        l => panic!("unexpected lookup: {:?}", l),
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+use sds::config::{load_file_config, ReloadableConfig};
+use sds::limiter::{ConcurrencyLimiter, LimitConfig};
+
+#[test]
+fn reloaded_limits_override_environment_limits() {
+    let path = std::env::temp_dir().join(format!("sds-limiter-{}.json", std::process::id()));
+    fs::write(&path, "{}").unwrap();
+    let path = path.to_str().unwrap().to_owned();
+    let file = ReloadableConfig::new(Some(path.clone()), load_file_config(&path).unwrap());
+    let mut per_route = HashMap::new();
+    per_route.insert("eds".to_owned(), 1);
+    let limiter = ConcurrencyLimiter::new(
+        LimitConfig {
+            global: Some(1),
+            per_route,
+            retry_after: Duration::from_secs(1),
+        },
+        file.clone(),
+    );
+
+    let first = limiter.try_acquire("sds").unwrap();
+    assert!(limiter.try_acquire("sds").is_none());
+    assert_eq!(limiter.retry_after(), Duration::from_secs(1));
+
+    fs::write(
+        &path,
+        r#"{"load_shedding": {"max_concurrent_requests": 3,
+            "max_concurrent_requests_per_route": {"sds": 1}, "retry_after_sec": 5}}"#,
+    )
+    .unwrap();
+    file.reload().unwrap();
+    assert!(limiter.try_acquire("sds").is_none());
+    let eds = limiter.try_acquire("eds").unwrap();
+    assert!(limiter.try_acquire("eds").is_none());
+    assert_eq!(limiter.retry_after(), Duration::from_secs(5));
+
+    drop(first);
+    drop(eds);
+    let _ = fs::remove_file(&path);
+}
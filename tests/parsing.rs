@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+use proptest::prelude::*;
+use serde_json;
+
+use sds::feedback::{FeedbackConfig, FeedbackTracker};
+use sds::request::{
+    match_drain_path, match_feedback_path, match_host_path, match_registration_path,
+    parse_deadline, parse_discovery_request, parse_exclude, parse_feedback_param, parse_fields,
+    parse_port, parse_registration_param, RegistrationParam,
+};
+use sds::types::Tag;
+
+fn arb_tag() -> impl Strategy<Value = Tag> {
+    (
+        ".*",
+        ".*",
+        ".*",
+        any::<bool>(),
+        proptest::option::of(any::<u8>()),
+        proptest::collection::btree_map(
+            "x_[a-z]{1,8}",
+            ".*".prop_map(serde_json::Value::from),
+            0..4,
+        ),
+    )
+        .prop_map(
+            |(az, region, instance_id, canary, load_balancing_weight, extra)| Tag {
+                az,
+                region,
+                instance_id,
+                canary,
+                load_balancing_weight,
+                extra,
+            },
+        )
+}
+
+fn arb_registration_param() -> impl Strategy<Value = RegistrationParam> {
+    (".*", any::<u16>(), ".*", arb_tag()).prop_map(|(ip, port, revision, tags)| RegistrationParam {
+        ip,
+        port,
+        revision,
+        tags,
+    })
+}
+
+proptest! {
+    #[test]
+    fn parsers_never_panic(body in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let _ = parse_registration_param(&body, false);
+        let _ = parse_registration_param(&body, true);
+        let _ = parse_discovery_request(&body);
+        let _ = parse_feedback_param(&body, false);
+        let _ = parse_feedback_param(&body, true);
+    }
+
+    #[test]
+    fn registration_param_round_trips(param in arb_registration_param()) {
+        let body = serde_json::to_vec(&param).unwrap();
+        prop_assert!(parse_registration_param(&body, true).is_ok());
+        let parsed = parse_registration_param(&body, false).unwrap();
+        prop_assert_eq!(parsed.ip, param.ip);
+        prop_assert_eq!(parsed.port, param.port);
+        prop_assert_eq!(parsed.revision, param.revision);
+        prop_assert_eq!(parsed.tags.az, param.tags.az);
+        prop_assert_eq!(parsed.tags.region, param.tags.region);
+        prop_assert_eq!(parsed.tags.instance_id, param.tags.instance_id);
+        prop_assert_eq!(parsed.tags.canary, param.tags.canary);
+        prop_assert_eq!(parsed.tags.load_balancing_weight, param.tags.load_balancing_weight);
+        prop_assert_eq!(parsed.tags.extra, param.tags.extra);
+    }
+
+    #[test]
+    fn path_matchers_never_panic(path in ".*") {
+        let _ = match_registration_path(&path);
+        let _ = match_feedback_path(&path);
+        let _ = match_host_path(&path);
+        let _ = match_drain_path(&path);
+    }
+
+    #[test]
+    fn path_matchers_extract_names(name in "[^/]+", ip in "[^/:]+", port in "[^/:]+") {
+        let registration = format!("/v1/registration/{}", name);
+        prop_assert_eq!(match_registration_path(&registration), Some(name.as_str()));
+        let feedback = format!("/v1/feedback/{}/", name);
+        prop_assert_eq!(match_feedback_path(&feedback), Some(name.as_str()));
+        let host = format!("/v1/registration/{}/{}:{}", name, ip, port);
+        prop_assert_eq!(
+            match_host_path(&host),
+            Some((name.as_str(), ip.as_str(), port.as_str()))
+        );
+        let drain = format!("{}/drain", host);
+        prop_assert_eq!(
+            match_drain_path(&drain),
+            Some((name.as_str(), ip.as_str(), port.as_str()))
+        );
+    }
+
+    #[test]
+    fn fields_parser_never_panics(value in ".*") {
+        let _ = parse_fields(&value);
+    }
+
+    #[test]
+    fn exclude_parser_round_trips(ip in "[0-9a-f.:]{1,39}", port in any::<u16>()) {
+        let value = format!("[{}]:{}", ip, port);
+        prop_assert_eq!(parse_exclude(&value), Ok((ip, port)));
+    }
+
+    #[test]
+    fn port_parser_accepts_only_u16(s in ".*") {
+        prop_assert_eq!(parse_port(&s).is_ok(), s.parse::<u16>().is_ok());
+    }
+
+    #[test]
+    fn deadline_parser_handles_any_budget(ms in any::<u64>()) {
+        let now = Instant::now();
+        let deadline = parse_deadline(ms.to_string().as_bytes(), now).unwrap();
+        if let Some(d) = deadline {
+            prop_assert_eq!(d - now, Duration::from_millis(ms));
+        }
+    }
+
+    #[test]
+    fn deadline_parser_never_panics(value in proptest::collection::vec(any::<u8>(), 0..64)) {
+        let _ = parse_deadline(&value, Instant::now());
+    }
+
+    #[test]
+    fn feedback_counts_never_overflow(reports in proptest::collection::vec((any::<u64>(), any::<u64>()), 1..16)) {
+        let tracker = FeedbackTracker::new(FeedbackConfig::default());
+        for (requests, failures) in reports {
+            tracker.report("service", "10.0.0.1:80", requests, failures);
+        }
+        let _ = tracker.demoted_endpoints("service");
+    }
+}
+
+#[test]
+fn non_string_tags_are_accepted() {
+    let body = br#"{"ip": "10.0.0.1", "port": 8080, "revision": "abc", "tags": {"az": "us-east-1a", "region": "us-east-1", "instance_id": "i-1", "canary": false, "build": 42, "debug": true}}"#;
+    let param = parse_registration_param(body, true).unwrap();
+    assert_eq!(param.tags.extra["build"], 42);
+    assert_eq!(param.tags.extra["debug"], true);
+}
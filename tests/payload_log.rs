@@ -0,0 +1,24 @@
+use sds::payload_log::{parse_list, render, PayloadLogConfig};
+
+#[test]
+fn redacts_keys_at_any_depth() {
+    let config = PayloadLogConfig {
+        redact_keys: parse_list("instance_id, token"),
+        ..Default::default()
+    };
+    let body = br#"{"ip":"10.0.0.1","tags":{"az":"us-east-1a","instance_id":"i-1"},"hosts":[{"token":"x"}]}"#;
+    assert_eq!(
+        render(&config, body),
+        r#"{"hosts":[{"token":"[REDACTED]"}],"ip":"10.0.0.1","tags":{"az":"us-east-1a","instance_id":"[REDACTED]"}}"#
+    );
+}
+
+#[test]
+fn truncates_to_max_bytes() {
+    let config = PayloadLogConfig {
+        max_bytes: 4,
+        ..Default::default()
+    };
+    assert_eq!(render(&config, b"not json"), "not ...(8 bytes)");
+    assert_eq!(render(&config, "ééé".as_bytes()), "éé...(6 bytes)");
+}
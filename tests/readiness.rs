@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sds::readiness::{start_warm_up, Readiness, State};
+use sds::storage::StorageError;
+use sds::types::{Host, SchemaCheck, Storage};
+
+// Storage whose schema is incompatible until made compatible, and whose schema check fails
+// while throttled.
+#[derive(Clone)]
+struct SchemaStorage {
+    compatible: Arc<AtomicBool>,
+    throttled: Arc<AtomicBool>,
+}
+
+impl Storage for SchemaStorage {
+    type E = StorageError;
+
+    fn query_items(&self, _name: &str) -> Result<Vec<Host>, Self::E> {
+        Ok(Vec::new())
+    }
+
+    fn store_item(&self, _name: &str, _host: Host) -> Result<(), Self::E> {
+        Ok(())
+    }
+
+    fn delete_item(&self, _name: &str, _ip: String, _port: u64) -> Result<Option<Host>, Self::E> {
+        Ok(None)
+    }
+
+    fn check_schema(&self) -> Result<SchemaCheck, Self::E> {
+        if self.throttled.load(Ordering::SeqCst) {
+            Err(StorageError::new("Rate exceeded".to_owned()))
+        } else if self.compatible.load(Ordering::SeqCst) {
+            Ok(SchemaCheck::Compatible)
+        } else {
+            Ok(SchemaCheck::Incompatible(
+                "Table sds doesn't exist".to_owned(),
+            ))
+        }
+    }
+
+    fn ttl(&self) -> u64 {
+        30
+    }
+}
+
+#[test]
+fn incompatible_schema_keeps_readiness_down() {
+    let compatible = Arc::new(AtomicBool::new(false));
+    let storage = SchemaStorage {
+        compatible: compatible.clone(),
+        throttled: Arc::new(AtomicBool::new(false)),
+    };
+    let readiness = Readiness::new();
+    start_warm_up(readiness.clone(), storage, Duration::from_millis(50));
+
+    // The warm-up timeout doesn't mark an incompatible storage ready.
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        readiness.state(),
+        State::Incompatible("Table sds doesn't exist".to_owned())
+    );
+    assert!(!readiness.is_ready());
+    assert_eq!(
+        readiness.state().to_string(),
+        "incompatible storage: Table sds doesn't exist"
+    );
+
+    compatible.store(true, Ordering::SeqCst);
+    let started = Instant::now();
+    while !readiness.is_ready() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(readiness.is_ready());
+}
+
+#[test]
+fn schema_check_errors_are_retried_without_incompatibility() {
+    let throttled = Arc::new(AtomicBool::new(true));
+    let storage = SchemaStorage {
+        compatible: Arc::new(AtomicBool::new(true)),
+        throttled: throttled.clone(),
+    };
+    let readiness = Readiness::new();
+    start_warm_up(readiness.clone(), storage, Duration::from_millis(50));
+
+    // Neither incompatible nor ready by the warm-up timeout while the schema is unknown.
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(readiness.state(), State::WarmingUp);
+
+    throttled.store(false, Ordering::SeqCst);
+    let started = Instant::now();
+    while !readiness.is_ready() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(readiness.is_ready());
+}
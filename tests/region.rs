@@ -0,0 +1,33 @@
+use sds::region::demote_other_regions;
+use sds::v2xds::{Locality, LocalityLbEndpoints};
+
+fn lle(region: &str, zone: &str, priority: Option<u32>) -> LocalityLbEndpoints {
+    LocalityLbEndpoints {
+        locality: Locality {
+            region: region.to_owned(),
+            zone: zone.to_owned(),
+        },
+        lb_endpoints: Vec::new(),
+        priority,
+    }
+}
+
+#[test]
+fn other_regions_go_below_the_region() {
+    let mut endpoints = vec![
+        lle("us-east-1", "us-east-1a", Some(0)),
+        lle("us-east-1", "us-east-1b", Some(1)),
+        lle("us-west-2", "us-west-2a", Some(0)),
+        lle("us-west-2", "us-west-2b", Some(1)),
+    ];
+    demote_other_regions(&mut endpoints, "us-east-1");
+    let priorities: Vec<Option<u32>> = endpoints.iter().map(|e| e.priority).collect();
+    assert_eq!(priorities, vec![Some(0), Some(1), Some(2), Some(3)]);
+}
+
+#[test]
+fn priorities_are_kept_without_the_region() {
+    let mut endpoints = vec![lle("us-west-2", "us-west-2a", None)];
+    demote_other_regions(&mut endpoints, "us-east-1");
+    assert_eq!(endpoints[0].priority, None);
+}
@@ -0,0 +1,46 @@
+#![cfg(feature = "memory")]
+
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sds::metrics::Metrics;
+use sds::shadow::ShadowStorage;
+use sds::storage::MemoryStorage;
+use sds::types::Storage;
+
+use common::host;
+
+fn mismatches(metrics: &Metrics) -> Option<u64> {
+    metrics
+        .stats()
+        .services
+        .get("user")
+        .and_then(|s| s.shadow_mismatches)
+}
+
+#[test]
+fn writes_go_to_both_and_mismatches_are_counted() {
+    let primary = MemoryStorage::new(30);
+    let secondary = MemoryStorage::new(30);
+    let metrics = Metrics::new();
+    let storage = ShadowStorage::new(primary.clone(), secondary.clone(), metrics.clone());
+
+    storage.store_item("user", host(1)).unwrap();
+    assert_eq!(secondary.query_items("user").unwrap().len(), 1);
+    storage
+        .delete_item("user", "10.0.0.1".to_owned(), 1)
+        .unwrap();
+    assert!(secondary.query_items("user").unwrap().is_empty());
+
+    // Written before shadowing started, so only the primary has it.
+    primary.store_item("user", host(2)).unwrap();
+    assert_eq!(storage.query_items("user").unwrap().len(), 1);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while mismatches(&metrics).is_none() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(mismatches(&metrics), Some(1));
+}
@@ -0,0 +1,61 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sds::readiness::{Readiness, State};
+use sds::shutdown::{parse_steps, run_pre_shutdown, ShutdownConfig, ShutdownStep};
+
+#[test]
+fn steps_are_parsed_in_order() {
+    assert_eq!(
+        parse_steps("webhooks, not_ready,wait"),
+        Ok(vec![
+            ShutdownStep::Webhooks,
+            ShutdownStep::NotReady,
+            ShutdownStep::Wait
+        ])
+    );
+    assert_eq!(parse_steps(""), Ok(vec![]));
+    assert!(parse_steps("not_ready,sleep").is_err());
+}
+
+#[test]
+fn pre_shutdown_marks_draining_and_calls_webhooks() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        // Reads until the end of the JSON body.
+        while !request.ends_with(b"}") {
+            let n = stream.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .unwrap();
+        String::from_utf8_lossy(&request).into_owned()
+    });
+
+    let readiness = Readiness::new();
+    readiness.transition(State::WarmingUp, State::Ready);
+    let config = ShutdownConfig {
+        delay: Duration::from_millis(100),
+        webhooks: vec![url],
+        ..Default::default()
+    };
+    let started = Instant::now();
+    run_pre_shutdown(&config, &readiness, 8080);
+
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(readiness.state(), State::Draining);
+    assert!(!readiness.is_ready());
+    let request = server.join().unwrap();
+    assert!(request.contains("\"kind\":\"pre_shutdown\""));
+    assert!(request.contains("\"listen_port\":8080"));
+}
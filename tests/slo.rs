@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+use sds::slo::{SloObjective, SloTracker};
+
+fn objective() -> SloObjective {
+    SloObjective {
+        name: "sds-latency".to_owned(),
+        route: Some("sds".to_owned()),
+        latency_ms: Some(100),
+        target: 0.9,
+        short_window_sec: 60,
+        long_window_sec: 600,
+        burn_rate_threshold: 2.0,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn slow_and_failed_requests_burn_budget() {
+    let tracker = SloTracker::new();
+    let objectives = vec![objective()];
+    let now = Instant::now();
+    let fast = Duration::from_millis(10);
+    for _ in 0..6 {
+        tracker.record(&objectives, "sds", Some("user"), 200, fast, now);
+    }
+    tracker.record(
+        &objectives,
+        "sds",
+        Some("user"),
+        200,
+        Duration::from_secs(1),
+        now,
+    );
+    tracker.record(&objectives, "sds", Some("user"), 503, fast, now);
+    // Other routes don't count.
+    tracker.record(&objectives, "eds", None, 503, fast, now);
+
+    let report = tracker.report(&objectives, now);
+    assert_eq!(report.len(), 1);
+    assert_eq!(report[0].short_window.requests, 8);
+    assert_eq!(report[0].short_window.bad_requests, 2);
+    // 25% bad over a 10% budget.
+    assert!((report[0].short_window.burn_rate - 2.5).abs() < 1e-9);
+    assert!(report[0].violated);
+}
+
+#[test]
+fn short_window_recovers_first() {
+    let tracker = SloTracker::new();
+    let objectives = vec![objective()];
+    let now = Instant::now();
+    tracker.record(&objectives, "sds", None, 500, Duration::from_millis(1), now);
+
+    let later = now + Duration::from_secs(120);
+    tracker.record(
+        &objectives,
+        "sds",
+        None,
+        200,
+        Duration::from_millis(1),
+        later,
+    );
+    let report = tracker.report(&objectives, later);
+    assert_eq!(report[0].short_window.bad_requests, 0);
+    assert_eq!(report[0].long_window.bad_requests, 1);
+    assert!(!report[0].violated);
+
+    let metrics = tracker.render_prometheus(&objectives, later);
+    assert!(metrics.contains("sds_slo_burn_rate{slo=\"sds-latency\",window=\"long\"} 5"));
+    assert!(metrics.contains("sds_slo_violated{slo=\"sds-latency\"} 0"));
+}
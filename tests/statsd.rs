@@ -0,0 +1,70 @@
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use sds::metrics::Metrics;
+use sds::statsd::{format_line, parse_tags, StatsdClient, StatsdConfig, StatsdFlavor};
+
+fn config(flavor: StatsdFlavor) -> StatsdConfig {
+    StatsdConfig {
+        address: "127.0.0.1:8125".to_owned(),
+        prefix: "sds".to_owned(),
+        tags: vec![("env".to_owned(), "prod".to_owned())],
+        flavor,
+    }
+}
+
+#[test]
+fn dogstatsd_sends_labels_as_tags() {
+    let line = format_line(
+        &config(StatsdFlavor::DogStatsd),
+        "truncated_responses",
+        "1",
+        "c",
+        &[("service", "user")],
+    );
+    assert_eq!(line, "sds.truncated_responses:1|c|#env:prod,service:user");
+}
+
+#[test]
+fn statsd_appends_labels_to_name() {
+    let line = format_line(
+        &config(StatsdFlavor::Statsd),
+        "request.duration",
+        "12",
+        "ms",
+        &[("route", "eds"), ("status", "200")],
+    );
+    assert_eq!(line, "sds.request.duration.eds.200:12|ms");
+}
+
+#[test]
+fn parses_tags() {
+    assert_eq!(
+        parse_tags("env:prod, team:discovery").unwrap(),
+        vec![
+            ("env".to_owned(), "prod".to_owned()),
+            ("team".to_owned(), "discovery".to_owned())
+        ]
+    );
+    assert!(parse_tags("").unwrap().is_empty());
+    assert!(parse_tags("env").is_err());
+}
+
+#[test]
+fn metrics_are_sent_over_udp() {
+    let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    agent
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut c = config(StatsdFlavor::DogStatsd);
+    c.address = agent.local_addr().unwrap().to_string();
+    let metrics = Metrics::new().with_statsd(StatsdClient::new(c).unwrap());
+
+    metrics.inc_truncated_responses("user");
+    let mut buf = [0; 512];
+    let n = agent.recv(&mut buf).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&buf[..n]),
+        "sds.truncated_responses:1|c|#env:prod,service:user"
+    );
+}
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+
+use sds::metrics::Metrics;
+use sds::write_lock::ServiceLocks;
+
+#[test]
+fn writes_of_a_service_are_serialized() {
+    let metrics = Metrics::new();
+    let locks = ServiceLocks::new(metrics.clone());
+    let guard = locks.lock("user").wait().unwrap();
+
+    // Other services aren't blocked.
+    drop(locks.lock("order").wait().unwrap());
+
+    let acquired = Arc::new(AtomicBool::new(false));
+    let waiter = {
+        let locks = locks.clone();
+        let acquired = acquired.clone();
+        thread::spawn(move || {
+            let _guard = locks.lock("user").wait().unwrap();
+            acquired.store(true, Ordering::SeqCst);
+        })
+    };
+    thread::sleep(Duration::from_millis(100));
+    assert!(!acquired.load(Ordering::SeqCst));
+
+    drop(guard);
+    waiter.join().unwrap();
+    assert!(acquired.load(Ordering::SeqCst));
+
+    let stats = metrics.stats();
+    let wait = stats.services["user"].write_lock_wait_ms.as_ref().unwrap();
+    assert_eq!(wait.count, 2);
+    assert!(wait.sum >= 100);
+    assert_eq!(
+        stats.services["order"]
+            .write_lock_wait_ms
+            .as_ref()
+            .unwrap()
+            .count,
+        1
+    );
+}
+
+#[test]
+fn dropped_waiters_pass_the_lock_on() {
+    let locks = ServiceLocks::new(Metrics::new());
+    let guard = locks.lock("user").wait().unwrap();
+    // e.g. the client of a waiting request disconnected.
+    let abandoned = locks.lock("user");
+    let next = locks.lock("user");
+    drop(guard);
+    drop(abandoned);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(next.wait().is_ok());
+    });
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(true));
+}
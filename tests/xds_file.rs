@@ -0,0 +1,77 @@
+#![cfg(feature = "memory")]
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use sds::clock::system_clock;
+use sds::config::{FileConfig, ReloadableConfig};
+use sds::storage::MemoryStorage;
+use sds::types::Storage;
+use sds::xds_file::XdsFileWriter;
+
+use common::host;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sds-xds-file-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn read(dir: &PathBuf, name: &str) -> Value {
+    let body = fs::read_to_string(dir.join(format!("{}.json", name))).unwrap();
+    serde_json::from_str(&body).unwrap()
+}
+
+#[test]
+fn writes_only_changed_services() {
+    let dir = temp_dir("changed");
+    let storage = MemoryStorage::new(30);
+    let file = ReloadableConfig::new(None, FileConfig::default());
+    let clock = system_clock();
+    let mut writer = XdsFileWriter::new(dir.clone());
+
+    storage.store_item("user", host(8080)).unwrap();
+    assert_eq!(writer.render(&storage, &file, &clock).unwrap(), 1);
+    let v = read(&dir, "user");
+    assert_eq!(v["resources"][0]["cluster_name"], "user");
+    let version = v["version_info"].to_owned();
+
+    assert_eq!(writer.render(&storage, &file, &clock).unwrap(), 0);
+
+    storage.store_item("user", host(8081)).unwrap();
+    assert_eq!(writer.render(&storage, &file, &clock).unwrap(), 1);
+    let v = read(&dir, "user");
+    assert_ne!(v["version_info"], version);
+    let endpoints = v["resources"][0]["endpoints"][0]["lb_endpoints"]
+        .as_array()
+        .unwrap();
+    assert_eq!(endpoints.len(), 2);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn keeps_services_without_hosts() {
+    let dir = temp_dir("empty");
+    let storage = MemoryStorage::new(30);
+    let file = ReloadableConfig::new(None, FileConfig::default());
+    let clock = system_clock();
+    let mut writer = XdsFileWriter::new(dir.clone());
+
+    storage.store_item("user", host(8080)).unwrap();
+    writer.render(&storage, &file, &clock).unwrap();
+    storage
+        .delete_item("user", "10.0.0.1".to_owned(), 8080)
+        .unwrap();
+    assert_eq!(writer.render(&storage, &file, &clock).unwrap(), 1);
+    let v = read(&dir, "user");
+    assert!(v["resources"][0]["endpoints"]
+        .as_array()
+        .unwrap()
+        .is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}